@@ -7,12 +7,214 @@ CREATE TABLE "user" (
   username VARCHAR(128) NOT NULL, 
   pwd varchar(256),
   pwd_salt uuid NOT NULL DEFAULT gen_random_uuid(),
-  token_salt uuid NOT NULL DEFAULT gen_random_uuid()
+  token_salt uuid NOT NULL DEFAULT gen_random_uuid(),
+  -- can read the audit events
+  admin BOOLEAN NOT NULL DEFAULT false
 );
 
+-- Workspace
+-- tenant of the tasks (and of their labels, comments, attachments),
+-- the base functions only see the rows of the workspace of the ctx
+CREATE TABLE "workspace" (
+  id BIGINT GENERATED BY DEFAULT AS IDENTITY (START WITH 1000) PRIMARY KEY,
+  name VARCHAR(128) NOT NULL
+);
+
+CREATE TABLE "workspace_member" (
+  workspace_id BIGINT NOT NULL REFERENCES "workspace" (id) ON DELETE CASCADE,
+  user_id BIGINT NOT NULL REFERENCES "user" (id) ON DELETE CASCADE,
+  PRIMARY KEY (workspace_id, user_id)
+);
+CREATE INDEX workspace_member_user_idx ON "workspace_member" (user_id);
+
 -- Task 
+-- declaration order is the order of the priorities (for the order_bys and filters)
+CREATE TYPE task_priority AS ENUM ('low', 'medium', 'high', 'urgent');
+
 CREATE TABLE "task" (
   id BIGINT GENERATED BY DEFAULT AS IDENTITY (START WITH 1000) PRIMARY KEY,
+  workspace_id BIGINT NOT NULL REFERENCES "workspace" (id) ON DELETE CASCADE,
   title VARCHAR(256) NOT NULL,
-  done bool NOT NULL DEFAULT false
+  -- subtask of, see TaskBmc::set_parent for the cycle check
+  parent_id BIGINT REFERENCES "task" (id) ON DELETE SET NULL,
+  -- markdown
+  description TEXT,
+  priority task_priority NOT NULL DEFAULT 'medium',
+  due_at timestamp with time zone,
+  done bool NOT NULL DEFAULT false,
+  -- set by the task_done_at trigger
+  done_at timestamp with time zone,
+  -- key of the task in an external tracker, for the upserts of the sync
+  -- (unique in the workspace)
+  external_id VARCHAR(256),
+  -- RRULE subset, e.g., FREQ=WEEKLY (see task_recurrence.rs)
+  recurrence VARCHAR(256),
+  -- occurrence this task was generated from (unique, so at most one next occurrence)
+  recurrence_prev_id BIGINT UNIQUE REFERENCES "task" (id) ON DELETE SET NULL,
+  -- set once the next occurrence was generated (even if deleted afterwards)
+  recurred_at timestamp with time zone,
+  -- optimistic concurrency, incremented on every update
+  version BIGINT NOT NULL DEFAULT 0,
+  -- full text search, see TaskBmc::search
+  search tsvector GENERATED ALWAYS AS (
+    setweight(to_tsvector('english', title), 'A') ||
+    setweight(to_tsvector('english', coalesce(description, '')), 'B')
+  ) STORED,
+  UNIQUE (workspace_id, external_id)
+);
+CREATE INDEX task_workspace_idx ON "task" (workspace_id);
+CREATE INDEX task_search_idx ON "task" USING GIN (search);
+CREATE INDEX task_parent_idx ON "task" (parent_id);
+CREATE INDEX task_recurrence_pending_idx ON "task" (id)
+  WHERE recurrence IS NOT NULL AND recurred_at IS NULL;
+
+-- Task Dependency
+-- task_id is blocked by blocker_id, see TaskDependencyBmc::link for the cycle check
+CREATE TABLE "task_dependency" (
+  task_id BIGINT NOT NULL REFERENCES "task" (id) ON DELETE CASCADE,
+  blocker_id BIGINT NOT NULL REFERENCES "task" (id) ON DELETE CASCADE,
+  PRIMARY KEY (task_id, blocker_id),
+  CHECK (task_id <> blocker_id)
+);
+CREATE INDEX task_dependency_blocker_idx ON "task_dependency" (blocker_id);
+
+-- done_at is set when done flips to true, and cleared when it flips back
+CREATE FUNCTION task_done_at() RETURNS trigger AS $$
+BEGIN
+  IF NEW.done AND (TG_OP = 'INSERT' OR NOT OLD.done) THEN
+    NEW.done_at := now();
+  ELSIF NOT NEW.done THEN
+    NEW.done_at := NULL;
+  END IF;
+  RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER task_done_at BEFORE INSERT OR UPDATE OF done ON "task"
+  FOR EACH ROW EXECUTE FUNCTION task_done_at();
+
+-- Label
+CREATE TABLE "label" (
+  id BIGINT GENERATED BY DEFAULT AS IDENTITY (START WITH 1000) PRIMARY KEY,
+  workspace_id BIGINT NOT NULL REFERENCES "workspace" (id) ON DELETE CASCADE,
+  name VARCHAR(64) NOT NULL,
+  color VARCHAR(32),
+  UNIQUE (workspace_id, name)
+);
+
+CREATE TABLE "task_label" (
+  task_id BIGINT NOT NULL REFERENCES "task" (id) ON DELETE CASCADE,
+  label_id BIGINT NOT NULL REFERENCES "label" (id) ON DELETE CASCADE,
+  PRIMARY KEY (task_id, label_id)
+);
+CREATE INDEX task_label_label_idx ON "task_label" (label_id);
+
+-- Comment
+CREATE TABLE "comment" (
+  id BIGINT GENERATED BY DEFAULT AS IDENTITY (START WITH 1000) PRIMARY KEY,
+  -- the one of the task
+  workspace_id BIGINT NOT NULL REFERENCES "workspace" (id) ON DELETE CASCADE,
+  task_id BIGINT NOT NULL REFERENCES "task" (id) ON DELETE CASCADE,
+  -- user of the ctx which added it, the only one who can edit it
+  author_id BIGINT NOT NULL,
+  -- markdown
+  body TEXT NOT NULL,
+  ctime timestamp with time zone NOT NULL DEFAULT now(),
+  mtime timestamp with time zone NOT NULL DEFAULT now()
 );
+CREATE INDEX comment_task_idx ON "comment" (task_id);
+
+-- Attachment
+CREATE TABLE "attachment" (
+  id BIGINT GENERATED BY DEFAULT AS IDENTITY (START WITH 1000) PRIMARY KEY,
+  -- the one of the task
+  workspace_id BIGINT NOT NULL REFERENCES "workspace" (id) ON DELETE CASCADE,
+  task_id BIGINT NOT NULL REFERENCES "task" (id) ON DELETE CASCADE,
+  -- user who uploaded it, counted in the quota of this user
+  owner_id BIGINT NOT NULL,
+  name VARCHAR(256) NOT NULL,
+  content_type VARCHAR(256) NOT NULL,
+  size BIGINT NOT NULL,
+  -- hex
+  sha256 CHAR(64) NOT NULL,
+  -- key of the content in the blob store
+  blob_key VARCHAR(256) NOT NULL UNIQUE,
+  ctime timestamp with time zone NOT NULL DEFAULT now()
+);
+CREATE INDEX attachment_task_idx ON "attachment" (task_id);
+CREATE INDEX attachment_owner_idx ON "attachment" (owner_id);
+
+-- blobs of the deleted attachments (also by the task cascade),
+-- deleted from the blob store by the scheduler
+CREATE TABLE "blob_orphan" (
+  blob_key VARCHAR(256) PRIMARY KEY,
+  ctime timestamp with time zone NOT NULL DEFAULT now(),
+  -- failed deletes, retried with a backoff from next_attempt_at
+  attempts INT NOT NULL DEFAULT 0,
+  next_attempt_at timestamp with time zone NOT NULL DEFAULT now()
+);
+CREATE INDEX blob_orphan_next_attempt_idx ON "blob_orphan" (next_attempt_at);
+
+CREATE FUNCTION attachment_orphan_blob() RETURNS trigger AS $$
+BEGIN
+  INSERT INTO "blob_orphan" (blob_key) VALUES (OLD.blob_key) ON CONFLICT DO NOTHING;
+  RETURN OLD;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER attachment_orphan_blob AFTER DELETE ON "attachment"
+  FOR EACH ROW EXECUTE FUNCTION attachment_orphan_blob();
+
+-- Task History
+-- one row per create/update/delete done through the model base
+CREATE TABLE "task_history" (
+  id BIGINT GENERATED BY DEFAULT AS IDENTITY (START WITH 1000) PRIMARY KEY,
+  entity VARCHAR(64) NOT NULL,
+  entity_id BIGINT NOT NULL,
+  -- the one of the entity, if it has one
+  workspace_id BIGINT,
+  op VARCHAR(16) NOT NULL,
+  user_id BIGINT NOT NULL,
+  ctime timestamp with time zone NOT NULL DEFAULT now(),
+  diff JSONB NOT NULL
+);
+CREATE INDEX task_history_entity_idx ON "task_history" (entity, entity_id);
+
+-- Change Event
+-- bounded log of the change events, for clients to resume their stream
+CREATE TABLE "change_event" (
+  id BIGINT GENERATED BY DEFAULT AS IDENTITY (START WITH 1000) PRIMARY KEY,
+  entity VARCHAR(64) NOT NULL,
+  op VARCHAR(16) NOT NULL,
+  entity_id BIGINT NOT NULL,
+  -- the one of the entity, if it has one
+  workspace_id BIGINT,
+  data JSONB NOT NULL,
+  ctime timestamp with time zone NOT NULL DEFAULT now()
+);
+
+-- Audit Event
+-- security events (logins, token failures, permission denials, ...), append-only:
+-- the rows are never updated, only deleted by the retention pruning
+CREATE TABLE "audit_event" (
+  id BIGINT GENERATED BY DEFAULT AS IDENTITY (START WITH 1000) PRIMARY KEY,
+  kind VARCHAR(32) NOT NULL,
+  -- no foreign key, the events are kept after the user is deleted
+  user_id BIGINT,
+  client_ip VARCHAR(64),
+  user_agent VARCHAR(512),
+  request_id VARCHAR(128),
+  detail JSONB NOT NULL DEFAULT '{}',
+  ctime timestamp with time zone NOT NULL DEFAULT now()
+);
+CREATE INDEX audit_event_ctime_idx ON "audit_event" (ctime);
+CREATE INDEX audit_event_user_idx ON "audit_event" (user_id, ctime);
+
+CREATE FUNCTION audit_event_no_update() RETURNS trigger AS $$
+BEGIN
+  RAISE EXCEPTION 'audit_event is append-only';
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER audit_event_no_update BEFORE UPDATE ON "audit_event"
+  FOR EACH ROW EXECUTE FUNCTION audit_event_no_update();
@@ -1 +1,7 @@
 INSERT INTO "user" (username) VALUES ('demo1');
+-- can read the audit events (not a member of any workspace)
+INSERT INTO "user" (username, admin) VALUES ('admin', true);
+
+-- demo1 is a member of the demo workspace (id 1000, see _dev_utils::DEMO_WORKSPACE_ID)
+INSERT INTO "workspace" (name) VALUES ('demo');
+INSERT INTO "workspace_member" (workspace_id, user_id) VALUES (1000, 1000);
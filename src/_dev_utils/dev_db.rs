@@ -56,12 +56,14 @@ pub async fn init_dev_db() -> Result<(), Box<dyn std::error::Error>> {
     let mm = ModelManager::new().await?;
     let ctx = Ctx::root_ctx();
 
-    let demo1_user: User = UserBmc::first_by_username(&ctx, &mm, "demo1")
-        .await?
-        .unwrap();
+    for username in ["demo1", "admin"] {
+        let user: User = UserBmc::first_by_username(&ctx, &mm, username)
+            .await?
+            .unwrap();
 
-    UserBmc::update_pwd(&ctx, &mm, demo1_user.id, DEMO_PWD).await?;
-    info!("{:12} - init_dev_db - set demo1 pwd", "FOR-DEV-ONLY");
+        UserBmc::update_pwd(&ctx, &mm, user.id, DEMO_PWD).await?;
+        info!("{:12} - init_dev_db - set {username} pwd", "FOR-DEV-ONLY");
+    }
     Ok(())
 }
 
@@ -72,14 +74,41 @@ async fn pexec(db: &Db, file: &str) -> Result<(), sqlx::Error> {
     // ? works because sqlx::Error has a  from_std_io error
     let content = fs::read_to_string(file)?;
 
-    //FIXME: make split more sql proof
-    let sqls: Vec<&str> = content.split(';').collect();
-    for sql in sqls {
-        sqlx::query(sql).execute(db).await?;
+    for sql in split_sql(&content) {
+        sqlx::query(&sql).execute(db).await?;
     }
     Ok(())
 }
 
+/// Splits the sql file content on `;`, except within `$$` quoted bodies
+/// (e.g., functions).
+fn split_sql(content: &str) -> Vec<String> {
+    let mut sqls = Vec::new();
+    let mut sql = String::new();
+
+    // the odd parts are the ones within `$$`
+    for (i, part) in content.split("$$").enumerate() {
+        if i > 0 {
+            sql.push_str("$$");
+        }
+        if i % 2 == 1 {
+            sql.push_str(part);
+            continue;
+        }
+        let mut statements = part.split(';');
+        if let Some(first) = statements.next() {
+            sql.push_str(first);
+        }
+        for statement in statements {
+            sqls.push(std::mem::take(&mut sql));
+            sql.push_str(statement);
+        }
+    }
+    sqls.push(sql);
+
+    sqls
+}
+
 async fn new_db_pool(db_con_url: &str) -> Result<Db, sqlx::Error> {
     PgPoolOptions::new()
         .max_connections(1)
@@ -15,7 +15,6 @@ use crate::{
 
 /// Initialize env for local dev
 /// For early dev, will be called from main()
-
 pub async fn init_dev() {
     static INIT: OnceCell<()> = OnceCell::const_new();
 
@@ -27,8 +26,15 @@ pub async fn init_dev() {
     .await;
 }
 
-/// Testing environment
+/// Workspace of demo1, seeded by the dev db.
+pub const DEMO_WORKSPACE_ID: i64 = 1000;
 
+/// The root ctx in the demo workspace, for the tests of the Bmcs with workspace.
+pub fn demo_ctx() -> Ctx {
+    Ctx::root_ctx_in(DEMO_WORKSPACE_ID)
+}
+
+/// Testing environment
 pub async fn init_test() -> ModelManager {
     static INIT: OnceCell<ModelManager> = OnceCell::const_new();
 
@@ -45,15 +51,15 @@ pub async fn seed_tasks(ctx: &Ctx, mm: &ModelManager, titles: &[&str]) -> model:
     let mut tasks = Vec::new();
 
     for title in titles {
-        let id = TaskBmc::create(
+        let task = TaskBmc::create_returning(
             ctx,
             mm,
             TaskForCreate {
                 title: title.to_string(),
+                ..Default::default()
             },
         )
         .await?;
-        let task = TaskBmc::get(ctx, mm, id).await?;
         tasks.push(task);
     }
     Ok(tasks)
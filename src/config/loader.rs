@@ -0,0 +1,566 @@
+//! Resolution of the config values, by key (e.g., `DB_URL`), in order:
+//!
+//! 1. The `SERVICE_<KEY>` env variable, or the content of the file at `SERVICE_<KEY>_FILE`
+//!    (e.g., a secret mounted by the container).
+//! 2. `<key>` (or `<key>_file`) in the `[profile.<profile>]` table of the config file.
+//! 3. `<key>` (or `<key>_file`) at the top of the config file.
+//! 4. The default of the key, when it has one.
+//!
+//! In the config file, the keys are in lowercase and the tables are prefixes
+//! (e.g., `cert_path` in `[tls]` is `TLS_CERT_PATH`).
+//! The problems (missing or wrong values, unknown keys of the file, ...) are collected
+//! instead of failing at the first one, so that they can all be reported at once.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::env;
+use std::str::FromStr;
+
+pub const ENV_PREFIX: &str = "SERVICE_";
+/// Selects the `[profile.<name>]` table of the config file (`dev` by default).
+pub const PROFILE_ENV: &str = "SERVICE_PROFILE";
+pub const CONFIG_FILE_ENV: &str = "SERVICE_CONFIG_FILE";
+pub const PROFILES: &[&str] = &["dev", "test", "prod"];
+const DEFAULT_PROFILE: &str = "dev";
+const FILE_SUFFIX: &str = "_FILE";
+
+/// Redacted in the dump and in the problems.
+const SECRET_KEYS: &[&str] = &[
+    "PWD_KEY",
+    "TOKEN_KEY",
+    "DB_URL",
+    "S3_ACCESS_KEY_ID",
+    "S3_SECRET_ACCESS_KEY",
+];
+const REDACTED: &str = "<redacted>";
+
+/// A resolved (or missing) value, for the dump.
+pub struct Entry {
+    pub key: &'static str,
+    pub value: Option<String>,
+    /// e.g., `env SERVICE_PORT`, `config/app.toml [profile.dev]`, `default`
+    pub origin: String,
+}
+
+impl Entry {
+    pub fn redacted_value(&self) -> Option<&str> {
+        match &self.value {
+            Some(_) if SECRET_KEYS.contains(&self.key) => Some(REDACTED),
+            value => value.as_deref(),
+        }
+    }
+}
+
+enum Lookup {
+    Found {
+        value: String,
+        origin: String,
+    },
+    /// Set, but could not be read (already reported).
+    Failed,
+    Missing,
+}
+
+pub struct Loader {
+    /// The env variables (injected by the tests).
+    env: HashMap<String, String>,
+    pub profile: String,
+    pub file_path: Option<String>,
+    /// Values of the config file by profile (`None` for the top ones), then by key.
+    file_values: BTreeMap<Option<String>, BTreeMap<String, String>>,
+    /// Keys looked up, to report the unknown keys of the config file.
+    known_keys: BTreeSet<String>,
+    pub entries: Vec<Entry>,
+    pub problems: Vec<String>,
+}
+
+impl Loader {
+    pub fn new() -> Self {
+        Self::with_env(env::vars().collect())
+    }
+
+    /// Reads the config file, if any (its problems are reported as the others).
+    pub fn with_env(env: HashMap<String, String>) -> Self {
+        let env_opt = |name: &str| env.get(name).filter(|val| !val.is_empty()).cloned();
+        let mut loader = Loader {
+            profile: env_opt(PROFILE_ENV).unwrap_or_else(|| DEFAULT_PROFILE.to_string()),
+            file_path: env_opt(CONFIG_FILE_ENV),
+            env,
+            file_values: BTreeMap::new(),
+            known_keys: BTreeSet::new(),
+            entries: Vec::new(),
+            problems: Vec::new(),
+        };
+
+        if !PROFILES.contains(&loader.profile.as_str()) {
+            loader.problems.push(format!(
+                "{PROFILE_ENV} - unknown profile '{}' (expected one of {PROFILES:?})",
+                loader.profile
+            ));
+        }
+        if let Some(path) = loader.file_path.clone() {
+            loader.read_file(&path);
+        }
+
+        loader
+    }
+
+    fn read_file(&mut self, path: &str) {
+        let table = std::fs::read_to_string(path)
+            .map_err(|ex| ex.to_string())
+            .and_then(|content| {
+                toml::from_str::<toml::Table>(&content).map_err(|ex| ex.to_string())
+            });
+        let table = match table {
+            Ok(table) => table,
+            Err(ex) => {
+                self.problems.push(format!("{path} - cannot load: {ex}"));
+                return;
+            }
+        };
+
+        let mut values = Vec::new();
+        flatten_table(Vec::new(), table, &mut values);
+
+        for (mut parts, value) in values {
+            let profile = match parts.as_slice() {
+                [first, profile, _, ..] if first == "profile" => {
+                    let profile = profile.clone();
+                    parts.drain(..2);
+                    if !PROFILES.contains(&profile.as_str()) {
+                        self.problems.push(format!(
+                            "{path} - unknown profile '{profile}' (expected one of {PROFILES:?})"
+                        ));
+                        continue;
+                    }
+                    Some(profile)
+                }
+                _ => None,
+            };
+            let Some(value) = config_string(&value) else {
+                self.problems.push(format!(
+                    "{path} - '{}' must be a string, number, boolean or array of them",
+                    parts.join(".")
+                ));
+                continue;
+            };
+            let key = parts.join("_").to_uppercase();
+            self.file_values
+                .entry(profile)
+                .or_default()
+                .insert(key, value);
+        }
+    }
+
+    // region:    --- Getters
+
+    /// Reports it when missing or in the wrong format.
+    pub fn required<T: FromStr + Default>(&mut self, key: &'static str) -> T {
+        self.required_if(key, true, "")
+    }
+
+    /// Required when `needed` (e.g., `with SERVICE_S3_BUCKET`), optional otherwise.
+    pub fn required_if<T: FromStr + Default>(
+        &mut self,
+        key: &'static str,
+        needed: bool,
+        reason: &str,
+    ) -> T {
+        match self.lookup(key) {
+            Lookup::Found { value, origin } => self.parse(key, value, origin).unwrap_or_default(),
+            Lookup::Missing => {
+                if needed {
+                    let reason = if reason.is_empty() {
+                        String::new()
+                    } else {
+                        format!(" ({reason})")
+                    };
+                    self.problem(key, format!("missing{reason}"));
+                }
+                self.push_entry(key, None, String::new());
+                T::default()
+            }
+            Lookup::Failed => T::default(),
+        }
+    }
+
+    pub fn or_default<T: FromStr + Default>(&mut self, key: &'static str, default: &str) -> T {
+        match self.lookup(key) {
+            Lookup::Found { value, origin } => self.parse(key, value, origin).unwrap_or_default(),
+            Lookup::Missing => self
+                .parse(key, default.to_string(), "default".to_string())
+                .unwrap_or_default(),
+            Lookup::Failed => T::default(),
+        }
+    }
+
+    /// `None` when not set (or in the wrong format, which is reported).
+    pub fn optional<T: FromStr>(&mut self, key: &'static str) -> Option<T> {
+        match self.lookup(key) {
+            Lookup::Found { value, origin } => self.parse(key, value, origin),
+            Lookup::Missing => {
+                self.push_entry(key, None, String::new());
+                None
+            }
+            Lookup::Failed => None,
+        }
+    }
+
+    /// Reports a problem of the value of `key` (e.g., out of range).
+    pub fn problem(&mut self, key: &str, msg: impl AsRef<str>) {
+        self.problems
+            .push(format!("{} - {}", key_label(key), msg.as_ref()));
+    }
+
+    // endregion: --- Getters
+
+    /// Reports the unknown keys of the config file (of all the profiles).
+    pub fn finish(&mut self) {
+        let path = self.file_path.clone().unwrap_or_default();
+        for (profile, values) in &self.file_values {
+            for key in values.keys() {
+                if !self.known_keys.contains(key) {
+                    let table = profile
+                        .as_ref()
+                        .map(|profile| format!(" in [profile.{profile}]"))
+                        .unwrap_or_default();
+                    self.problems.push(format!(
+                        "{path} - unknown key '{}'{table}",
+                        key.to_lowercase()
+                    ));
+                }
+            }
+        }
+    }
+
+    /// The effective values (the secrets redacted) with their origin, then the problems.
+    pub fn dump(&self) -> String {
+        let mut dump = format!(
+            "profile: {}\nconfig file: {}\n\n",
+            self.profile,
+            self.file_path.as_deref().unwrap_or("(none)")
+        );
+        let width = self.entries.iter().map(|e| e.key.len()).max().unwrap_or(0);
+        for entry in &self.entries {
+            let line = match entry.redacted_value() {
+                Some(value) => format!("{:<width$} = {value:?}  # {}", entry.key, entry.origin),
+                None => format!("{:<width$}   (not set)", entry.key),
+            };
+            dump.push_str(&line);
+            dump.push('\n');
+        }
+        if !self.problems.is_empty() {
+            dump.push_str(&format!("\n{} problem(s):\n", self.problems.len()));
+            for problem in &self.problems {
+                dump.push_str(&format!("- {problem}\n"));
+            }
+        }
+
+        dump
+    }
+
+    fn parse<T: FromStr>(&mut self, key: &'static str, value: String, origin: String) -> Option<T> {
+        let parsed = value.parse::<T>().ok();
+        if parsed.is_none() {
+            let shown = if SECRET_KEYS.contains(&key) {
+                REDACTED
+            } else {
+                &value
+            };
+            self.problem(key, format!("wrong format '{shown}' (from {origin})"));
+        }
+        self.push_entry(key, Some(value), origin);
+
+        parsed
+    }
+
+    fn push_entry(&mut self, key: &'static str, value: Option<String>, origin: String) {
+        self.entries.push(Entry { key, value, origin });
+    }
+
+    /// `None` when not set (or empty).
+    fn env_opt(&self, name: &str) -> Option<String> {
+        self.env.get(name).filter(|val| !val.is_empty()).cloned()
+    }
+
+    fn lookup(&mut self, key: &'static str) -> Lookup {
+        let file_key = format!("{key}{FILE_SUFFIX}");
+        self.known_keys.insert(key.to_string());
+        self.known_keys.insert(file_key.clone());
+
+        // -- Env
+        let env_name = format!("{ENV_PREFIX}{key}");
+        let env_file_name = format!("{ENV_PREFIX}{file_key}");
+        let lookup = self.lookup_in(
+            self.env_opt(&env_name),
+            self.env_opt(&env_file_name),
+            &format!("env {env_name}"),
+            &format!("env {env_file_name}"),
+        );
+        if !matches!(lookup, Lookup::Missing) {
+            return lookup;
+        }
+
+        // -- Config file (profile, then top)
+        let path = self.file_path.clone().unwrap_or_default();
+        for profile in [Some(self.profile.clone()), None] {
+            let (value, file_value) = match self.file_values.get(&profile) {
+                Some(values) => (values.get(key).cloned(), values.get(&file_key).cloned()),
+                None => continue,
+            };
+            let table = profile
+                .map(|profile| format!(" [profile.{profile}]"))
+                .unwrap_or_default();
+            let lookup = self.lookup_in(
+                value,
+                file_value,
+                &format!("{path}{table}"),
+                &format!("{path}{table} {}", file_key.to_lowercase()),
+            );
+            if !matches!(lookup, Lookup::Missing) {
+                return lookup;
+            }
+        }
+
+        Lookup::Missing
+    }
+
+    /// The value, or the content of the file at `file_path` (without the trailing new lines).
+    fn lookup_in(
+        &mut self,
+        value: Option<String>,
+        file_path: Option<String>,
+        origin: &str,
+        file_origin: &str,
+    ) -> Lookup {
+        match (value, file_path) {
+            (Some(_), Some(_)) => {
+                self.problems.push(format!(
+                    "{origin} - set with its file too ({file_origin}), only one is allowed"
+                ));
+                Lookup::Failed
+            }
+            (Some(value), None) => Lookup::Found {
+                value,
+                origin: origin.to_string(),
+            },
+            (None, Some(file_path)) => match std::fs::read_to_string(&file_path) {
+                Ok(content) => Lookup::Found {
+                    value: content.trim_end_matches(['\r', '\n']).to_string(),
+                    origin: format!("{file_origin} ({file_path})"),
+                },
+                Err(ex) => {
+                    self.problems
+                        .push(format!("{file_origin} - cannot read '{file_path}': {ex}"));
+                    Lookup::Failed
+                }
+            },
+            (None, None) => Lookup::Missing,
+        }
+    }
+}
+
+/// e.g., `SERVICE_TLS_CERT_PATH (tls_cert_path)`
+/// The values of `table` (and of its sub tables), with their key path (e.g., `[profile, dev, db_url]`).
+fn flatten_table(
+    path: Vec<String>,
+    table: toml::Table,
+    values: &mut Vec<(Vec<String>, toml::Value)>,
+) {
+    for (name, value) in table {
+        let mut key_path = path.clone();
+        key_path.push(name);
+        match value {
+            toml::Value::Table(table) => flatten_table(key_path, table, values),
+            value => values.push((key_path, value)),
+        }
+    }
+}
+
+/// The value as in an env variable (the arrays are comma separated),
+/// or `None` for the tables and nested arrays.
+fn config_string(value: &toml::Value) -> Option<String> {
+    match value {
+        toml::Value::String(val) => Some(val.clone()),
+        toml::Value::Integer(val) => Some(val.to_string()),
+        toml::Value::Float(val) => Some(val.to_string()),
+        toml::Value::Boolean(val) => Some(val.to_string()),
+        toml::Value::Datetime(val) => Some(val.to_string()),
+        toml::Value::Array(vals) => vals
+            .iter()
+            .map(|val| match val {
+                toml::Value::Array(_) | toml::Value::Table(_) => None,
+                val => config_string(val),
+            })
+            .collect::<Option<Vec<_>>>()
+            .map(|vals| vals.join(",")),
+        toml::Value::Table(_) => None,
+    }
+}
+
+fn key_label(key: &str) -> String {
+    format!("{ENV_PREFIX}{key} ({})", key.to_lowercase())
+}
+
+// region:    --- Tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use std::path::PathBuf;
+
+    /// Writes the files in a new temp dir.
+    fn fx_dir(files: &[(&str, &str)]) -> Result<PathBuf> {
+        let dir = env::temp_dir().join(format!("config-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir)?;
+        for (name, content) in files {
+            std::fs::write(dir.join(name), content)?;
+        }
+        Ok(dir)
+    }
+
+    fn fx_env(vars: &[(&str, String)]) -> HashMap<String, String> {
+        vars.iter()
+            .map(|(name, val)| (name.to_string(), val.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn test_loader_precedence_ok() -> Result<()> {
+        // -- Setup & Fixtures
+        let fx_toml = r#"
+port = 8080
+host = "0.0.0.0"
+shutdown_timeout_sec = 30
+token_key_file = "wrong/path"
+
+[tls]
+cert_path = "certs/cert.pem"
+
+[profile.test]
+port = 9090
+token_key_file = "{dir}/token_key"
+tls.cert_path = "test/cert.pem"
+"#;
+        let dir = fx_dir(&[("token_key", "secret-key\n")])?;
+        let fx_toml = fx_toml.replace("{dir}", &dir.to_string_lossy());
+        std::fs::write(dir.join("app.toml"), fx_toml)?;
+        let env = fx_env(&[
+            (
+                CONFIG_FILE_ENV,
+                dir.join("app.toml").to_string_lossy().into(),
+            ),
+            (PROFILE_ENV, "test".to_string()),
+            ("SERVICE_HOST", "127.0.0.1".to_string()),
+        ]);
+
+        // -- Exec
+        let mut loader = Loader::with_env(env);
+        let host: String = loader.required("HOST");
+        let port: u16 = loader.required("PORT");
+        let shutdown: u64 = loader.required("SHUTDOWN_TIMEOUT_SEC");
+        let duration: f64 = loader.or_default("TOKEN_DURATION_SEC", "1800");
+        let token_key: String = loader.required("TOKEN_KEY");
+        let cert_path: Option<String> = loader.optional("TLS_CERT_PATH");
+        let unix_socket: Option<String> = loader.optional("UNIX_SOCKET");
+        loader.finish();
+
+        // -- Check
+        assert_eq!(loader.problems, Vec::<String>::new());
+        assert_eq!(host, "127.0.0.1"); // env
+        assert_eq!(port, 9090); // profile
+        assert_eq!(shutdown, 30); // top of the file
+        assert_eq!(duration, 1800.); // default
+        assert_eq!(token_key, "secret-key"); // file of the profile
+        assert_eq!(cert_path.as_deref(), Some("test/cert.pem"));
+        assert_eq!(unix_socket, None);
+
+        let dump = loader.dump();
+        assert!(!dump.contains("secret-key"), "{dump}");
+        assert!(dump.contains("TOKEN_KEY"), "{dump}");
+        assert!(dump.contains("env SERVICE_HOST"), "{dump}");
+
+        // -- Clean
+        std::fs::remove_dir_all(dir)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_loader_err_all_problems() -> Result<()> {
+        // -- Setup & Fixtures
+        let fx_toml = r#"
+port = "not-a-port"
+db_url = "postgres://top"
+db_url_file = "db_url"
+tls_cert_pth = "typo"
+
+[profile.staging]
+port = 1
+"#;
+        let dir = fx_dir(&[("app.toml", fx_toml)])?;
+        let env = fx_env(&[
+            (
+                CONFIG_FILE_ENV,
+                dir.join("app.toml").to_string_lossy().into(),
+            ),
+            ("SERVICE_PWD_KEY_FILE", "/no/such/file".to_string()),
+        ]);
+
+        // -- Exec
+        let mut loader = Loader::with_env(env);
+        let _: u16 = loader.required("PORT");
+        let _: String = loader.required("DB_URL");
+        let _: String = loader.required("PWD_KEY");
+        let _: String = loader.required("TOKEN_KEY");
+        let _: Option<String> = loader.optional("TLS_CERT_PATH");
+        loader.finish();
+
+        // -- Check
+        let problems = loader.problems.join("\n");
+        assert_eq!(loader.problems.len(), 6, "{problems}");
+        for expected in [
+            "unknown profile 'staging'",
+            "SERVICE_PORT (port) - wrong format 'not-a-port'",
+            "only one is allowed",
+            "env SERVICE_PWD_KEY_FILE - cannot read '/no/such/file'",
+            "SERVICE_TOKEN_KEY (token_key) - missing",
+            "unknown key 'tls_cert_pth'",
+        ] {
+            assert!(
+                problems.contains(expected),
+                "{expected:?} not in:\n{problems}"
+            );
+        }
+
+        // -- Clean
+        std::fs::remove_dir_all(dir)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_loader_err_file_syntax() -> Result<()> {
+        // -- Setup & Fixtures
+        let fx_toml = "port = 8080\nhost = \"unclosed\n";
+        let dir = fx_dir(&[("app.toml", fx_toml)])?;
+        let env = fx_env(&[(
+            CONFIG_FILE_ENV,
+            dir.join("app.toml").to_string_lossy().into(),
+        )]);
+
+        // -- Exec
+        let loader = Loader::with_env(env);
+
+        // -- Check
+        let problems = loader.problems.join("\n");
+        assert_eq!(loader.problems.len(), 1, "{problems}");
+        assert!(problems.contains("cannot load"), "{problems}");
+        assert!(problems.contains("line 2"), "{problems}");
+
+        // -- Clean
+        std::fs::remove_dir_all(dir)?;
+
+        Ok(())
+    }
+}
+// endregion: --- Tests
@@ -0,0 +1,245 @@
+//! The config is loaded once, from the env variables, the config file and the defaults
+//! (see `loader` for the precedence), and all its problems are reported at once.
+//!
+//! - `SERVICE_CONFIG_FILE`: path of the TOML config file (optional).
+//! - `SERVICE_PROFILE`: `dev` (default), `test` or `prod`, the `[profile.<name>]` table
+//!   of the config file overriding its top values.
+//! - `SERVICE_<KEY>_FILE` (or `<key>_file` in the config file): the value is the content
+//!   of that file (e.g., `SERVICE_TOKEN_KEY_FILE=/run/secrets/token_key`).
+
+mod loader;
+
+use crate::log::sink::SinkConfig;
+use crate::model::blob_store::S3Config;
+use crate::server::TlsConfig;
+use crate::telemetry::OtelConfig;
+use crate::{Error, Result};
+use loader::Loader;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+// config is small enough to use the crate errors instead of using its own errors
+
+/// Retrieves the global configuration.
+///
+/// This function lazily initializes and returns a reference to the global `Config` object.
+pub fn config() -> &'static Config {
+    // only one thread can acquire the lock and initialize the config
+    static INSTANCE: OnceLock<Config> = OnceLock::new();
+
+    // here we want to panic because if the config is not there, we don't want the app
+    // to start at all
+    INSTANCE.get_or_init(|| {
+        Config::load().unwrap_or_else(|ex| match ex {
+            Error::ConfigInvalid { problems } => panic!(
+                "FATAL - WHILE LOADING CONF - {} problem(s):\n- {}",
+                problems.len(),
+                problems.join("\n- ")
+            ),
+            ex => panic!("FATAL - WHILE LOADING CONF - Cause: {ex}"),
+        })
+    })
+}
+
+/// The effective config, with the origin of each value and the secrets redacted,
+/// then its problems (for `--dump-config`).
+pub fn dump_redacted() -> String {
+    let mut loader = Loader::new();
+    Config::read(&mut loader);
+    loader.finish();
+
+    loader.dump()
+}
+
+#[allow(non_snake_case)]
+pub struct Config {
+    // -- Crypt
+    pub PWD_KEY: Vec<u8>,
+    pub TOKEN_KEY: Vec<u8>,
+    pub TOKEN_DURATION_SEC: f64,
+    // -- DB
+    pub DB_URL: String,
+    // -- Web
+    pub WEB_FOLDER: String,
+    // -- Server
+    pub HOST: String,
+    pub PORT: u16,
+    /// Set when `SERVICE_TLS_CERT_PATH` is, then the server only accepts https.
+    pub TLS: Option<TlsConfig>,
+    /// Path of a Unix domain socket to listen on too.
+    pub UNIX_SOCKET: Option<String>,
+    /// How long the shutdown waits for the connections to close.
+    pub SHUTDOWN_TIMEOUT_SEC: u64,
+    // -- Request log
+    /// From `SERVICE_REQUEST_LOG_SINKS`, all of them get the request log lines.
+    pub REQUEST_LOG_SINKS: Vec<SinkConfig>,
+    /// Share of the successful requests logged (0 to 1), the errors are always logged.
+    pub REQUEST_LOG_SUCCESS_SAMPLE_RATIO: f64,
+    // -- Telemetry
+    /// Set when `SERVICE_OTEL_ENDPOINT` is, the traces are not exported otherwise.
+    pub OTEL: Option<OtelConfig>,
+    // -- Task
+    pub TASK_DONE_REQUIRES_NO_OPEN_BLOCKERS: bool,
+    // -- Blob store
+    /// Root of the local blob store, used when `S3` is `None`.
+    pub BLOB_DIR: String,
+    /// Set when `SERVICE_S3_BUCKET` is.
+    pub S3: Option<S3Config>,
+    // -- Audit
+    /// The audit events older than this are pruned by the scheduler.
+    pub AUDIT_RETENTION_DAYS: i64,
+    // -- Attachment
+    /// Total size of the attachments of a user.
+    pub ATTACHMENT_QUOTA_BYTES: i64,
+}
+
+impl Config {
+    fn load() -> Result<Config> {
+        let mut loader = Loader::new();
+        let config = Config::read(&mut loader);
+        loader.finish();
+
+        if !loader.problems.is_empty() {
+            return Err(Error::ConfigInvalid {
+                problems: loader.problems,
+            });
+        }
+
+        Ok(config)
+    }
+
+    /// Reads all the keys (even when some are not needed, so that the unknown keys of
+    /// the config file can be reported), the problems are in the loader.
+    fn read(l: &mut Loader) -> Config {
+        Config {
+            PWD_KEY: read_b64u(l, "PWD_KEY"),
+            TOKEN_KEY: read_b64u(l, "TOKEN_KEY"),
+            TOKEN_DURATION_SEC: l.or_default("TOKEN_DURATION_SEC", "1800"),
+            DB_URL: l.required("DB_URL"),
+            WEB_FOLDER: l.or_default("WEB_FOLDER", "web-folder/"),
+            HOST: l.or_default("HOST", "0.0.0.0"),
+            PORT: l.or_default("PORT", "8080"),
+            TLS: read_tls(l),
+            UNIX_SOCKET: l.optional("UNIX_SOCKET"),
+            SHUTDOWN_TIMEOUT_SEC: l.or_default("SHUTDOWN_TIMEOUT_SEC", "30"),
+            REQUEST_LOG_SINKS: read_request_log_sinks(l),
+            REQUEST_LOG_SUCCESS_SAMPLE_RATIO: read_ratio(l, "REQUEST_LOG_SUCCESS_SAMPLE_RATIO"),
+            OTEL: read_otel(l),
+            TASK_DONE_REQUIRES_NO_OPEN_BLOCKERS: l
+                .or_default("TASK_DONE_REQUIRES_NO_OPEN_BLOCKERS", "true"),
+            BLOB_DIR: l.or_default("BLOB_DIR", "blobs/"),
+            S3: read_s3(l),
+            AUDIT_RETENTION_DAYS: l.or_default("AUDIT_RETENTION_DAYS", "90"),
+            ATTACHMENT_QUOTA_BYTES: l.or_default("ATTACHMENT_QUOTA_BYTES", "104857600"),
+        }
+    }
+}
+
+fn read_s3(l: &mut Loader) -> Option<S3Config> {
+    let bucket: Option<String> = l.optional("S3_BUCKET");
+    let needed = bucket.is_some();
+    let reason = "with SERVICE_S3_BUCKET";
+
+    let s3_config = S3Config {
+        endpoint: l.required_if("S3_ENDPOINT", needed, reason),
+        bucket: bucket.clone().unwrap_or_default(),
+        region: l.required_if("S3_REGION", needed, reason),
+        access_key_id: l.required_if("S3_ACCESS_KEY_ID", needed, reason),
+        secret_access_key: l.required_if("S3_SECRET_ACCESS_KEY", needed, reason),
+    };
+
+    bucket.map(|_| s3_config)
+}
+
+fn read_tls(l: &mut Loader) -> Option<TlsConfig> {
+    let cert_path: Option<String> = l.optional("TLS_CERT_PATH");
+    let key_path = l.required_if(
+        "TLS_KEY_PATH",
+        cert_path.is_some(),
+        "with SERVICE_TLS_CERT_PATH",
+    );
+
+    cert_path.map(|cert_path| TlsConfig {
+        cert_path,
+        key_path,
+    })
+}
+
+/// Comma separated list of `debug`, `stdout`, `file` and `http`.
+fn read_request_log_sinks(l: &mut Loader) -> Vec<SinkConfig> {
+    let names: String = l.or_default("REQUEST_LOG_SINKS", "debug");
+    let names: Vec<String> = names
+        .split(',')
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .collect();
+    let has = |sink: &str| names.iter().any(|name| name == sink);
+    let (has_file, has_http) = (has("file"), has("http"));
+
+    let file_path: String = l.required_if("REQUEST_LOG_FILE", has_file, "with the file sink");
+    let file_max_bytes = l.or_default("REQUEST_LOG_FILE_MAX_BYTES", "10485760");
+    let file_rotate_interval = l
+        .optional("REQUEST_LOG_FILE_ROTATE_SEC")
+        .map(Duration::from_secs);
+    let http_url: String = l.required_if("REQUEST_LOG_HTTP_URL", has_http, "with the http sink");
+    let http_batch_size = l.or_default("REQUEST_LOG_HTTP_BATCH_SIZE", "100");
+
+    let mut sinks = Vec::new();
+    for name in &names {
+        let sink = match name.as_str() {
+            "debug" => SinkConfig::Debug,
+            "stdout" => SinkConfig::Stdout,
+            "file" => SinkConfig::File {
+                path: file_path.clone(),
+                max_bytes: file_max_bytes,
+                rotate_interval: file_rotate_interval,
+            },
+            "http" => SinkConfig::Http {
+                url: http_url.clone(),
+                batch_size: http_batch_size,
+            },
+            _ => {
+                l.problem(
+                    "REQUEST_LOG_SINKS",
+                    format!("unknown sink '{name}' (expected debug, stdout, file or http)"),
+                );
+                continue;
+            }
+        };
+        sinks.push(sink);
+    }
+
+    sinks
+}
+
+fn read_otel(l: &mut Loader) -> Option<OtelConfig> {
+    let endpoint: Option<String> = l.optional("OTEL_ENDPOINT");
+    let sample_ratio = read_ratio(l, "OTEL_SAMPLE_RATIO");
+
+    endpoint.map(|endpoint| OtelConfig {
+        endpoint,
+        sample_ratio,
+    })
+}
+
+/// Required, base64url encoded.
+fn read_b64u(l: &mut Loader, key: &'static str) -> Vec<u8> {
+    let val: String = l.required(key);
+    if val.is_empty() {
+        return Vec::new();
+    }
+
+    base64_url::decode(&val).unwrap_or_else(|_| {
+        l.problem(key, "wrong format (not base64url)");
+        Vec::new()
+    })
+}
+
+/// Between 0 and 1, 1 by default.
+fn read_ratio(l: &mut Loader, key: &'static str) -> f64 {
+    let ratio: f64 = l.or_default(key, "1.0");
+    if !(0. ..=1.).contains(&ratio) {
+        l.problem(key, format!("{ratio} is not between 0 and 1"));
+    }
+    ratio
+}
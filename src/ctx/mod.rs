@@ -4,26 +4,66 @@ mod error;
 
 pub use self::error::{Error, Result};
 
+use std::net::IpAddr;
+use std::sync::Arc;
+
 // endregion: --- Modules
 
+/// Where the request of a ctx comes from (see `web::mw_ctx_resolve`),
+/// for the spans and the audit events.
+#[derive(Debug, Default)]
+pub struct CtxOrigin {
+    /// See `web::mw_request_id`.
+    pub request_id: Option<String>,
+    pub client_ip: Option<IpAddr>,
+    pub user_agent: Option<String>,
+}
+
 #[derive(Clone, Debug)]
 pub struct Ctx {
     user_id: i64,
+    /// Active workspace, the data of the other workspaces is not accessible.
+    /// Only the root ctx can be without one, then it sees all the workspaces.
+    workspace_id: Option<i64>,
+    /// None when not from a web request (e.g., the scheduler).
+    origin: Option<Arc<CtxOrigin>>,
 }
 
 // Constructor.
 impl Ctx {
     pub fn root_ctx() -> Self {
-        Ctx { user_id: 0 }
+        Ctx {
+            user_id: 0,
+            workspace_id: None,
+            origin: None,
+        }
     }
 
-    pub fn new(user_id: i64) -> Result<Self> {
+    /// The root ctx restricted to the workspace.
+    pub fn root_ctx_in(workspace_id: i64) -> Self {
+        Ctx {
+            user_id: 0,
+            workspace_id: Some(workspace_id),
+            origin: None,
+        }
+    }
+
+    pub fn new(user_id: i64, workspace_id: Option<i64>) -> Result<Self> {
         if user_id == 0 {
             Err(Error::CtxCannotNewRootCtx)
         } else {
-            Ok(Self { user_id })
+            Ok(Self {
+                user_id,
+                workspace_id,
+                origin: None,
+            })
         }
     }
+
+    pub fn with_origin(mut self, origin: Arc<CtxOrigin>) -> Self {
+        self.origin = Some(origin);
+        self
+    }
 }
 
 // Property Accessors.
@@ -31,4 +71,20 @@ impl Ctx {
     pub fn user_id(&self) -> i64 {
         self.user_id
     }
+
+    pub fn workspace_id(&self) -> Option<i64> {
+        self.workspace_id
+    }
+
+    pub fn origin(&self) -> Option<&CtxOrigin> {
+        self.origin.as_deref()
+    }
+
+    pub fn request_id(&self) -> Option<&str> {
+        self.origin()?.request_id.as_deref()
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.user_id == 0
+    }
 }
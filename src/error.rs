@@ -1,14 +1,26 @@
-use crate::model;
+use crate::{log, model};
 
 pub type Result<T> = core::result::Result<T, Error>;
 
 #[derive(Debug)]
 pub enum Error {
     // -- Config
-    ConfigMissingEnv(&'static str),
-    ConfigWrongFormat(&'static str),
+    /// All the problems of the config (see `config::loader`).
+    ConfigInvalid {
+        problems: Vec<String>,
+    },
+    // -- Server
+    ServerBindFail {
+        addr: String,
+        cause: String,
+    },
+    ServerTlsLoadFail {
+        path: String,
+        cause: String,
+    },
     // -- Modules
     Model(model::Error),
+    Log(log::Error),
 }
 
 // region:    --- Froms
@@ -17,6 +29,12 @@ impl From<model::Error> for Error {
         Self::Model(val)
     }
 }
+
+impl From<log::Error> for Error {
+    fn from(val: log::Error) -> Self {
+        Self::Log(val)
+    }
+}
 // endregion: --- Froms
 
 // region:    --- Error Boilerplate
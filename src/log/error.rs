@@ -0,0 +1,38 @@
+use serde::Serialize;
+
+pub type Result<T> = core::result::Result<T, Error>;
+
+#[derive(Debug, Serialize)]
+pub enum Error {
+    Io(String),
+    SerdeJson(String),
+    // -- Http sink
+    HttpSinkRequestFail(String),
+    HttpSinkFail { status: u16 },
+}
+
+impl From<std::io::Error> for Error {
+    fn from(val: std::io::Error) -> Self {
+        Self::Io(val.to_string())
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(val: serde_json::Error) -> Self {
+        Self::SerdeJson(val.to_string())
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(val: reqwest::Error) -> Self {
+        Self::HttpSinkRequestFail(val.to_string())
+    }
+}
+
+impl core::fmt::Display for Error {
+    fn fmt(&self, fmt: &mut core::fmt::Formatter) -> core::result::Result<(), core::fmt::Error> {
+        write!(fmt, "{self:?}")
+    }
+}
+
+impl std::error::Error for Error {}
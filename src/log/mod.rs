@@ -1,28 +1,135 @@
+//! Request log lines
+//!
+//! - The lines are written to the sinks (see `sink`) by a writer task (see `start_writer`),
+//!   through a bounded channel. When the channel is full, the line is dropped (and counted),
+//!   so that logging never delays or fails a request.
+//! - `flush` writes the pending lines, at shutdown.
+//! - The successful requests are sampled at `REQUEST_LOG_SUCCESS_SAMPLE_RATIO`,
+//!   the errors (error status or web error) are always logged.
+//! - Without a writer (e.g., tests, or after the flush), the lines are written with `debug!`.
+
+// region:    --- Modules
+
+mod error;
+pub mod sink;
+
+pub use self::error::{Error, Result};
+
+use self::sink::{debug_line, LogSink};
+use crate::config;
 use crate::ctx::Ctx;
+use crate::utils::{format_time, now_utc};
 use crate::web::rpc::RpcInfo;
-use crate::web::{self, ClientError};
-use crate::Result;
-use axum::http::{Method, Uri};
+use crate::web::{self, ClientError, RequestInfo};
+use axum::http::{Method, StatusCode, Uri};
 use serde::Serialize;
-use serde_json::{json, Value};
+use serde_json::Value;
 use serde_with::skip_serializing_none;
-use std::time::{SystemTime, UNIX_EPOCH};
-use tracing::debug;
-use uuid::Uuid;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tracing::{error, warn};
+
+// endregion: --- Modules
+
+/// Pending lines, the next ones are dropped.
+const WRITER_BUFFER: usize = 4096;
+/// Max lines given at once to the sinks.
+const WRITE_BATCH_MAX: usize = 256;
+/// How often the sinks are flushed (and the dropped lines reported).
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+static WRITER: Mutex<Option<Writer>> = Mutex::new(None);
+/// Lines dropped because the channel was full, since the last report.
+static DROPPED: AtomicU64 = AtomicU64::new(0);
+
+struct Writer {
+    tx: mpsc::Sender<RequestLogLine>,
+    task: JoinHandle<()>,
+}
+
+/// Starts the writer task to the sinks (replacing the previous one, if any).
+pub fn start_writer(sinks: Vec<Box<dyn LogSink>>) {
+    let (tx, rx) = mpsc::channel(WRITER_BUFFER);
+    let task = tokio::spawn(write_loop(rx, sinks));
+
+    *lock_writer() = Some(Writer { tx, task });
+}
+
+/// Writes the pending lines, flushes the sinks and stops the writer.
+pub async fn flush() {
+    let Some(Writer { tx, task }) = lock_writer().take() else {
+        return;
+    };
+
+    // the writer ends once the senders are dropped and the channel is empty
+    drop(tx);
+    let _ = task.await;
+}
 
+// a panic while holding the lock leaves a valid value, so poisoning is ignored
+fn lock_writer() -> std::sync::MutexGuard<'static, Option<Writer>> {
+    WRITER.lock().unwrap_or_else(|ex| ex.into_inner())
+}
+
+async fn write_loop(mut rx: mpsc::Receiver<RequestLogLine>, mut sinks: Vec<Box<dyn LogSink>>) {
+    let mut interval = tokio::time::interval(FLUSH_INTERVAL);
+    let mut batch = Vec::with_capacity(WRITE_BATCH_MAX);
+
+    loop {
+        tokio::select! {
+            count = rx.recv_many(&mut batch, WRITE_BATCH_MAX) => {
+                // closed and empty
+                if count == 0 {
+                    break;
+                }
+                for sink in sinks.iter_mut() {
+                    if let Err(ex) = sink.write(&batch).await {
+                        error!("{:<12} - request log sink write fail: {ex}", "LOG");
+                    }
+                }
+                batch.clear();
+            }
+            _ = interval.tick() => {
+                flush_sinks(&mut sinks).await;
+                let dropped = DROPPED.swap(0, Ordering::Relaxed);
+                if dropped > 0 {
+                    warn!("{:<12} - {dropped} request log lines dropped", "LOG");
+                }
+            }
+        }
+    }
+
+    flush_sinks(&mut sinks).await;
+}
+
+async fn flush_sinks(sinks: &mut [Box<dyn LogSink>]) {
+    for sink in sinks.iter_mut() {
+        if let Err(ex) = sink.flush().await {
+            error!("{:<12} - request log sink flush fail: {ex}", "LOG");
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 pub async fn log_request(
-    uuid: Uuid,
+    request_id: &str,
     req_method: Method,
     uri: Uri,
+    req_info: Option<&RequestInfo>,
+    status: StatusCode,
+    res_body_size: Option<u64>,
     rpc_info: Option<&RpcInfo>,
     ctx: Option<Ctx>,
     web_error: Option<&web::Error>,
     client_error: Option<ClientError>,
 ) -> Result<()> {
-    let timestamp = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap()
-        .as_millis();
+    let is_error = web_error.is_some() || status.is_client_error() || status.is_server_error();
+    if !is_error && !is_sampled(config().REQUEST_LOG_SUCCESS_SAMPLE_RATIO) {
+        return Ok(());
+    }
 
     let error_type = web_error.map(|se| se.as_ref().to_string());
     let error_data = serde_json::to_value(web_error)
@@ -31,11 +138,18 @@ pub async fn log_request(
 
     // Create the RequestLogLine
     let log_line = RequestLogLine {
-        uuid: uuid.to_string(),
-        timestamp: timestamp.to_string(),
+        uuid: request_id.to_string(),
+        timestamp: format_time(now_utc()),
+        duration_ms: req_info.map(|info| info.start.elapsed().as_secs_f64() * 1000.),
+
+        client_ip: req_info.and_then(|info| info.origin.client_ip.map(|ip| ip.to_string())),
+        user_agent: req_info.and_then(|info| info.origin.user_agent.clone()),
 
         http_path: uri.to_string(),
         http_method: req_method.to_string(),
+        http_status: status.as_u16(),
+        req_body_bytes: req_info.and_then(|info| info.body_size),
+        res_body_bytes: res_body_size,
 
         rpc_id: rpc_info.and_then(|rpc| rpc.id.as_ref().map(|id| id.to_string())),
         rpc_method: rpc_info.map(|rpc| rpc.method.to_string()),
@@ -48,25 +162,44 @@ pub async fn log_request(
         error_data,
     };
 
-    debug!("REQUEST LOG LINE:\n{}", json!(log_line));
-
-    // TODO - Send to cloud-watch.
+    let tx = lock_writer().as_ref().map(|writer| writer.tx.clone());
+    match tx {
+        Some(tx) => match tx.try_send(log_line) {
+            Ok(()) => (),
+            Err(mpsc::error::TrySendError::Full(_)) => {
+                DROPPED.fetch_add(1, Ordering::Relaxed);
+            }
+            // the writer is gone, written here instead
+            Err(mpsc::error::TrySendError::Closed(log_line)) => debug_line(&log_line),
+        },
+        None => debug_line(&log_line),
+    }
 
     Ok(())
 }
 
+fn is_sampled(ratio: f64) -> bool {
+    ratio >= 1. || rand::random::<f64>() < ratio
+}
+
 #[skip_serializing_none]
 #[derive(Serialize)]
-struct RequestLogLine {
-    uuid: String,      // uuid string formatted
-    timestamp: String, // (should be iso8601)
+pub struct RequestLogLine {
+    uuid: String,      // request id (see `web::mw_request_id`), uuid unless from the caller
+    timestamp: String, // (Rfc3339, so iso8601)
+    duration_ms: Option<f64>,
 
     // -- User and context attributes.
     user_id: Option<i64>,
+    client_ip: Option<String>,
+    user_agent: Option<String>,
 
     // -- http request attributes.
     http_path: String,
     http_method: String,
+    http_status: u16, // final, after the error remapping
+    req_body_bytes: Option<u64>,
+    res_body_bytes: Option<u64>,
 
     // -- rpc info
     rpc_id: Option<String>,
@@ -77,3 +210,29 @@ struct RequestLogLine {
     error_type: Option<String>,
     error_data: Option<Value>,
 }
+
+// region:    --- Test Fixtures
+#[cfg(test)]
+impl RequestLogLine {
+    pub(crate) fn fx_new() -> Self {
+        RequestLogLine {
+            uuid: uuid::Uuid::new_v4().to_string(),
+            timestamp: format_time(now_utc()),
+            duration_ms: Some(1.5),
+            user_id: Some(1000),
+            client_ip: Some("127.0.0.1".to_string()),
+            user_agent: Some("test".to_string()),
+            http_path: "/api/rpc".to_string(),
+            http_method: "POST".to_string(),
+            http_status: 200,
+            req_body_bytes: Some(64),
+            res_body_bytes: Some(128),
+            rpc_id: Some("1".to_string()),
+            rpc_method: Some("list_tasks".to_string()),
+            client_error_type: None,
+            error_type: None,
+            error_data: None,
+        }
+    }
+}
+// endregion: --- Test Fixtures
@@ -0,0 +1,156 @@
+use crate::log::sink::LogSink;
+use crate::log::{RequestLogLine, Result};
+use crate::utils::now_utc;
+use async_trait::async_trait;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tokio::fs::{self, File, OpenOptions};
+use tokio::io::{AsyncWriteExt, BufWriter};
+
+/// Newline-delimited JSON file. When rotated, the file is renamed to `<path>.<utc time>`
+/// (e.g., `requests.ndjson.20240301T120000123Z`) and a new one is started.
+pub struct FileSink {
+    path: PathBuf,
+    max_bytes: u64,
+    rotate_interval: Option<Duration>,
+    file: BufWriter<File>,
+    /// Bytes in the current file.
+    size: u64,
+    opened_at: Instant,
+}
+
+impl FileSink {
+    /// Appends to the file when it exists (creating the parent directories if needed).
+    pub async fn open(
+        path: impl AsRef<Path>,
+        max_bytes: u64,
+        rotate_interval: Option<Duration>,
+    ) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            fs::create_dir_all(parent).await?;
+        }
+        let (file, size) = open_append(&path).await?;
+
+        Ok(FileSink {
+            path,
+            max_bytes,
+            rotate_interval,
+            file,
+            size,
+            opened_at: Instant::now(),
+        })
+    }
+
+    async fn rotate(&mut self) -> Result<()> {
+        self.file.flush().await?;
+
+        let now = now_utc();
+        let time = format!(
+            "{:04}{:02}{:02}T{:02}{:02}{:02}{:03}Z",
+            now.year(),
+            u8::from(now.month()),
+            now.day(),
+            now.hour(),
+            now.minute(),
+            now.second(),
+            now.millisecond()
+        );
+        let mut rotated = self.path.clone().into_os_string();
+        rotated.push(format!(".{time}"));
+        fs::rename(&self.path, &rotated).await?;
+
+        (self.file, self.size) = open_append(&self.path).await?;
+        self.opened_at = Instant::now();
+
+        Ok(())
+    }
+
+    fn is_expired(&self) -> bool {
+        self.rotate_interval
+            .is_some_and(|interval| self.opened_at.elapsed() >= interval)
+    }
+}
+
+async fn open_append(path: &Path) -> Result<(BufWriter<File>, u64)> {
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await?;
+    let size = file.metadata().await?.len();
+
+    Ok((BufWriter::new(file), size))
+}
+
+#[async_trait]
+impl LogSink for FileSink {
+    async fn write(&mut self, lines: &[RequestLogLine]) -> Result<()> {
+        for line in lines {
+            let mut json = serde_json::to_vec(line)?;
+            json.push(b'\n');
+
+            // a line is never split, so a file may only exceed `max_bytes` with its first line
+            let is_full = self.size > 0 && self.size + json.len() as u64 > self.max_bytes;
+            if is_full || (self.size > 0 && self.is_expired()) {
+                self.rotate().await?;
+            }
+
+            self.file.write_all(&json).await?;
+            self.size += json.len() as u64;
+        }
+
+        Ok(())
+    }
+
+    async fn flush(&mut self) -> Result<()> {
+        self.file.flush().await?;
+        Ok(())
+    }
+}
+
+// region:    --- Tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use uuid::Uuid;
+
+    #[tokio::test]
+    async fn test_file_sink_rotate_size_ok() -> Result<()> {
+        // -- Setup & Fixtures
+        let fx_dir = std::env::temp_dir().join(format!("request-log-{}", Uuid::new_v4()));
+        let fx_path = fx_dir.join("requests.ndjson");
+        let fx_lines: Vec<_> = (0..3).map(|_| RequestLogLine::fx_new()).collect();
+        let line_len = serde_json::to_vec(&fx_lines[0])?.len() as u64 + 1;
+        // room for 2 lines per file (with some slack, the timestamps vary in length)
+        let mut sink = FileSink::open(&fx_path, line_len * 2 + line_len / 2, None).await?;
+
+        // -- Exec
+        sink.write(&fx_lines).await?;
+        sink.flush().await?;
+
+        // -- Check
+        let mut entries = fs::read_dir(&fx_dir).await?;
+        let mut rotated = Vec::new();
+        while let Some(entry) = entries.next_entry().await? {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if name != "requests.ndjson" {
+                rotated.push(entry.path());
+            }
+        }
+        assert_eq!(rotated.len(), 1, "rotated files");
+        let rotated_content = fs::read_to_string(&rotated[0]).await?;
+        let content = fs::read_to_string(&fx_path).await?;
+        assert_eq!(rotated_content.lines().count(), 2);
+        assert_eq!(content.lines().count(), 1);
+        let line: serde_json::Value = serde_json::from_str(content.trim_end())?;
+        assert_eq!(line["uuid"], fx_lines[2].uuid);
+
+        // -- Clean
+        fs::remove_dir_all(&fx_dir).await?;
+
+        Ok(())
+    }
+}
+// endregion: --- Tests
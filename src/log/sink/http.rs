@@ -0,0 +1,144 @@
+use crate::log::sink::LogSink;
+use crate::log::{Error, RequestLogLine, Result};
+use async_trait::async_trait;
+use reqwest::header::CONTENT_TYPE;
+use reqwest::Client;
+use std::time::Duration;
+
+const POST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Posts the lines as newline-delimited JSON (`application/x-ndjson`), by batches of
+/// `batch_size` (and the remaining ones at each flush).
+/// A failed batch is dropped, so that a down collector does not hold the lines in memory.
+pub struct HttpSink {
+    client: Client,
+    url: String,
+    batch_size: usize,
+    /// The serialized lines, each ending with `\n`.
+    batch: Vec<u8>,
+    batch_count: usize,
+}
+
+impl HttpSink {
+    pub fn new(url: impl Into<String>, batch_size: usize) -> Result<Self> {
+        let client = Client::builder().timeout(POST_TIMEOUT).build()?;
+
+        Ok(HttpSink {
+            client,
+            url: url.into(),
+            batch_size: batch_size.max(1),
+            batch: Vec::new(),
+            batch_count: 0,
+        })
+    }
+
+    async fn post(&mut self) -> Result<()> {
+        if self.batch_count == 0 {
+            return Ok(());
+        }
+        let body = std::mem::take(&mut self.batch);
+        self.batch_count = 0;
+
+        let res = self
+            .client
+            .post(&self.url)
+            .header(CONTENT_TYPE, "application/x-ndjson")
+            .body(body)
+            .send()
+            .await?;
+        if !res.status().is_success() {
+            return Err(Error::HttpSinkFail {
+                status: res.status().as_u16(),
+            });
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl LogSink for HttpSink {
+    async fn write(&mut self, lines: &[RequestLogLine]) -> Result<()> {
+        for line in lines {
+            serde_json::to_writer(&mut self.batch, line)?;
+            self.batch.push(b'\n');
+            self.batch_count += 1;
+
+            if self.batch_count >= self.batch_size {
+                self.post().await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn flush(&mut self) -> Result<()> {
+        self.post().await
+    }
+}
+
+// region:    --- Tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use axum::extract::State;
+    use axum::http::{HeaderMap, StatusCode};
+    use axum::routing::post;
+    use axum::Router;
+    use std::sync::{Arc, Mutex};
+
+    type Posts = Arc<Mutex<Vec<String>>>;
+
+    /// Collector stand-in, keeping the bodies of the ndjson posts.
+    async fn serve_collector_stand_in() -> Result<(String, Posts)> {
+        async fn post_lines(
+            State(posts): State<Posts>,
+            headers: HeaderMap,
+            body: String,
+        ) -> StatusCode {
+            if headers.get(CONTENT_TYPE).and_then(|v| v.to_str().ok())
+                != Some("application/x-ndjson")
+            {
+                return StatusCode::UNSUPPORTED_MEDIA_TYPE;
+            }
+            posts.lock().unwrap().push(body);
+            StatusCode::OK
+        }
+
+        let posts: Posts = Arc::default();
+        let routes = Router::new()
+            .route("/logs", post(post_lines))
+            .with_state(posts.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        tokio::spawn(async move { axum::serve(listener, routes).await });
+
+        Ok((format!("http://{addr}/logs"), posts))
+    }
+
+    #[tokio::test]
+    async fn test_http_sink_batch_ok() -> Result<()> {
+        // -- Setup & Fixtures
+        let (url, posts) = serve_collector_stand_in().await?;
+        let fx_lines: Vec<_> = (0..3).map(|_| RequestLogLine::fx_new()).collect();
+        let mut sink = HttpSink::new(url, 2)?;
+
+        // -- Exec
+        sink.write(&fx_lines).await?;
+        let posted_before_flush = posts.lock().unwrap().len();
+        sink.flush().await?;
+
+        // -- Check
+        assert_eq!(posted_before_flush, 1);
+        let posts = posts.lock().unwrap().clone();
+        assert_eq!(posts.len(), 2);
+        assert_eq!(posts[0].lines().count(), 2);
+        assert_eq!(posts[1].lines().count(), 1);
+        let line: serde_json::Value = serde_json::from_str(posts[1].trim_end())?;
+        assert_eq!(line["uuid"], fx_lines[2].uuid);
+
+        Ok(())
+    }
+}
+// endregion: --- Tests
@@ -0,0 +1,114 @@
+//! Destinations of the request log lines, behind the `LogSink` trait:
+//!
+//! - `DebugSink` - the `debug!` log of the app (the default, for dev).
+//! - `StdoutSink` - one JSON line per request on stdout (e.g., for the container logs).
+//! - `FileSink` - newline-delimited JSON files, rotated by size and/or time.
+//! - `HttpSink` - batches of newline-delimited JSON posted to a collector.
+//!
+//! Configured with `REQUEST_LOG_SINKS` (see `config`), all the sinks get all the lines.
+
+// region:    --- Modules
+
+mod file;
+mod http;
+
+pub use self::file::FileSink;
+pub use self::http::HttpSink;
+
+use super::{RequestLogLine, Result};
+use async_trait::async_trait;
+use serde_json::json;
+use std::io::Write;
+use std::time::Duration;
+use tracing::debug;
+
+// endregion: --- Modules
+
+#[async_trait]
+pub trait LogSink: Send {
+    /// Lines in the order of the requests (may be buffered until `flush`).
+    async fn write(&mut self, lines: &[RequestLogLine]) -> Result<()>;
+
+    /// Called periodically by the writer, and before it stops.
+    async fn flush(&mut self) -> Result<()>;
+}
+
+#[derive(Debug, Clone)]
+pub enum SinkConfig {
+    Debug,
+    Stdout,
+    File {
+        path: String,
+        /// The file is rotated before exceeding this size.
+        max_bytes: u64,
+        /// The file is also rotated when older than this, if set.
+        rotate_interval: Option<Duration>,
+    },
+    Http {
+        url: String,
+        /// Lines per post (the remaining ones are posted at each flush).
+        batch_size: usize,
+    },
+}
+
+/// Note: opens the files, so that a wrong path fails at startup.
+pub async fn new_sinks(configs: &[SinkConfig]) -> Result<Vec<Box<dyn LogSink>>> {
+    let mut sinks: Vec<Box<dyn LogSink>> = Vec::with_capacity(configs.len());
+    for config in configs {
+        let sink: Box<dyn LogSink> = match config {
+            SinkConfig::Debug => Box::new(DebugSink),
+            SinkConfig::Stdout => Box::new(StdoutSink),
+            SinkConfig::File {
+                path,
+                max_bytes,
+                rotate_interval,
+            } => Box::new(FileSink::open(path, *max_bytes, *rotate_interval).await?),
+            SinkConfig::Http { url, batch_size } => Box::new(HttpSink::new(url, *batch_size)?),
+        };
+        sinks.push(sink);
+    }
+
+    Ok(sinks)
+}
+
+// region:    --- Debug & Stdout
+
+pub struct DebugSink;
+
+#[async_trait]
+impl LogSink for DebugSink {
+    async fn write(&mut self, lines: &[RequestLogLine]) -> Result<()> {
+        lines.iter().for_each(debug_line);
+        Ok(())
+    }
+
+    async fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+pub struct StdoutSink;
+
+#[async_trait]
+impl LogSink for StdoutSink {
+    async fn write(&mut self, lines: &[RequestLogLine]) -> Result<()> {
+        // one lock for the batch, so that the lines are not interleaved with other logs
+        let mut stdout = std::io::stdout().lock();
+        for line in lines {
+            serde_json::to_writer(&mut stdout, line)?;
+            stdout.write_all(b"\n")?;
+        }
+        Ok(())
+    }
+
+    async fn flush(&mut self) -> Result<()> {
+        std::io::stdout().flush()?;
+        Ok(())
+    }
+}
+
+pub(super) fn debug_line(line: &RequestLogLine) {
+    debug!("REQUEST LOG LINE:\n{}", json!(line));
+}
+
+// endregion: --- Debug & Stdout
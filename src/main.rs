@@ -8,7 +8,11 @@ mod crypt;
 mod ctx;
 mod error;
 mod log;
+mod metrics;
 mod model;
+mod scheduler;
+mod server;
+mod telemetry;
 mod utils;
 mod web;
 
@@ -27,26 +31,63 @@ pub use config::config;
 // then imports
 use crate::model::ModelManager;
 use crate::web::mw_auth::{mw_ctx_require, mw_ctx_resolve};
+use crate::web::mw_metrics::mw_metrics;
+use crate::web::mw_request_id::mw_request_id;
 use crate::web::mw_res_map::mw_reponse_map;
-use crate::web::{routes_login, routes_static, rpc};
+use crate::web::mw_trace::mw_trace;
+use crate::web::{
+    routes_attachment, routes_health, routes_login, routes_metrics, routes_sse, routes_static,
+    routes_ws, rpc,
+};
 use axum::{middleware, Router};
 use std::net::SocketAddr;
+use tokio_util::sync::CancellationToken;
 use tower_cookies::CookieManagerLayer;
-use tracing::info;
-use tracing_subscriber::EnvFilter;
+use tracing::{info, Level};
+use tracing_subscriber::filter::{dynamic_filter_fn, filter_fn, FilterExt};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{EnvFilter, Layer};
 
 // endregion: --- Modules
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    tracing_subscriber::fmt()
-        .without_time() // omits timestamps from logs
-        .with_target(false) // don't show file
-        // filter logs based on the RUST_LOG env var
-        // for ex with RUST_LOG=info,my_crate=debug
-        // we would only use log info or above except for my_crate module
-        // where we log debug or above
-        .with_env_filter(EnvFilter::from_default_env())
+    // -- Effective config (secrets redacted) and its problems, without starting
+    if std::env::args().any(|arg| arg == "--dump-config") {
+        print!("{}", config::dump_redacted());
+        return Ok(());
+    }
+
+    // -- Traces export, when configured
+    let (otel_layer, otel_exporter) = match &config().OTEL {
+        Some(otel_config) => {
+            let (layer, exporter) = telemetry::new_layer(otel_config);
+            (Some(layer), Some(exporter))
+        }
+        None => (None, None),
+    };
+
+    tracing_subscriber::registry()
+        .with(
+            tracing_subscriber::fmt::layer()
+                .without_time() // omits timestamps from logs
+                .with_target(false) // don't show file
+                // filter logs based on the RUST_LOG env var
+                // for ex with RUST_LOG=info,my_crate=debug
+                // we would only use log info or above except for my_crate module
+                // where we log debug or above
+                // the events only, the spans are for the traces
+                .with_filter(EnvFilter::from_default_env().and(filter_fn(|meta| meta.is_event()))),
+        )
+        // the spans of this crate only, whatever RUST_LOG
+        // Note: dynamic, so that the filters are evaluated for each span (with only static
+        //       filters, the first span of a callsite can be missed by the layers)
+        .with(otel_layer.with_filter(dynamic_filter_fn(|meta, _| {
+            meta.is_span()
+                && meta.target().starts_with(env!("CARGO_CRATE_NAME"))
+                && *meta.level() <= Level::INFO
+        })))
         .init();
 
     // -- FIXME: FOR DEV ONLY
@@ -54,9 +95,23 @@ async fn main() -> Result<()> {
 
     // Initialize ModelManager.
     let mm = ModelManager::new().await?;
+    // (the files are opened here, so a wrong path fails before serving)
+    let log_sinks = log::sink::new_sinks(&config().REQUEST_LOG_SINKS).await?;
+
+    // -- Cancelled on SIGINT/SIGTERM, stops the server and the background tasks
+    let shutdown = CancellationToken::new();
+    server::shutdown_on_signal(shutdown.clone());
+
+    // -- Start the background jobs
+    let scheduler = scheduler::spawn(mm.clone(), shutdown.clone());
+    log::start_writer(log_sinks);
 
     // -- Define Routes
-    let routes_rpc = rpc::routes(mm.clone()).route_layer(middleware::from_fn(mw_ctx_require));
+    let routes_rpc = rpc::routes(mm.clone())
+        .merge(routes_ws::routes(mm.clone(), shutdown.clone()))
+        .merge(routes_sse::routes(mm.clone(), shutdown.clone()))
+        .merge(routes_attachment::routes(mm.clone()))
+        .route_layer(middleware::from_fn(mw_ctx_require));
 
     let routes_hello = Router::new()
         .route("/hello", get(|| async { Html("Hello world") }))
@@ -69,13 +124,29 @@ async fn main() -> Result<()> {
         .layer(middleware::map_response(mw_reponse_map))
         .layer(middleware::from_fn_with_state(mm.clone(), mw_ctx_resolve))
         .layer(CookieManagerLayer::new())
-        .fallback_service(routes_static::serve_dir());
+        .layer(middleware::from_fn(mw_metrics))
+        .layer(middleware::from_fn(mw_trace))
+        // after the layers, so without ctx resolve, request log lines, metrics and traces
+        .merge(routes_health::routes(mm.clone()))
+        .merge(routes_metrics::routes(mm.clone()))
+        .fallback_service(routes_static::serve_dir())
+        // outermost, for all the responses
+        .layer(middleware::from_fn(mw_request_id));
 
     // region:    --- Start Server
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:8080").await.unwrap();
-    info!("{:<12} - {:?}\n", "LISTENING", listener.local_addr());
-    axum::serve(listener, routes_all).await.unwrap();
+    // returns once shut down and the connections drained
+    server::serve(routes_all, shutdown.clone()).await?;
     // endregion: --- Start Server
 
+    // region:    --- Shutdown
+    let _ = scheduler.await;
+    log::flush().await;
+    if let Some(otel_exporter) = otel_exporter {
+        otel_exporter.flush().await;
+    }
+    mm.close().await;
+    info!("{:<12} - done", "SHUTDOWN");
+    // endregion: --- Shutdown
+
     Ok(())
 }
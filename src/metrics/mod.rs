@@ -0,0 +1,337 @@
+//! Prometheus metrics, rendered in the text exposition format by `web::routes_metrics`.
+//!
+//! - The counters and histograms are recorded in-process (see `web::mw_metrics`),
+//!   and reset when the process restarts.
+//! - The db pool statistics are read at render time (see `ModelManager::pool_stats`).
+
+use crate::model::PoolStats;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Upper bounds (seconds) of the latency histogram buckets.
+const LATENCY_BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+// region:    --- Metric Names
+
+const HTTP_REQUESTS: &str = "http_requests_total";
+const HTTP_DURATION: &str = "http_request_duration_seconds";
+const RPC_REQUESTS: &str = "rpc_requests_total";
+const RPC_DURATION: &str = "rpc_request_duration_seconds";
+const CLIENT_ERRORS: &str = "client_errors_total";
+const LOGINS: &str = "logins_total";
+
+/// (name, help) of the counters, in render order.
+const COUNTERS: &[(&str, &str)] = &[
+    (HTTP_REQUESTS, "HTTP requests, by method, route and status."),
+    (RPC_REQUESTS, "RPC requests, by method and result."),
+    (CLIENT_ERRORS, "Error responses, by client error."),
+    (LOGINS, "Login attempts, by result."),
+];
+
+/// (name, help) of the histograms, in render order.
+const HISTOGRAMS: &[(&str, &str)] = &[
+    (HTTP_DURATION, "HTTP request latency, by method and route."),
+    (RPC_DURATION, "RPC request latency, by method."),
+];
+
+// endregion: --- Metric Names
+
+static REGISTRY: Mutex<Registry> = Mutex::new(Registry::new());
+
+/// Label values are escaped, the label names are static.
+type Labels = String;
+
+struct Registry {
+    counters: BTreeMap<&'static str, BTreeMap<Labels, u64>>,
+    histograms: BTreeMap<&'static str, BTreeMap<Labels, Histogram>>,
+}
+
+impl Registry {
+    const fn new() -> Self {
+        Registry {
+            counters: BTreeMap::new(),
+            histograms: BTreeMap::new(),
+        }
+    }
+
+    fn inc(&mut self, name: &'static str, labels: Labels) {
+        *self
+            .counters
+            .entry(name)
+            .or_default()
+            .entry(labels)
+            .or_default() += 1;
+    }
+
+    fn observe(&mut self, name: &'static str, labels: Labels, duration: Duration) {
+        self.histograms
+            .entry(name)
+            .or_default()
+            .entry(labels)
+            .or_insert_with(Histogram::new)
+            .observe(duration.as_secs_f64());
+    }
+}
+
+struct Histogram {
+    /// Not cumulative, one per `LATENCY_BUCKETS` (the `+Inf` one is `count`).
+    buckets: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn new() -> Self {
+        Histogram {
+            buckets: vec![0; LATENCY_BUCKETS.len()],
+            sum: 0.,
+            count: 0,
+        }
+    }
+
+    fn observe(&mut self, value: f64) {
+        if let Some(idx) = LATENCY_BUCKETS.iter().position(|le| value <= *le) {
+            self.buckets[idx] += 1;
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+}
+
+// a panic while holding the lock leaves a valid value, so poisoning is ignored
+fn registry() -> std::sync::MutexGuard<'static, Registry> {
+    REGISTRY.lock().unwrap_or_else(|ex| ex.into_inner())
+}
+
+// region:    --- Record
+
+/// `route` is the matched route (e.g., `/api/attachments/:id`), not the path.
+pub fn record_http(method: &str, route: &str, status: u16, duration: Duration) {
+    let mut registry = registry();
+    registry.inc(
+        HTTP_REQUESTS,
+        labels(&[
+            ("method", method),
+            ("route", route),
+            ("status", &status.to_string()),
+        ]),
+    );
+    registry.observe(
+        HTTP_DURATION,
+        labels(&[("method", method), ("route", route)]),
+        duration,
+    );
+}
+
+pub fn record_rpc(method: &str, success: bool, duration: Duration) {
+    let result = if success { "success" } else { "error" };
+    let mut registry = registry();
+    registry.inc(
+        RPC_REQUESTS,
+        labels(&[("method", method), ("result", result)]),
+    );
+    registry.observe(RPC_DURATION, labels(&[("method", method)]), duration);
+}
+
+/// `error` is the `ClientError` variant name.
+pub fn record_client_error(error: &str) {
+    registry().inc(CLIENT_ERRORS, labels(&[("error", error)]));
+}
+
+pub fn record_login(success: bool) {
+    let result = if success { "success" } else { "failure" };
+    registry().inc(LOGINS, labels(&[("result", result)]));
+}
+
+// endregion: --- Record
+
+// region:    --- Render
+
+/// Renders the recorded metrics, then the pool ones (if any).
+pub fn render(pool_stats: Option<&PoolStats>) -> String {
+    let mut out = String::new();
+
+    {
+        let registry = registry();
+        for (name, help) in COUNTERS {
+            write_header(&mut out, name, help, "counter");
+            for (labels, value) in registry.counters.get(name).into_iter().flatten() {
+                write_sample(&mut out, name, labels, *value as f64);
+            }
+        }
+
+        for (name, help) in HISTOGRAMS {
+            write_header(&mut out, name, help, "histogram");
+            for (labels, histogram) in registry.histograms.get(name).into_iter().flatten() {
+                write_histogram(&mut out, name, labels, histogram);
+            }
+        }
+    }
+
+    if let Some(pool_stats) = pool_stats {
+        write_pool_stats(&mut out, pool_stats);
+    }
+
+    out
+}
+
+fn write_pool_stats(out: &mut String, pool_stats: &PoolStats) {
+    let PoolStats {
+        max,
+        in_use,
+        idle,
+        acquire_wait,
+    } = pool_stats;
+
+    write_header(
+        out,
+        "db_pool_connections",
+        "Connections of the db pool, by state.",
+        "gauge",
+    );
+    write_sample(
+        out,
+        "db_pool_connections",
+        &labels(&[("state", "in_use")]),
+        *in_use as f64,
+    );
+    write_sample(
+        out,
+        "db_pool_connections",
+        &labels(&[("state", "idle")]),
+        *idle as f64,
+    );
+
+    write_header(
+        out,
+        "db_pool_max_connections",
+        "Maximum connections of the db pool.",
+        "gauge",
+    );
+    write_sample(out, "db_pool_max_connections", "", *max as f64);
+
+    write_header(
+        out,
+        "db_pool_acquire_wait_seconds",
+        "Time to acquire a connection of the db pool, at the scrape (NaN on timeout).",
+        "gauge",
+    );
+    let acquire_wait = acquire_wait.map_or(f64::NAN, |wait| wait.as_secs_f64());
+    write_sample(out, "db_pool_acquire_wait_seconds", "", acquire_wait);
+}
+
+fn write_header(out: &mut String, name: &str, help: &str, kind: &str) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {kind}");
+}
+
+fn write_sample(out: &mut String, name: &str, labels: &str, value: f64) {
+    if labels.is_empty() {
+        let _ = writeln!(out, "{name} {value}");
+    } else {
+        let _ = writeln!(out, "{name}{{{labels}}} {value}");
+    }
+}
+
+fn write_histogram(out: &mut String, name: &str, labels: &str, histogram: &Histogram) {
+    let sep = if labels.is_empty() { "" } else { "," };
+    let mut cumulative = 0;
+    for (le, count) in LATENCY_BUCKETS.iter().zip(&histogram.buckets) {
+        cumulative += count;
+        let _ = writeln!(
+            out,
+            "{name}_bucket{{{labels}{sep}le=\"{le}\"}} {cumulative}"
+        );
+    }
+    let _ = writeln!(
+        out,
+        "{name}_bucket{{{labels}{sep}le=\"+Inf\"}} {}",
+        histogram.count
+    );
+    write_sample(out, &format!("{name}_sum"), labels, histogram.sum);
+    write_sample(
+        out,
+        &format!("{name}_count"),
+        labels,
+        histogram.count as f64,
+    );
+}
+
+fn labels(pairs: &[(&str, &str)]) -> Labels {
+    pairs
+        .iter()
+        .map(|(name, value)| format!("{name}=\"{}\"", escape_label_value(value)))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+// endregion: --- Render
+
+// region:    --- Tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn test_render_histogram_ok() -> Result<()> {
+        // -- Setup & Fixtures
+        let fx_route = "/test_render_histogram_ok";
+
+        // -- Exec
+        record_http("GET", fx_route, 200, Duration::from_millis(20));
+        record_http("GET", fx_route, 200, Duration::from_secs(20));
+        record_http("GET", fx_route, 500, Duration::from_millis(1));
+        let out = render(None);
+
+        // -- Check
+        let labels = format!("method=\"GET\",route=\"{fx_route}\"");
+        assert!(out.contains(&format!(
+            "http_requests_total{{{labels},status=\"200\"}} 2\n"
+        )));
+        assert!(out.contains(&format!(
+            "http_requests_total{{{labels},status=\"500\"}} 1\n"
+        )));
+        // cumulative buckets
+        assert!(out.contains(&format!(
+            "http_request_duration_seconds_bucket{{{labels},le=\"0.005\"}} 1\n"
+        )));
+        assert!(out.contains(&format!(
+            "http_request_duration_seconds_bucket{{{labels},le=\"0.025\"}} 2\n"
+        )));
+        assert!(out.contains(&format!(
+            "http_request_duration_seconds_bucket{{{labels},le=\"10\"}} 2\n"
+        )));
+        assert!(out.contains(&format!(
+            "http_request_duration_seconds_bucket{{{labels},le=\"+Inf\"}} 3\n"
+        )));
+        assert!(out.contains(&format!(
+            "http_request_duration_seconds_count{{{labels}}} 3\n"
+        )));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_labels_escape_ok() -> Result<()> {
+        // -- Exec
+        let labels = labels(&[("method", "a\"b\\c\nd")]);
+
+        // -- Check
+        assert_eq!(labels, r#"method="a\"b\\c\nd""#);
+
+        Ok(())
+    }
+}
+// endregion: --- Tests
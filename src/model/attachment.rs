@@ -0,0 +1,526 @@
+//! Files attached to the tasks.
+//!
+//! - The content is in the blob store (see `blob_store`), under a generated `blob_key`.
+//! - The total size of the attachments of each user is limited by `ATTACHMENT_QUOTA_BYTES`.
+//! - The blobs of the deleted attachments (also when deleted with their task) are recorded
+//!   in `blob_orphan` by the db, and deleted from the blob store by `purge_orphan_blobs`
+//!   (run by the scheduler).
+
+use crate::config;
+use crate::ctx::Ctx;
+use crate::model::base::{self, DbBmc};
+use crate::model::blob_store::BlobStream;
+use crate::model::task::TaskBmc;
+use crate::model::ModelManager;
+use crate::model::{Error, Result};
+use crate::utils::serialize_time;
+use bytes::Bytes;
+use futures::{Stream, StreamExt};
+use modql::field::{Fields, HasFields};
+use modql::filter::{FilterNodes, ListOptions, OpValsInt64, OpValsString};
+use sea_query::{PostgresQueryBuilder, Query};
+use sea_query_binder::SqlxBinder;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::FromRow;
+use std::ops::Range;
+use std::path::PathBuf;
+use time::OffsetDateTime;
+use tokio::fs::File;
+use tokio::io::AsyncWriteExt;
+use tracing::error;
+use uuid::Uuid;
+
+/// Number of orphan blobs deleted by each `purge_orphan_blobs`.
+const PURGE_BATCH_SIZE: i64 = 100;
+/// A failed orphan blob is retried after 1, 2, 4, ... minutes, up to 2^10 (about 17 hours).
+const PURGE_BACKOFF_MAX_SHIFT: i32 = 10;
+
+/// Size of the attachments of the owner `$1`.
+const USED_BYTES_SQL: &str =
+    "SELECT COALESCE(SUM(size), 0)::BIGINT FROM attachment WHERE owner_id = $1";
+
+#[derive(Debug, Clone, Fields, FromRow, Serialize)]
+pub struct Attachment {
+    pub id: i64,
+    pub task_id: i64,
+    /// User who uploaded it.
+    pub owner_id: i64,
+    /// File name
+    pub name: String,
+    pub content_type: String,
+    /// In bytes
+    pub size: i64,
+    /// Hex digest of the content.
+    pub sha256: String,
+    #[serde(skip)]
+    blob_key: String,
+    #[serde(serialize_with = "serialize_time")]
+    pub ctime: OffsetDateTime,
+}
+
+pub struct AttachmentForCreate {
+    pub task_id: i64,
+    pub name: String,
+    pub content_type: String,
+}
+
+/// `AttachmentForCreate` with what is known once the content is stored,
+/// in the workspace of the task.
+#[derive(Fields)]
+struct AttachmentForInsert {
+    workspace_id: i64,
+    task_id: i64,
+    owner_id: i64,
+    name: String,
+    content_type: String,
+    size: i64,
+    sha256: String,
+    blob_key: String,
+}
+
+#[derive(FilterNodes, Deserialize, Default, Debug)]
+pub struct AttachmentFilter {
+    id: Option<OpValsInt64>,
+
+    task_id: Option<OpValsInt64>,
+    owner_id: Option<OpValsInt64>,
+    name: Option<OpValsString>,
+    content_type: Option<OpValsString>,
+}
+
+/// Size of the attachments of a user, and the maximum.
+#[derive(Debug, Serialize)]
+pub struct AttachmentUsage {
+    pub used: i64,
+    pub quota: i64,
+}
+
+pub struct AttachmentBmc;
+
+impl DbBmc for AttachmentBmc {
+    const TABLE: &'static str = "attachment";
+
+    fn has_workspace() -> bool {
+        true
+    }
+}
+
+impl AttachmentBmc {
+    /// Stores the content, streamed, and attaches it to the task, owned by the user of the ctx.
+    /// Fails with `AttachmentQuotaExceeded` as soon as the content goes over the quota
+    /// of the user.
+    pub async fn create<S>(
+        ctx: &Ctx,
+        mm: &ModelManager,
+        attachment_c: AttachmentForCreate,
+        content: S,
+    ) -> Result<Attachment>
+    where
+        S: Stream<Item = std::io::Result<Bytes>> + Send,
+    {
+        let quota = config().ATTACHMENT_QUOTA_BYTES;
+        Self::create_within(ctx, mm, attachment_c, content, quota).await
+    }
+
+    async fn create_within<S>(
+        ctx: &Ctx,
+        mm: &ModelManager,
+        attachment_c: AttachmentForCreate,
+        content: S,
+        quota: i64,
+    ) -> Result<Attachment>
+    where
+        S: Stream<Item = std::io::Result<Bytes>> + Send,
+    {
+        // also for the EntityNotFound error
+        let task = TaskBmc::get(ctx, mm, attachment_c.task_id).await?;
+
+        // early check, the final one is done with the insert
+        let used = Self::used_bytes(mm, ctx.user_id()).await?;
+        let upload = TempUpload::new();
+        let (size, sha256) = upload
+            .write(content, quota - used)
+            .await
+            .map_err(|ex| match ex {
+                UploadError::OverMax => Error::AttachmentQuotaExceeded { quota, used },
+                UploadError::Io(ex) => Error::AttachmentFailUpload(ex.to_string()),
+            })?;
+
+        let blob_key = format!("attachments/{}", Uuid::new_v4());
+        mm.blob_store()
+            .put_file(&blob_key, &upload.path, size as u64, &sha256)
+            .await?;
+
+        let attachment_i = AttachmentForInsert {
+            workspace_id: task.workspace_id,
+            task_id: attachment_c.task_id,
+            owner_id: ctx.user_id(),
+            name: attachment_c.name,
+            content_type: attachment_c.content_type,
+            size,
+            sha256,
+            blob_key: blob_key.clone(),
+        };
+        let res = Self::insert_within(mm, attachment_i, quota).await;
+        if res.is_err() {
+            // best effort, the blob is not referenced
+            let _ = mm.blob_store().delete(&blob_key).await;
+        }
+
+        res
+    }
+
+    /// Inserts the attachment if the quota of its owner allows it.
+    async fn insert_within(
+        mm: &ModelManager,
+        attachment_i: AttachmentForInsert,
+        quota: i64,
+    ) -> Result<Attachment> {
+        let (owner_id, size) = (attachment_i.owner_id, attachment_i.size);
+
+        // serializes the inserts of the user, so that concurrent uploads can't
+        // go over the quota together
+        let mut tx = mm.db().begin().await?;
+        sqlx::query("SELECT pg_advisory_xact_lock(hashtextextended('attachment_quota:' || $1, 0))")
+            .bind(owner_id)
+            .execute(&mut *tx)
+            .await?;
+        let used: i64 = sqlx::query_scalar(USED_BYTES_SQL)
+            .bind(owner_id)
+            .fetch_one(&mut *tx)
+            .await?;
+        if used + size > quota {
+            tx.rollback().await?;
+            return Err(Error::AttachmentQuotaExceeded { quota, used });
+        }
+
+        let (columns, sea_values) = attachment_i.not_none_fields().for_sea_insert();
+        let mut query = Query::insert();
+        query
+            .into_table(Self::table_ref())
+            .columns(columns)
+            .values(sea_values)?
+            .returning(Query::returning().columns(Attachment::field_column_refs()));
+
+        let (sql, values) = query.build_sqlx(PostgresQueryBuilder);
+        let attachment = sqlx::query_as_with::<_, Attachment, _>(&sql, values)
+            .fetch_one(&mut *tx)
+            .await?;
+        tx.commit().await?;
+
+        Ok(attachment)
+    }
+
+    /// Fails with `EntityNotFound` if the task of the attachment is not accessible.
+    pub async fn get(ctx: &Ctx, mm: &ModelManager, id: i64) -> Result<Attachment> {
+        let attachment: Attachment = base::get::<Self, _>(ctx, mm, id).await?;
+        TaskBmc::get(ctx, mm, attachment.task_id).await?;
+
+        Ok(attachment)
+    }
+
+    /// The attachment with its content, or only the bytes in `range` (not empty).
+    pub async fn open(
+        ctx: &Ctx,
+        mm: &ModelManager,
+        id: i64,
+        range: Option<Range<u64>>,
+    ) -> Result<(Attachment, BlobStream)> {
+        let attachment = Self::get(ctx, mm, id).await?;
+        let content = mm.blob_store().get(&attachment.blob_key, range).await?;
+
+        Ok((attachment, content))
+    }
+
+    pub async fn list(
+        ctx: &Ctx,
+        mm: &ModelManager,
+        filters: Option<Vec<AttachmentFilter>>,
+        list_options: Option<ListOptions>,
+    ) -> Result<Vec<Attachment>> {
+        base::list::<Self, _, _>(ctx, mm, filters, list_options).await
+    }
+
+    /// The blob is deleted later, see `purge_orphan_blobs`.
+    pub async fn delete(ctx: &Ctx, mm: &ModelManager, id: i64) -> Result<()> {
+        base::delete::<Self>(ctx, mm, id).await
+    }
+
+    pub async fn usage(ctx: &Ctx, mm: &ModelManager) -> Result<AttachmentUsage> {
+        Ok(AttachmentUsage {
+            used: Self::used_bytes(mm, ctx.user_id()).await?,
+            quota: config().ATTACHMENT_QUOTA_BYTES,
+        })
+    }
+
+    async fn used_bytes(mm: &ModelManager, owner_id: i64) -> Result<i64> {
+        let used = sqlx::query_scalar(USED_BYTES_SQL)
+            .bind(owner_id)
+            .fetch_one(mm.db())
+            .await?;
+
+        Ok(used)
+    }
+
+    /// Deletes a batch of the blobs of the deleted attachments from the blob store.
+    /// A blob failing to delete is logged and retried later, with an exponential backoff,
+    /// so that it does not hold back the others.
+    /// Returns the number deleted.
+    pub async fn purge_orphan_blobs(mm: &ModelManager) -> Result<usize> {
+        let blob_keys: Vec<String> = sqlx::query_scalar(
+            "SELECT blob_key FROM blob_orphan WHERE next_attempt_at <= now()
+             ORDER BY next_attempt_at, blob_key LIMIT $1",
+        )
+        .bind(PURGE_BATCH_SIZE)
+        .fetch_all(mm.db())
+        .await?;
+
+        let mut purged = 0;
+        for blob_key in &blob_keys {
+            if let Err(ex) = mm.blob_store().delete(blob_key).await {
+                error!(
+                    "{:<12} - orphan blob '{blob_key}' delete fail: {ex}",
+                    "ATTACHMENT"
+                );
+                sqlx::query(
+                    "UPDATE blob_orphan SET attempts = attempts + 1,
+                       next_attempt_at = now() + make_interval(mins => 1 << LEAST(attempts, $2))
+                     WHERE blob_key = $1",
+                )
+                .bind(blob_key)
+                .bind(PURGE_BACKOFF_MAX_SHIFT)
+                .execute(mm.db())
+                .await?;
+                continue;
+            }
+            sqlx::query("DELETE FROM blob_orphan WHERE blob_key = $1")
+                .bind(blob_key)
+                .execute(mm.db())
+                .await?;
+            purged += 1;
+        }
+
+        Ok(purged)
+    }
+}
+
+// region:    --- Temp Upload
+
+enum UploadError {
+    OverMax,
+    Io(std::io::Error),
+}
+
+/// Temporary file of the uploaded content, removed on drop.
+struct TempUpload {
+    path: PathBuf,
+}
+
+impl TempUpload {
+    fn new() -> Self {
+        Self {
+            path: std::env::temp_dir().join(format!("upload-{}", Uuid::new_v4())),
+        }
+    }
+
+    /// Writes the content, returns its size and sha256 (hex).
+    async fn write<S>(
+        &self,
+        content: S,
+        max: i64,
+    ) -> core::result::Result<(i64, String), UploadError>
+    where
+        S: Stream<Item = std::io::Result<Bytes>> + Send,
+    {
+        let mut content = std::pin::pin!(content);
+        let mut file = File::create(&self.path).await.map_err(UploadError::Io)?;
+        let mut hasher = Sha256::new();
+        let mut size = 0;
+
+        while let Some(chunk) = content.next().await {
+            let chunk = chunk.map_err(UploadError::Io)?;
+            size += chunk.len() as i64;
+            if size > max {
+                return Err(UploadError::OverMax);
+            }
+            hasher.update(&chunk);
+            file.write_all(&chunk).await.map_err(UploadError::Io)?;
+        }
+        file.flush().await.map_err(UploadError::Io)?;
+
+        Ok((size, hex::encode(hasher.finalize())))
+    }
+}
+
+impl Drop for TempUpload {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+// endregion: --- Temp Upload
+
+// region:    --- Tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::_dev_utils;
+    use crate::model::blob_store::read_to_string;
+    use anyhow::Result;
+    use serial_test::serial;
+
+    fn fx_content(chunks: &[&'static str]) -> impl Stream<Item = std::io::Result<Bytes>> {
+        let chunks: Vec<_> = chunks
+            .iter()
+            .map(|chunk| Ok(Bytes::from_static(chunk.as_bytes())))
+            .collect();
+        futures::stream::iter(chunks)
+    }
+
+    #[serial]
+    #[tokio::test]
+    async fn test_create_open_and_delete_ok() -> Result<()> {
+        // -- Setup & Fixtures
+        let mm = _dev_utils::init_test().await;
+        let ctx = _dev_utils::demo_ctx();
+        let fx_task = _dev_utils::seed_tasks(&ctx, &mm, &["test_create_open_and_delete_ok"])
+            .await?
+            .remove(0);
+        let fx_attachment_c = AttachmentForCreate {
+            task_id: fx_task.id,
+            name: "notes.txt".to_string(),
+            content_type: "text/plain".to_string(),
+        };
+
+        // -- Exec
+        let attachment =
+            AttachmentBmc::create(&ctx, &mm, fx_attachment_c, fx_content(&["hello ", "world"]))
+                .await?;
+        let (_, all) = AttachmentBmc::open(&ctx, &mm, attachment.id, None).await?;
+        let (_, part) = AttachmentBmc::open(&ctx, &mm, attachment.id, Some(6..11)).await?;
+
+        // -- Check
+        assert_eq!(attachment.size, 11);
+        assert_eq!(
+            attachment.sha256,
+            hex::encode(Sha256::digest("hello world"))
+        );
+        assert_eq!(read_to_string(all).await?, "hello world");
+        assert_eq!(read_to_string(part).await?, "world");
+        assert_eq!(AttachmentBmc::usage(&ctx, &mm).await?.used, 11);
+
+        // -- Clean
+        // deleted with the task, then its blob by the purge
+        TaskBmc::delete(&ctx, &mm, fx_task.id).await?;
+        assert_eq!(AttachmentBmc::purge_orphan_blobs(&mm).await?, 1);
+        let res = mm.blob_store().get(&attachment.blob_key, None).await;
+        assert!(
+            matches!(
+                res,
+                Err(crate::model::blob_store::Error::BlobNotFound { .. })
+            ),
+            "blob should be purged"
+        );
+
+        Ok(())
+    }
+
+    #[serial]
+    #[tokio::test]
+    async fn test_create_err_quota_exceeded() -> Result<()> {
+        // -- Setup & Fixtures
+        let mm = _dev_utils::init_test().await;
+        let ctx = _dev_utils::demo_ctx();
+        let fx_task = _dev_utils::seed_tasks(&ctx, &mm, &["test_create_err_quota_exceeded"])
+            .await?
+            .remove(0);
+        let fx_quota = 10;
+        let fx_attachment_c = |name: &str| AttachmentForCreate {
+            task_id: fx_task.id,
+            name: name.to_string(),
+            content_type: "text/plain".to_string(),
+        };
+        AttachmentBmc::create_within(
+            &ctx,
+            &mm,
+            fx_attachment_c("01.txt"),
+            fx_content(&["123456"]),
+            fx_quota,
+        )
+        .await?;
+
+        // -- Exec
+        let res = AttachmentBmc::create_within(
+            &ctx,
+            &mm,
+            fx_attachment_c("02.txt"),
+            fx_content(&["123", "456"]),
+            fx_quota,
+        )
+        .await;
+
+        // -- Check
+        assert!(
+            matches!(
+                res,
+                Err(Error::AttachmentQuotaExceeded { quota: 10, used: 6 })
+            ),
+            "AttachmentQuotaExceeded not matching"
+        );
+        let attachments = AttachmentBmc::list(&ctx, &mm, None, None).await?;
+        assert_eq!(attachments.len(), 1);
+
+        // -- Clean
+        TaskBmc::delete(&ctx, &mm, fx_task.id).await?;
+        AttachmentBmc::purge_orphan_blobs(&mm).await?;
+
+        Ok(())
+    }
+
+    #[serial]
+    #[tokio::test]
+    async fn test_purge_orphan_blobs_failing_backoff_ok() -> Result<()> {
+        // -- Setup & Fixtures
+        let mm = _dev_utils::init_test().await;
+        // more failing keys (invalid for the store) than a batch, before a deletable one
+        sqlx::query(
+            "INSERT INTO blob_orphan (blob_key, ctime, next_attempt_at)
+             SELECT '../test_purge_failing-' || n, now() - interval '1 hour',
+                    now() - interval '1 hour'
+             FROM generate_series(1, $1) n",
+        )
+        .bind(PURGE_BATCH_SIZE + 1)
+        .execute(mm.db())
+        .await?;
+        let fx_key = "attachments/test_purge_orphan_blobs_failing_backoff_ok";
+        sqlx::query("INSERT INTO blob_orphan (blob_key) VALUES ($1)")
+            .bind(fx_key)
+            .execute(mm.db())
+            .await?;
+
+        // -- Exec
+        let purged_first = AttachmentBmc::purge_orphan_blobs(&mm).await?;
+        let purged_second = AttachmentBmc::purge_orphan_blobs(&mm).await?;
+
+        // -- Check
+        assert_eq!(purged_first, 0);
+        assert_eq!(purged_second, 1);
+        let (remaining, attempts): (i64, Option<i64>) = sqlx::query_as(
+            "SELECT count(*), min(attempts)::BIGINT FROM blob_orphan
+             WHERE blob_key LIKE '../test_purge_failing-%'",
+        )
+        .fetch_one(mm.db())
+        .await?;
+        assert_eq!(remaining, PURGE_BATCH_SIZE + 1);
+        assert_eq!(attempts, Some(1));
+
+        // -- Clean
+        sqlx::query("DELETE FROM blob_orphan WHERE blob_key LIKE '../test_purge_failing-%'")
+            .execute(mm.db())
+            .await?;
+
+        Ok(())
+    }
+}
+// endregion: --- Tests
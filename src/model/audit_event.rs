@@ -0,0 +1,323 @@
+//! Security audit log
+//!
+//! - The authentication and authorization events (logins, logoffs, token failures,
+//!   password changes, permission denials) are appended to the `audit_event` table,
+//!   with the origin of the ctx (request id, client ip, user agent, see `Ctx::origin`).
+//! - The table is append-only (an update fails, see the schema), the events are only
+//!   deleted by `AuditEventBmc::prune`, after `AUDIT_RETENTION_DAYS` (see `scheduler`).
+//! - Only the admins (and the root ctx) can list them.
+
+use crate::ctx::Ctx;
+use crate::model::base::{self, DbBmc};
+use crate::model::modql_utils::time_to_sea_value;
+use crate::model::user::UserBmc;
+use crate::model::{Error, ModelManager, Result};
+use crate::utils::serialize_time;
+use modql::field::Fields;
+use modql::filter::{FilterNodes, ListOptions, OpValsInt64, OpValsString, OpValsValue};
+use sea_query::{Expr, Iden, PostgresQueryBuilder, Query};
+use sea_query_binder::SqlxBinder;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sqlx::{FromRow, PgConnection};
+use time::OffsetDateTime;
+use tracing::error;
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type, strum_macros::AsRefStr,
+)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "varchar", rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum AuditKind {
+    LoginSuccess,
+    /// `detail.reason` is the login error (e.g., `LoginFailPwdNotMatching`).
+    LoginFail,
+    Logoff,
+    /// The auth token was rejected (`detail.reason`), see `web::mw_ctx_resolve`.
+    TokenFail,
+    PwdChange,
+    /// `detail.action` is what was denied.
+    PermissionDenied,
+}
+
+impl From<AuditKind> for sea_query::Value {
+    fn from(val: AuditKind) -> Self {
+        val.as_ref().into()
+    }
+}
+
+#[derive(Debug, Clone, Fields, FromRow, Serialize)]
+pub struct AuditEvent {
+    pub id: i64,
+    pub kind: AuditKind,
+    /// User the event is about (e.g., the one logging in), if known.
+    pub user_id: Option<i64>,
+    pub client_ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub request_id: Option<String>,
+    pub detail: Value,
+    #[serde(serialize_with = "serialize_time")]
+    pub ctime: OffsetDateTime,
+}
+
+/// The origin (client ip, user agent, request id) is the one of the ctx.
+pub struct AuditEventForCreate {
+    pub kind: AuditKind,
+    pub user_id: Option<i64>,
+    pub detail: Value,
+}
+
+#[derive(FilterNodes, Deserialize, Default, Debug)]
+pub struct AuditEventFilter {
+    id: Option<OpValsInt64>,
+
+    kind: Option<OpValsString>,
+    user_id: Option<OpValsInt64>,
+    client_ip: Option<OpValsString>,
+    request_id: Option<OpValsString>,
+
+    #[modql(to_sea_value_fn = "time_to_sea_value")]
+    ctime: Option<OpValsValue>,
+}
+
+#[derive(Iden)]
+enum AuditEventIden {
+    Kind,
+    UserId,
+    ClientIp,
+    UserAgent,
+    RequestId,
+    Detail,
+    Ctime,
+}
+
+pub struct AuditEventBmc;
+
+impl DbBmc for AuditEventBmc {
+    const TABLE: &'static str = "audit_event";
+}
+
+impl AuditEventBmc {
+    /// Appends the event, with the origin of the ctx.
+    pub async fn record(ctx: &Ctx, mm: &ModelManager, event_c: AuditEventForCreate) -> Result<()> {
+        let mut con = mm.db().acquire().await?;
+        Self::record_in(ctx, &mut con, event_c).await
+    }
+
+    /// Same as `record`, in the transaction of `con` (e.g., of the audited write).
+    pub async fn record_in(
+        ctx: &Ctx,
+        con: &mut PgConnection,
+        event_c: AuditEventForCreate,
+    ) -> Result<()> {
+        let origin = ctx.origin();
+
+        let mut query = Query::insert();
+        query
+            .into_table(Self::table_ref())
+            .columns([
+                AuditEventIden::Kind,
+                AuditEventIden::UserId,
+                AuditEventIden::ClientIp,
+                AuditEventIden::UserAgent,
+                AuditEventIden::RequestId,
+                AuditEventIden::Detail,
+            ])
+            .values([
+                event_c.kind.into(),
+                event_c.user_id.into(),
+                origin
+                    .and_then(|o| o.client_ip.map(|ip| ip.to_string()))
+                    .into(),
+                origin.and_then(|o| o.user_agent.clone()).into(),
+                origin.and_then(|o| o.request_id.clone()).into(),
+                event_c.detail.into(),
+            ])?;
+
+        let (sql, values) = query.build_sqlx(PostgresQueryBuilder);
+        sqlx::query_with(&sql, values).execute(con).await?;
+
+        Ok(())
+    }
+
+    /// Records the `PermissionDenied` of the `action` for the user of the ctx.
+    /// A record failure is only logged, the caller returns its own error anyway.
+    pub async fn record_denied(ctx: &Ctx, mm: &ModelManager, action: &str, mut detail: Value) {
+        if let Some(detail) = detail.as_object_mut() {
+            detail.insert("action".to_string(), action.into());
+        }
+
+        let res = Self::record(
+            ctx,
+            mm,
+            AuditEventForCreate {
+                kind: AuditKind::PermissionDenied,
+                user_id: Some(ctx.user_id()),
+                detail,
+            },
+        )
+        .await;
+        if let Err(ex) = res {
+            error!("{:<12} - audit event record fail: {ex}", "AUDIT");
+        }
+    }
+
+    /// Paginated with the list options, oldest first by default
+    /// (e.g., `"order_bys": "!id"` for the latest first).
+    /// Fails with `AdminRequired` (and records it) for a non admin user.
+    pub async fn list(
+        ctx: &Ctx,
+        mm: &ModelManager,
+        filters: Option<Vec<AuditEventFilter>>,
+        list_options: Option<ListOptions>,
+    ) -> Result<Vec<AuditEvent>> {
+        if !ctx.is_root() && !UserBmc::is_admin(ctx, mm, ctx.user_id()).await? {
+            Self::record_denied(ctx, mm, "list_audit_events", json!({})).await;
+            return Err(Error::AdminRequired {
+                user_id: ctx.user_id(),
+            });
+        }
+
+        base::list::<Self, _, _>(ctx, mm, filters, list_options).await
+    }
+
+    /// Deletes the events created before `before`, returns how many.
+    pub async fn prune(mm: &ModelManager, before: OffsetDateTime) -> Result<u64> {
+        let mut query = Query::delete();
+        query
+            .from_table(Self::table_ref())
+            .and_where(Expr::col(AuditEventIden::Ctime).lt(before));
+
+        let (sql, values) = query.build_sqlx(PostgresQueryBuilder);
+        let count = sqlx::query_with(&sql, values)
+            .execute(mm.db())
+            .await?
+            .rows_affected();
+
+        Ok(count)
+    }
+}
+
+// region:    --- Tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::_dev_utils;
+    use crate::ctx::CtxOrigin;
+    use crate::utils::now_utc;
+    use anyhow::Result;
+    use modql::filter::OpValString;
+    use serial_test::serial;
+    use std::sync::Arc;
+
+    #[serial]
+    #[tokio::test]
+    async fn test_record_and_list_ok() -> Result<()> {
+        // -- Setup & Fixtures
+        let mm = _dev_utils::init_test().await;
+        let fx_request_id = format!("test-audit-{}", uuid::Uuid::new_v4());
+        let fx_origin = CtxOrigin {
+            request_id: Some(fx_request_id.clone()),
+            client_ip: Some("10.0.0.1".parse()?),
+            user_agent: Some("test-agent".to_string()),
+        };
+        let ctx = Ctx::new(1000, None)?.with_origin(Arc::new(fx_origin));
+
+        // -- Exec
+        AuditEventBmc::record(
+            &ctx,
+            &mm,
+            AuditEventForCreate {
+                kind: AuditKind::LoginSuccess,
+                user_id: Some(1000),
+                detail: json!({}),
+            },
+        )
+        .await?;
+        let filter: AuditEventFilter = serde_json::from_value(json!({
+            "request_id": fx_request_id,
+            "kind": "login_success",
+        }))?;
+        let events = AuditEventBmc::list(&Ctx::root_ctx(), &mm, Some(vec![filter]), None).await?;
+
+        // -- Check
+        assert_eq!(events.len(), 1);
+        let event = &events[0];
+        assert_eq!(event.kind, AuditKind::LoginSuccess);
+        assert_eq!(event.user_id, Some(1000));
+        assert_eq!(event.client_ip.as_deref(), Some("10.0.0.1"));
+        assert_eq!(event.user_agent.as_deref(), Some("test-agent"));
+
+        // the table is append-only
+        let res = sqlx::query("UPDATE audit_event SET kind = 'logoff' WHERE id = $1")
+            .bind(event.id)
+            .execute(mm.db())
+            .await;
+        assert!(res.is_err(), "update should fail");
+
+        Ok(())
+    }
+
+    #[serial]
+    #[tokio::test]
+    async fn test_list_err_admin_required() -> Result<()> {
+        // -- Setup & Fixtures
+        let mm = _dev_utils::init_test().await;
+        let fx_request_id = format!("test-audit-{}", uuid::Uuid::new_v4());
+        let fx_origin = CtxOrigin {
+            request_id: Some(fx_request_id.clone()),
+            ..Default::default()
+        };
+        // demo1, not an admin
+        let ctx = Ctx::new(1000, None)?.with_origin(Arc::new(fx_origin));
+
+        // -- Exec
+        let res = AuditEventBmc::list(&ctx, &mm, None, None).await;
+
+        // -- Check
+        assert!(
+            matches!(res, Err(Error::AdminRequired { user_id: 1000 })),
+            "AdminRequired not matching"
+        );
+        let filter = AuditEventFilter {
+            request_id: Some(OpValString::Eq(fx_request_id).into()),
+            ..Default::default()
+        };
+        let events = AuditEventBmc::list(&Ctx::root_ctx(), &mm, Some(vec![filter]), None).await?;
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].kind, AuditKind::PermissionDenied);
+        assert_eq!(events[0].detail["action"], "list_audit_events");
+
+        Ok(())
+    }
+
+    #[serial]
+    #[tokio::test]
+    async fn test_prune_ok() -> Result<()> {
+        // -- Setup & Fixtures
+        let mm = _dev_utils::init_test().await;
+        let ctx = Ctx::root_ctx();
+        AuditEventBmc::record(
+            &ctx,
+            &mm,
+            AuditEventForCreate {
+                kind: AuditKind::Logoff,
+                user_id: None,
+                detail: json!({}),
+            },
+        )
+        .await?;
+
+        // -- Exec
+        let pruned = AuditEventBmc::prune(&mm, now_utc() + time::Duration::seconds(1)).await?;
+
+        // -- Check
+        assert!(pruned >= 1, "pruned {pruned}");
+        let events = AuditEventBmc::list(&ctx, &mm, None, None).await?;
+        assert!(events.is_empty(), "events left {}", events.len());
+
+        Ok(())
+    }
+}
+// endregion: --- Tests
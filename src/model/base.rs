@@ -1,20 +1,34 @@
 use crate::ctx::Ctx;
+use crate::model::change_event::{record_change_events, ChangeEventForCreate, ChangeOp};
+use crate::model::task_history::{
+    diff_created, diff_deleted, diff_updated, TaskHistoryBmc, TaskHistoryForCreate,
+};
 use crate::model::ModelManager;
 use crate::model::{Error, Result};
-use modql::field::HasFields;
+use modql::field::{Field, Fields, HasFields};
 use modql::filter::{FilterGroups, ListOptions};
 use modql::SIden;
-use sea_query::{Condition, Expr, Iden, IntoIden, PostgresQueryBuilder, Query, TableRef};
+use sea_query::{
+    Condition, DynIden, Expr, Iden, IntoIden, LockType, OnConflict, Order, PostgresQueryBuilder,
+    Query, SimpleExpr, TableRef,
+};
 use sea_query_binder::SqlxBinder;
+use serde_json::Value;
 use sqlx::postgres::PgRow;
-use sqlx::FromRow;
+use sqlx::{FromRow, PgConnection, Row};
+use std::collections::HashMap;
+use std::future::Future;
+use tracing::{info_span, Instrument, Span};
 
 const LIST_LIMIT_DEFAULT: i64 = 300;
 const LIST_LIMIT_MAX: i64 = 1000;
+const BULK_CREATE_MAX: usize = 1000;
 
 #[derive(Iden)]
 pub enum CommonIden {
     Id,
+    Version,
+    WorkspaceId,
 }
 
 pub trait DbBmc {
@@ -23,6 +37,49 @@ pub trait DbBmc {
     fn table_ref() -> TableRef {
         TableRef::Table(SIden(Self::TABLE).into_iden())
     }
+
+    /// Specifies that the table for this Bmc has a `version` column,
+    /// incremented on every update and used for optimistic concurrency.
+    fn has_version() -> bool {
+        false
+    }
+
+    /// Specifies that the rows of this Bmc belong to a workspace (`workspace_id` column).
+    /// The base functions then only read and write the rows of the workspace of the ctx,
+    /// and the inserts get it (see `workspace_cond`).
+    fn has_workspace() -> bool {
+        false
+    }
+
+    /// Specifies that the creates/updates/deletes of this Bmc are recorded
+    /// in the `task_history` table.
+    fn has_history() -> bool {
+        false
+    }
+
+    /// Specifies that the creates/updates/deletes of this Bmc are notified
+    /// as `ChangeEvent` (see `ModelManager::subscribe_changes`).
+    fn has_change_events() -> bool {
+        false
+    }
+
+    /// Columns left out of the row json of the history and the change events
+    /// (e.g., generated columns).
+    fn row_json_excluded() -> &'static [&'static str] {
+        &[]
+    }
+
+    /// Checks run in the transaction of the updates (and of the upserts of an existing
+    /// row), before the write, with the ids of the rows to update (locked) and the
+    /// fields to set. An error aborts the update (e.g., the rules on the task `done`).
+    fn check_update(
+        _ctx: &Ctx,
+        _con: &mut PgConnection,
+        _ids: &[i64],
+        _fields: &[Field],
+    ) -> impl Future<Output = Result<()>> + Send {
+        async { Ok(()) }
+    }
 }
 
 pub fn finalize_list_options(list_options: Option<ListOptions>) -> Result<ListOptions> {
@@ -53,14 +110,81 @@ pub fn finalize_list_options(list_options: Option<ListOptions>) -> Result<ListOp
     }
 }
 
-pub async fn create<MC, E>(_ctx: &Ctx, mm: &ModelManager, data: E) -> Result<i64>
+pub async fn create<MC, E>(ctx: &Ctx, mm: &ModelManager, data: E) -> Result<i64>
+where
+    MC: DbBmc,
+    E: HasFields,
+{
+    let row = exec_create::<MC, E>(ctx, mm, data, Vec::new()).await?;
+    let id = row.try_get(0)?;
+
+    Ok(id)
+}
+
+/// Same as `create`, but returns the created entity, read in the same statement.
+pub async fn create_returning<MC, E, R>(ctx: &Ctx, mm: &ModelManager, data: E) -> Result<R>
+where
+    MC: DbBmc,
+    E: HasFields,
+    R: for<'r> FromRow<'r, PgRow> + Unpin + Send,
+    R: HasFields,
+{
+    let returning = returning_exprs::<R>();
+    let row = exec_create::<MC, E>(ctx, mm, data, returning).await?;
+    let entity = R::from_row(&row)?;
+
+    Ok(entity)
+}
+
+/// Same as `create_returning`, in the transaction of `con`
+/// (e.g., with the other writes of the same operation).
+pub async fn create_returning_in<MC, E, R>(ctx: &Ctx, con: &mut PgConnection, data: E) -> Result<R>
+where
+    MC: DbBmc,
+    E: HasFields,
+    R: for<'r> FromRow<'r, PgRow> + Unpin + Send,
+    R: HasFields,
+{
+    let returning = returning_exprs::<R>();
+    let row = exec_create_in::<MC, E>(ctx, con, data, returning).await?;
+    let entity = R::from_row(&row)?;
+
+    Ok(entity)
+}
+
+/// Inserts the entity and returns the row of `RETURNING id, <row json>, <returning...>`.
+async fn exec_create<MC, E>(
+    ctx: &Ctx,
+    mm: &ModelManager,
+    data: E,
+    returning: Vec<SimpleExpr>,
+) -> Result<PgRow>
+where
+    MC: DbBmc,
+    E: HasFields,
+{
+    // within a transaction so that the history is written with the change
+    let mut tx = mm.db().begin().await?;
+    let row = exec_create_in::<MC, E>(ctx, &mut tx, data, returning).await?;
+    tx.commit().await?;
+
+    Ok(row)
+}
+
+async fn exec_create_in<MC, E>(
+    ctx: &Ctx,
+    con: &mut PgConnection,
+    data: E,
+    returning: Vec<SimpleExpr>,
+) -> Result<PgRow>
 where
     MC: DbBmc,
     E: HasFields,
 {
-    let db = mm.db();
     // Extract fields
     let fields = data.not_none_fields();
+    let field_names = field_names(&fields);
+    let fields = with_workspace::<MC>(ctx, fields)?;
 
     let (columns, sea_values) = fields.for_sea_insert();
 
@@ -70,18 +194,36 @@ where
         .into_table(MC::table_ref())
         .columns(columns)
         .values(sea_values)?
-        .returning(Query::returning().columns([CommonIden::Id]));
+        .returning(
+            Query::returning().exprs(
+                [Expr::col(CommonIden::Id).into(), row_json::<MC>()]
+                    .into_iter()
+                    .chain(returning),
+            ),
+        );
 
     // Execute query with sqlx
     let (sql, values) = query.build_sqlx(PostgresQueryBuilder);
-    let (id,) = sqlx::query_as_with::<_, (i64,), _>(&sql, values)
-        .fetch_one(db)
+    let row = sqlx::query_with(&sql, values)
+        .fetch_one(&mut *con)
+        .instrument(sql_span::<MC>(ctx, &sql))
         .await?;
+    let id: i64 = row.try_get(0)?;
+    let new_row: Value = row.try_get(1)?;
 
-    Ok(id)
+    let diff = MC::has_history().then(|| diff_created(&field_names, &new_row));
+    let change = RowChange {
+        id,
+        op: ChangeOp::Create,
+        diff,
+        row: new_row,
+    };
+    record_changes::<MC>(ctx, con, vec![change]).await?;
+
+    Ok(row)
 }
 
-pub async fn get<MC, E>(_ctx: &Ctx, mm: &ModelManager, id: i64) -> Result<E>
+pub async fn get<MC, E>(ctx: &Ctx, mm: &ModelManager, id: i64) -> Result<E>
 where
     MC: DbBmc,
     // for FromRow we need a lifetime
@@ -122,11 +264,15 @@ where
         .from(MC::table_ref())
         .columns(E::field_column_refs())
         .and_where(Expr::col(CommonIden::Id).eq(id));
+    if let Some(workspace_cond) = workspace_cond::<MC>(ctx)? {
+        query.and_where(workspace_cond);
+    }
 
     let (sql, values) = query.build_sqlx(PostgresQueryBuilder);
 
     let entity = sqlx::query_as_with::<_, E, _>(&sql, values)
         .fetch_optional(db)
+        .instrument(sql_span::<MC>(ctx, &sql))
         .await?
         .ok_or(Error::EntityNotFound {
             entity: MC::TABLE,
@@ -137,7 +283,7 @@ where
 }
 
 pub async fn list<MC, E, F>(
-    _ctx: &Ctx,
+    ctx: &Ctx,
     mm: &ModelManager,
     filter: Option<F>,
     list_options: Option<ListOptions>,
@@ -155,11 +301,16 @@ where
     query.from(MC::table_ref()).columns(E::field_column_refs());
 
     // Filter conditions
+    let mut cond = Condition::all();
     if let Some(filter) = filter {
         let filters: FilterGroups = filter.into();
-        let cond: Condition = filters.try_into()?;
-        query.cond_where(cond);
+        let filter_cond: Condition = filters.try_into()?;
+        cond = cond.add(filter_cond);
+    }
+    if let Some(workspace_cond) = workspace_cond::<MC>(ctx)? {
+        cond = cond.add(workspace_cond);
     }
+    query.cond_where(cond);
 
     // list options
     let list_options = finalize_list_options(list_options)?;
@@ -170,12 +321,61 @@ where
 
     let entities = sqlx::query_as_with::<_, E, _>(&sql, values)
         .fetch_all(db)
+        .instrument(sql_span::<MC>(ctx, &sql))
         .await?;
 
     Ok(entities)
 }
 
-pub async fn update<MC, E>(_ctx: &Ctx, mm: &ModelManager, id: i64, data: E) -> Result<()>
+/// Updates the entity with the not none fields of `data`.
+/// When the table has a version column and `version` is given, the update only
+/// happens if it still matches the one in db (optimistic concurrency).
+pub async fn update<MC, E>(
+    ctx: &Ctx,
+    mm: &ModelManager,
+    id: i64,
+    version: Option<i64>,
+    data: E,
+) -> Result<()>
+where
+    MC: DbBmc,
+    E: HasFields,
+{
+    exec_update::<MC, E>(ctx, mm, id, version, data, Vec::new()).await?;
+
+    Ok(())
+}
+
+/// Same as `update`, but returns the updated entity, read in the same statement.
+pub async fn update_returning<MC, E, R>(
+    ctx: &Ctx,
+    mm: &ModelManager,
+    id: i64,
+    version: Option<i64>,
+    data: E,
+) -> Result<R>
+where
+    MC: DbBmc,
+    E: HasFields,
+    R: for<'r> FromRow<'r, PgRow> + Unpin + Send,
+    R: HasFields,
+{
+    let returning = returning_exprs::<R>();
+    let row = exec_update::<MC, E>(ctx, mm, id, version, data, returning).await?;
+    let entity = R::from_row(&row)?;
+
+    Ok(entity)
+}
+
+/// Updates the entity and returns the row of `RETURNING <row json>, <returning...>`.
+async fn exec_update<MC, E>(
+    ctx: &Ctx,
+    mm: &ModelManager,
+    id: i64,
+    version: Option<i64>,
+    data: E,
+    returning: Vec<SimpleExpr>,
+) -> Result<PgRow>
 where
     MC: DbBmc,
     E: HasFields,
@@ -183,34 +383,234 @@ where
     let db = mm.db();
     // -- prep data
     let fields = data.not_none_fields();
-    let fields = fields.for_sea_update();
+    let field_names = field_names(&fields);
+    let fields = fields.into_vec();
 
     // -- build query
     let mut query = Query::update();
     query
         .table(MC::table_ref())
-        .values(fields)
-        .and_where(Expr::col(CommonIden::Id).eq(id));
+        .values(Fields::new(fields.clone()).for_sea_update())
+        .and_where(Expr::col(CommonIden::Id).eq(id))
+        .returning(Query::returning().exprs([row_json::<MC>()].into_iter().chain(returning)));
+    let workspace_cond = workspace_cond::<MC>(ctx)?;
+    if let Some(workspace_cond) = workspace_cond.clone() {
+        query.and_where(workspace_cond);
+    }
+
+    if MC::has_version() {
+        query.value(CommonIden::Version, Expr::col(CommonIden::Version).add(1));
+        if let Some(version) = version {
+            query.and_where(Expr::col(CommonIden::Version).eq(version));
+        }
+    }
 
     // -- exec query
+    let mut tx = db.begin().await?;
+    // the row before the update is only needed for the history
+    let old_row = if MC::has_history() {
+        let cond = Expr::col(CommonIden::Id).eq(id);
+        select_row_json_for_update::<MC>(ctx, &mut tx, cond, workspace_cond).await?
+    } else {
+        None
+    };
+    MC::check_update(ctx, &mut tx, &[id], &fields).await?;
+
     let (sql, values) = query.build_sqlx(PostgresQueryBuilder);
-    let count = sqlx::query_with(&sql, values)
-        .execute(db)
-        .await?
-        .rows_affected();
+    let row = sqlx::query_with(&sql, values)
+        .fetch_optional(&mut *tx)
+        .instrument(sql_span::<MC>(ctx, &sql))
+        .await?;
 
     // -- check result
-    if count == 0 {
-        Err(Error::EntityNotFound {
+    let Some(row) = row else {
+        // release the connection before looking for the cause
+        tx.rollback().await?;
+        return Err(update_fail_error::<MC>(ctx, mm, id, version).await?);
+    };
+    let new_row: Value = row.try_get(0)?;
+
+    let diff = old_row.map(|old_row| diff_updated(&field_names, &old_row, &new_row));
+    let change = RowChange {
+        id,
+        op: ChangeOp::Update,
+        diff,
+        row: new_row,
+    };
+    record_changes::<MC>(ctx, &mut tx, vec![change]).await?;
+    tx.commit().await?;
+
+    Ok(row)
+}
+
+/// Inserts the entity, or when a row with the same `key` (a unique column) exists,
+/// updates it with the not none fields of `data` (`ON CONFLICT .. DO UPDATE`).
+/// Returns the entity, with `true` when it was inserted.
+/// The upserts of the same key are serialized (advisory lock), so that the update of an
+/// existing row always goes through `DbBmc::check_update`, with its history diff.
+pub async fn upsert<MC, E, R>(
+    ctx: &Ctx,
+    mm: &ModelManager,
+    key: impl IntoIden,
+    data: E,
+) -> Result<(R, bool)>
+where
+    MC: DbBmc,
+    E: HasFields,
+    R: for<'r> FromRow<'r, PgRow> + Unpin + Send,
+    R: HasFields,
+{
+    let db = mm.db();
+    // -- prep data
+    let key = key.into_iden();
+    let fields = data.not_none_fields();
+    let field_names = field_names(&fields);
+    let key_value = fields
+        .clone()
+        .into_iter()
+        .find(|field| field.iden.to_string() == key.to_string())
+        .map(|field| field.value)
+        .ok_or_else(|| Error::UpsertKeyMissing {
             entity: MC::TABLE,
-            id,
-        })
+            key: key.to_string(),
+        })?;
+    let update_fields = fields.clone().into_vec();
+    let fields = with_workspace::<MC>(ctx, fields)?;
+    let (columns, sea_values) = fields.for_sea_insert();
+
+    // -- build query
+    // the key is unique in the workspace
+    let mut on_conflict = if MC::has_workspace() {
+        OnConflict::columns([CommonIden::WorkspaceId.into_iden(), key.clone()])
     } else {
-        Ok(())
+        OnConflict::column(key.clone())
+    };
+    on_conflict.update_columns(columns.clone());
+    if MC::has_version() {
+        let version = Expr::col((SIden(MC::TABLE), CommonIden::Version)).add(1);
+        on_conflict.value(CommonIden::Version, version);
+    }
+
+    // `xmax` is only 0 for a row which was just inserted
+    let inserted = Expr::cust("(xmax = 0)");
+    let mut query = Query::insert();
+    query
+        .into_table(MC::table_ref())
+        .columns(columns)
+        .values(sea_values)?
+        .on_conflict(on_conflict)
+        .returning(
+            Query::returning().exprs(
+                [Expr::col(CommonIden::Id).into(), row_json::<MC>(), inserted]
+                    .into_iter()
+                    .chain(returning_exprs::<R>()),
+            ),
+        );
+
+    // -- exec query
+    let mut tx = db.begin().await?;
+    // serializes the upserts of the key, so that a concurrent insert of a new key is
+    // seen below (as the existing row) instead of being updated by the `ON CONFLICT`
+    let lock_prefix = format!(
+        "upsert:{}:{}:",
+        MC::TABLE,
+        ctx.workspace_id()
+            .filter(|_| MC::has_workspace())
+            .unwrap_or_default()
+    );
+    let mut query_lock = Query::select();
+    query_lock.expr(Expr::cust_with_exprs(
+        "pg_advisory_xact_lock(hashtextextended($1 || ($2)::text, 0))",
+        [Expr::val(lock_prefix).into(), key_value.clone()],
+    ));
+    let (sql, values) = query_lock.build_sqlx(PostgresQueryBuilder);
+    sqlx::query_with(&sql, values).execute(&mut *tx).await?;
+
+    // the existing row, if any, for the update checks and the history
+    let cond = Expr::col(key).eq(key_value);
+    let old_row =
+        select_row_json_for_update::<MC>(ctx, &mut tx, cond, workspace_cond::<MC>(ctx)?).await?;
+    if let Some(old_id) = old_row.as_ref().and_then(|row| row["id"].as_i64()) {
+        MC::check_update(ctx, &mut tx, &[old_id], &update_fields).await?;
+    }
+
+    let (sql, values) = query.build_sqlx(PostgresQueryBuilder);
+    let row = sqlx::query_with(&sql, values)
+        .fetch_one(&mut *tx)
+        .instrument(sql_span::<MC>(ctx, &sql))
+        .await?;
+    let id: i64 = row.try_get(0)?;
+    let new_row: Value = row.try_get(1)?;
+    let inserted: bool = row.try_get(2)?;
+
+    let (op, diff) = if inserted {
+        let diff = MC::has_history().then(|| diff_created(&field_names, &new_row));
+        (ChangeOp::Create, diff)
+    } else {
+        let old_row = old_row.unwrap_or_default();
+        let diff = MC::has_history().then(|| diff_updated(&field_names, &old_row, &new_row));
+        (ChangeOp::Update, diff)
+    };
+    let change = RowChange {
+        id,
+        op,
+        diff,
+        row: new_row,
+    };
+    record_changes::<MC>(ctx, &mut tx, vec![change]).await?;
+    tx.commit().await?;
+
+    let entity = R::from_row(&row)?;
+
+    Ok((entity, inserted))
+}
+
+/// Finds out why an update did not affect any row: either the entity does not
+/// exist, or its version is not the expected one anymore.
+async fn update_fail_error<MC>(
+    ctx: &Ctx,
+    mm: &ModelManager,
+    id: i64,
+    version: Option<i64>,
+) -> Result<Error>
+where
+    MC: DbBmc,
+{
+    let not_found = Error::EntityNotFound {
+        entity: MC::TABLE,
+        id,
+    };
+
+    if !MC::has_version() || version.is_none() {
+        return Ok(not_found);
+    }
+
+    let mut query = Query::select();
+    query
+        .from(MC::table_ref())
+        .column(CommonIden::Version)
+        .and_where(Expr::col(CommonIden::Id).eq(id));
+    if let Some(workspace_cond) = workspace_cond::<MC>(ctx)? {
+        query.and_where(workspace_cond);
     }
+
+    let (sql, values) = query.build_sqlx(PostgresQueryBuilder);
+    let current = sqlx::query_as_with::<_, (i64,), _>(&sql, values)
+        .fetch_optional(mm.db())
+        .instrument(sql_span::<MC>(ctx, &sql))
+        .await?;
+
+    Ok(match current {
+        Some((current_version,)) => Error::EntityVersionConflict {
+            entity: MC::TABLE,
+            id,
+            current_version,
+        },
+        None => not_found,
+    })
 }
 
-pub async fn delete<MC>(_ctx: &Ctx, mm: &ModelManager, id: i64) -> Result<()>
+pub async fn delete<MC>(ctx: &Ctx, mm: &ModelManager, id: i64) -> Result<()>
 where
     MC: DbBmc,
 {
@@ -218,20 +618,578 @@ where
     let mut query = Query::delete();
     query
         .from_table(MC::table_ref())
-        .and_where(Expr::col(CommonIden::Id).eq(id));
+        .and_where(Expr::col(CommonIden::Id).eq(id))
+        .returning(Query::returning().expr(row_json::<MC>()));
+    if let Some(workspace_cond) = workspace_cond::<MC>(ctx)? {
+        query.and_where(workspace_cond);
+    }
 
+    let mut tx = db.begin().await?;
     let (sql, values) = query.build_sqlx(PostgresQueryBuilder);
-    let count = sqlx::query_with(&sql, values)
-        .execute(db)
-        .await?
-        .rows_affected();
+    let old_row = sqlx::query_as_with::<_, (Value,), _>(&sql, values)
+        .fetch_optional(&mut *tx)
+        .instrument(sql_span::<MC>(ctx, &sql))
+        .await?;
 
-    if count == 0 {
-        Err(Error::EntityNotFound {
+    let Some((old_row,)) = old_row else {
+        tx.rollback().await?;
+        return Err(Error::EntityNotFound {
             entity: MC::TABLE,
             id,
+        });
+    };
+
+    let diff = MC::has_history().then(|| diff_deleted(&old_row));
+    let change = RowChange {
+        id,
+        op: ChangeOp::Delete,
+        diff,
+        row: old_row,
+    };
+    record_changes::<MC>(ctx, &mut tx, vec![change]).await?;
+    tx.commit().await?;
+
+    Ok(())
+}
+
+// region:    --- Bulk
+
+/// Creates all the entities with a single multi-row insert, in one transaction.
+/// Returns the ids in the order of `data`.
+/// The fields which are none for some of the entities get the column default.
+pub async fn create_many<MC, E>(ctx: &Ctx, mm: &ModelManager, data: Vec<E>) -> Result<Vec<i64>>
+where
+    MC: DbBmc,
+    E: HasFields,
+{
+    let rows = exec_create_many::<MC, E>(ctx, mm, data, Vec::new()).await?;
+    let ids = rows
+        .iter()
+        .map(|row| row.try_get(0))
+        .collect::<core::result::Result<_, _>>()?;
+
+    Ok(ids)
+}
+
+/// Same as `create_many`, but returns the created entities, read in the same statement.
+pub async fn create_many_returning<MC, E, R>(
+    ctx: &Ctx,
+    mm: &ModelManager,
+    data: Vec<E>,
+) -> Result<Vec<R>>
+where
+    MC: DbBmc,
+    E: HasFields,
+    R: for<'r> FromRow<'r, PgRow> + Unpin + Send,
+    R: HasFields,
+{
+    let returning = returning_exprs::<R>();
+    let rows = exec_create_many::<MC, E>(ctx, mm, data, returning).await?;
+    let entities = rows
+        .iter()
+        .map(R::from_row)
+        .collect::<core::result::Result<_, _>>()?;
+
+    Ok(entities)
+}
+
+/// Inserts the entities and returns the rows of `RETURNING id, <row json>, <returning...>`,
+/// in the order of `data`.
+async fn exec_create_many<MC, E>(
+    ctx: &Ctx,
+    mm: &ModelManager,
+    data: Vec<E>,
+    returning: Vec<SimpleExpr>,
+) -> Result<Vec<PgRow>>
+where
+    MC: DbBmc,
+    E: HasFields,
+{
+    if data.len() > BULK_CREATE_MAX {
+        return Err(Error::BulkCreateOverMax {
+            max: BULK_CREATE_MAX,
+            actual: data.len(),
+        });
+    }
+    if data.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let db = mm.db();
+    // -- prep data
+    let mut rows: Vec<Vec<Field>> = Vec::with_capacity(data.len());
+    let mut rows_field_names: Vec<Vec<String>> = Vec::with_capacity(data.len());
+    for entity in data {
+        let fields = entity.not_none_fields();
+        rows_field_names.push(field_names(&fields));
+        rows.push(with_workspace::<MC>(ctx, fields)?.into_vec());
+    }
+
+    // all the columns set by at least one entity, in order of appearance
+    let mut columns: Vec<DynIden> = Vec::new();
+    for field in rows.iter().flatten() {
+        if !columns
+            .iter()
+            .any(|c| c.to_string() == field.iden.to_string())
+        {
+            columns.push(field.iden.clone());
+        }
+    }
+
+    // -- build query
+    let mut query = Query::insert();
+    query.into_table(MC::table_ref()).columns(columns.clone());
+
+    for row in rows {
+        let mut values = Vec::with_capacity(columns.len());
+        for column in columns.iter() {
+            let value = row
+                .iter()
+                .find(|field| field.iden.to_string() == column.to_string())
+                .map(|field| field.value.clone())
+                .unwrap_or_else(|| Expr::cust("DEFAULT"));
+            values.push(value);
+        }
+        query.values(values)?;
+    }
+    query.returning(
+        Query::returning().exprs(
+            [Expr::col(CommonIden::Id).into(), row_json::<MC>()]
+                .into_iter()
+                .chain(returning),
+        ),
+    );
+
+    // -- exec query
+    // postgres returns the inserted rows in the order of the values
+    let mut tx = db.begin().await?;
+    let (sql, values) = query.build_sqlx(PostgresQueryBuilder);
+    let created = sqlx::query_with(&sql, values)
+        .fetch_all(&mut *tx)
+        .instrument(sql_span::<MC>(ctx, &sql))
+        .await?;
+
+    let mut changes = Vec::with_capacity(created.len());
+    for (row, field_names) in created.iter().zip(rows_field_names) {
+        let new_row: Value = row.try_get(1)?;
+        changes.push(RowChange {
+            id: row.try_get(0)?,
+            op: ChangeOp::Create,
+            diff: MC::has_history().then(|| diff_created(&field_names, &new_row)),
+            row: new_row,
+        });
+    }
+    record_changes::<MC>(ctx, &mut tx, changes).await?;
+    tx.commit().await?;
+
+    Ok(created)
+}
+
+/// Updates all the entities matching `filter` with the not none fields of `data`,
+/// with a single update, in one transaction. Returns the updated ids, ascending.
+pub async fn update_many<MC, E, F>(
+    ctx: &Ctx,
+    mm: &ModelManager,
+    filter: F,
+    data: E,
+) -> Result<Vec<i64>>
+where
+    MC: DbBmc,
+    E: HasFields,
+    F: Into<FilterGroups>,
+{
+    let rows = exec_update_many::<MC, E, F>(ctx, mm, filter, data, Vec::new()).await?;
+    let ids = rows
+        .iter()
+        .map(|row| row.try_get(0))
+        .collect::<core::result::Result<_, _>>()?;
+
+    Ok(ids)
+}
+
+/// Same as `update_many`, but returns the updated entities (by ascending id),
+/// read in the same statement.
+pub async fn update_many_returning<MC, E, F, R>(
+    ctx: &Ctx,
+    mm: &ModelManager,
+    filter: F,
+    data: E,
+) -> Result<Vec<R>>
+where
+    MC: DbBmc,
+    E: HasFields,
+    F: Into<FilterGroups>,
+    R: for<'r> FromRow<'r, PgRow> + Unpin + Send,
+    R: HasFields,
+{
+    let returning = returning_exprs::<R>();
+    let rows = exec_update_many::<MC, E, F>(ctx, mm, filter, data, returning).await?;
+    let entities = rows
+        .iter()
+        .map(R::from_row)
+        .collect::<core::result::Result<_, _>>()?;
+
+    Ok(entities)
+}
+
+/// Updates the entities and returns the rows of `RETURNING id, <row json>, <returning...>`,
+/// by ascending id.
+async fn exec_update_many<MC, E, F>(
+    ctx: &Ctx,
+    mm: &ModelManager,
+    filter: F,
+    data: E,
+    returning: Vec<SimpleExpr>,
+) -> Result<Vec<PgRow>>
+where
+    MC: DbBmc,
+    E: HasFields,
+    F: Into<FilterGroups>,
+{
+    let db = mm.db();
+    // -- prep data
+    let cond = filter_cond::<MC, F>(ctx, filter)?;
+    let fields = data.not_none_fields();
+    let field_names = field_names(&fields);
+    let fields = fields.into_vec();
+
+    // -- lock the matching rows
+    // so that the update applies to exactly those, and to keep them for the history
+    let mut tx = db.begin().await?;
+    let mut query = Query::select();
+    query
+        .from(MC::table_ref())
+        .column(CommonIden::Id)
+        .expr(row_json::<MC>())
+        .cond_where(cond)
+        .order_by(CommonIden::Id, Order::Asc)
+        .lock(LockType::Update);
+
+    let (sql, values) = query.build_sqlx(PostgresQueryBuilder);
+    let old_rows = sqlx::query_as_with::<_, (i64, Value), _>(&sql, values)
+        .fetch_all(&mut *tx)
+        .instrument(sql_span::<MC>(ctx, &sql))
+        .await?;
+
+    if old_rows.is_empty() {
+        tx.rollback().await?;
+        return Ok(Vec::new());
+    }
+    let ids: Vec<i64> = old_rows.iter().map(|(id, _)| *id).collect();
+    let mut old_rows: HashMap<i64, Value> = old_rows.into_iter().collect();
+    MC::check_update(ctx, &mut tx, &ids, &fields).await?;
+
+    // -- update them
+    let mut query = Query::update();
+    query
+        .table(MC::table_ref())
+        .values(Fields::new(fields).for_sea_update())
+        .and_where(Expr::col(CommonIden::Id).is_in(ids))
+        .returning(
+            Query::returning().exprs(
+                [Expr::col(CommonIden::Id).into(), row_json::<MC>()]
+                    .into_iter()
+                    .chain(returning),
+            ),
+        );
+
+    if MC::has_version() {
+        query.value(CommonIden::Version, Expr::col(CommonIden::Version).add(1));
+    }
+
+    let (sql, values) = query.build_sqlx(PostgresQueryBuilder);
+    let mut new_rows = sqlx::query_with(&sql, values)
+        .fetch_all(&mut *tx)
+        .instrument(sql_span::<MC>(ctx, &sql))
+        .await?
+        .into_iter()
+        .map(|row| Ok((row.try_get::<i64, _>(0)?, row)))
+        .collect::<Result<Vec<_>>>()?;
+    new_rows.sort_by_key(|(id, _)| *id);
+
+    let mut changes = Vec::with_capacity(new_rows.len());
+    for (id, row) in new_rows.iter() {
+        let new_row: Value = row.try_get(1)?;
+        let old_row = old_rows.remove(id).unwrap_or_default();
+        changes.push(RowChange {
+            id: *id,
+            op: ChangeOp::Update,
+            diff: MC::has_history().then(|| diff_updated(&field_names, &old_row, &new_row)),
+            row: new_row,
+        });
+    }
+    record_changes::<MC>(ctx, &mut tx, changes).await?;
+    tx.commit().await?;
+
+    Ok(new_rows.into_iter().map(|(_, row)| row).collect())
+}
+
+/// Deletes all the entities matching `filter` with a single delete, in one transaction.
+/// Returns the deleted ids, ascending.
+pub async fn delete_many<MC, F>(ctx: &Ctx, mm: &ModelManager, filter: F) -> Result<Vec<i64>>
+where
+    MC: DbBmc,
+    F: Into<FilterGroups>,
+{
+    let db = mm.db();
+    let cond = filter_cond::<MC, F>(ctx, filter)?;
+
+    let mut query = Query::delete();
+    query
+        .from_table(MC::table_ref())
+        .cond_where(cond)
+        .returning(Query::returning().exprs([Expr::col(CommonIden::Id).into(), row_json::<MC>()]));
+
+    let mut tx = db.begin().await?;
+    let (sql, values) = query.build_sqlx(PostgresQueryBuilder);
+    let mut old_rows = sqlx::query_as_with::<_, (i64, Value), _>(&sql, values)
+        .fetch_all(&mut *tx)
+        .instrument(sql_span::<MC>(ctx, &sql))
+        .await?;
+    old_rows.sort_by_key(|(id, _)| *id);
+
+    let ids: Vec<i64> = old_rows.iter().map(|(id, _)| *id).collect();
+    let changes = old_rows
+        .into_iter()
+        .map(|(id, old_row)| RowChange {
+            id,
+            op: ChangeOp::Delete,
+            diff: MC::has_history().then(|| diff_deleted(&old_row)),
+            row: old_row,
         })
+        .collect();
+    record_changes::<MC>(ctx, &mut tx, changes).await?;
+    tx.commit().await?;
+
+    Ok(ids)
+}
+
+/// The condition of the filter, restricted to the workspace of the ctx.
+/// Fails with `BulkFilterEmpty` when there is no filter group, or one without condition
+/// (e.g., `{}`), which would match all the rows.
+fn filter_cond<MC, F>(ctx: &Ctx, filter: F) -> Result<Condition>
+where
+    MC: DbBmc,
+    F: Into<FilterGroups>,
+{
+    let empty_err = Error::BulkFilterEmpty { entity: MC::TABLE };
+    let groups = filter.into().into_vec();
+    if groups.is_empty() {
+        return Err(empty_err);
+    }
+
+    let mut filter_cond = Condition::any();
+    for group in groups {
+        let group_cond = Condition::try_from(group)?;
+        if group_cond.is_empty() {
+            return Err(empty_err);
+        }
+        filter_cond = filter_cond.add(group_cond);
+    }
+    let mut cond = Condition::all().add(filter_cond);
+    if let Some(workspace_cond) = workspace_cond::<MC>(ctx)? {
+        cond = cond.add(workspace_cond);
+    }
+
+    Ok(cond)
+}
+
+// endregion: --- Bulk
+
+// region:    --- Workspace
+
+/// The condition restricting the rows to the workspace of the ctx, for the Bmcs
+/// with a workspace. None when there is no restriction (Bmc without workspace,
+/// or root ctx without workspace).
+/// To be added by the Bmcs to their custom queries too.
+/// Fails with `CtxNoWorkspace` for a user ctx without workspace.
+pub fn workspace_cond<MC>(ctx: &Ctx) -> Result<Option<SimpleExpr>>
+where
+    MC: DbBmc,
+{
+    if !MC::has_workspace() {
+        return Ok(None);
+    }
+
+    match ctx.workspace_id() {
+        Some(workspace_id) => Ok(Some(
+            Expr::col((SIden(MC::TABLE), CommonIden::WorkspaceId)).eq(workspace_id),
+        )),
+        None if ctx.is_root() => Ok(None),
+        None => Err(Error::CtxNoWorkspace {
+            user_id: ctx.user_id(),
+        }),
+    }
+}
+
+/// The fields to insert with the `workspace_id` of the ctx, for the Bmcs with a workspace.
+/// A root ctx without workspace keeps the one of the fields, if any
+/// (e.g., the one of the parent entity).
+fn with_workspace<MC>(ctx: &Ctx, fields: Fields) -> Result<Fields>
+where
+    MC: DbBmc,
+{
+    if !MC::has_workspace() {
+        return Ok(fields);
+    }
+    let Some(workspace_id) = ctx.workspace_id() else {
+        return match ctx.is_root() {
+            true => Ok(fields),
+            false => Err(Error::CtxNoWorkspace {
+                user_id: ctx.user_id(),
+            }),
+        };
+    };
+
+    let workspace_iden = CommonIden::WorkspaceId.to_string();
+    let mut fields: Vec<Field> = fields
+        .into_iter()
+        .filter(|field| field.iden.to_string() != workspace_iden)
+        .collect();
+    fields.push(Field::new(CommonIden::WorkspaceId, workspace_id.into()));
+
+    Ok(Fields::new(fields))
+}
+
+// endregion: --- Workspace
+
+// region:    --- Change Recording
+
+/// A created, updated or deleted row, see `record_changes`.
+struct RowChange {
+    id: i64,
+    op: ChangeOp,
+    /// For the history, when the Bmc has one.
+    diff: Option<Value>,
+    /// The row after the change (before for a delete).
+    row: Value,
+}
+
+/// Records the changes in the history (those with a diff) and the change events,
+/// within the transaction of the changes, with a statement for each (whatever the
+/// number of changes).
+async fn record_changes<MC>(
+    ctx: &Ctx,
+    con: &mut PgConnection,
+    changes: Vec<RowChange>,
+) -> Result<()>
+where
+    MC: DbBmc,
+{
+    let mut revisions = Vec::new();
+    let mut events_c = Vec::new();
+    for change in changes {
+        let workspace_id = change.row.get("workspace_id").and_then(Value::as_i64);
+        if let Some(diff) = change.diff {
+            revisions.push(TaskHistoryForCreate {
+                entity_id: change.id,
+                workspace_id,
+                op: change.op,
+                diff,
+            });
+        }
+        if MC::has_change_events() {
+            events_c.push(ChangeEventForCreate {
+                op: change.op,
+                entity_id: change.id,
+                workspace_id,
+                data: change.row,
+            });
+        }
+    }
+
+    TaskHistoryBmc::record(ctx, con, MC::TABLE, revisions).await?;
+    record_change_events(con, MC::TABLE, events_c).await?;
+
+    Ok(())
+}
+
+// endregion: --- Change Recording
+
+// region:    --- Row Json
+
+/// The whole row as a json object (i.e., `to_jsonb("task")`), used for the history
+/// and the change events.
+fn row_json<MC>() -> SimpleExpr
+where
+    MC: DbBmc,
+{
+    let excluded = MC::row_json_excluded();
+    if excluded.is_empty() {
+        Expr::cust(format!("to_jsonb(\"{}\")", MC::TABLE))
     } else {
-        Ok(())
+        Expr::cust(format!(
+            "to_jsonb(\"{}\") - '{{{}}}'::text[]",
+            MC::TABLE,
+            excluded.join(",")
+        ))
     }
 }
+
+async fn select_row_json_for_update<MC>(
+    ctx: &Ctx,
+    con: &mut PgConnection,
+    cond: SimpleExpr,
+    workspace_cond: Option<SimpleExpr>,
+) -> Result<Option<Value>>
+where
+    MC: DbBmc,
+{
+    let mut query = Query::select();
+    query
+        .from(MC::table_ref())
+        .expr(row_json::<MC>())
+        .and_where(cond)
+        .lock(LockType::Update);
+    if let Some(workspace_cond) = workspace_cond {
+        query.and_where(workspace_cond);
+    }
+
+    let (sql, values) = query.build_sqlx(PostgresQueryBuilder);
+    let row = sqlx::query_as_with::<_, (Value,), _>(&sql, values)
+        .fetch_optional(con)
+        .instrument(sql_span::<MC>(ctx, &sql))
+        .await?;
+
+    Ok(row.map(|(row,)| row))
+}
+
+/// Span of the statement, for the traces (see `telemetry`).
+fn sql_span<MC>(ctx: &Ctx, sql: &str) -> Span
+where
+    MC: DbBmc,
+{
+    let operation = sql.split_whitespace().next().unwrap_or_default();
+    info_span!(
+        "sql",
+        otel.name = %format!("{operation} {}", MC::TABLE),
+        otel.kind = "client",
+        db.system = "postgresql",
+        db.operation = operation,
+        db.sql.table = MC::TABLE,
+        db.statement = sql,
+        user_id = ctx.user_id(),
+        request_id = ctx.request_id(),
+    )
+}
+
+/// The columns of `R`, to be appended to a `RETURNING` clause.
+fn returning_exprs<R>() -> Vec<SimpleExpr>
+where
+    R: HasFields,
+{
+    R::field_column_refs()
+        .into_iter()
+        .map(SimpleExpr::Column)
+        .collect()
+}
+
+fn field_names(fields: &Fields) -> Vec<String> {
+    fields
+        .clone()
+        .into_iter()
+        .map(|field| field.iden.to_string())
+        .collect()
+}
+
+// endregion: --- Row Json
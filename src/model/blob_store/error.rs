@@ -0,0 +1,34 @@
+use serde::Serialize;
+
+pub type Result<T> = core::result::Result<T, Error>;
+
+#[derive(Debug, Serialize)]
+pub enum Error {
+    KeyInvalid(String),
+    BlobNotFound { key: String },
+    Io(String),
+    // -- S3
+    S3UrlInvalid(String),
+    S3RequestFail(String),
+    S3Fail { status: u16, body: String },
+}
+
+impl From<std::io::Error> for Error {
+    fn from(val: std::io::Error) -> Self {
+        Self::Io(val.to_string())
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(val: reqwest::Error) -> Self {
+        Self::S3RequestFail(val.to_string())
+    }
+}
+
+impl core::fmt::Display for Error {
+    fn fmt(&self, fmt: &mut core::fmt::Formatter) -> core::result::Result<(), core::fmt::Error> {
+        write!(fmt, "{self:?}")
+    }
+}
+
+impl std::error::Error for Error {}
@@ -0,0 +1,126 @@
+use super::{check_key, BlobStore, BlobStream, Error, Result};
+use async_trait::async_trait;
+use std::io::{ErrorKind, SeekFrom};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use tokio::fs::{self, File};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio_util::io::ReaderStream;
+use uuid::Uuid;
+
+/// Blobs as files under a root directory, at their key path.
+pub struct FsBlobStore {
+    root: PathBuf,
+}
+
+impl FsBlobStore {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    fn path_of(&self, key: &str) -> Result<PathBuf> {
+        check_key(key)?;
+        Ok(self.root.join(key))
+    }
+}
+
+#[async_trait]
+impl BlobStore for FsBlobStore {
+    async fn put_file(&self, key: &str, path: &Path, _size: u64, _sha256: &str) -> Result<()> {
+        let blob_path = self.path_of(key)?;
+        let dir = blob_path.parent().unwrap_or(&self.root);
+        fs::create_dir_all(dir).await?;
+
+        // copied next to the blob, then renamed, so that the blob is never partial
+        let tmp_path = dir.join(format!(".{}.tmp", Uuid::new_v4()));
+        let res = match fs::copy(path, &tmp_path).await {
+            Ok(_) => fs::rename(&tmp_path, &blob_path).await,
+            Err(ex) => Err(ex),
+        };
+        if res.is_err() {
+            let _ = fs::remove_file(&tmp_path).await;
+        }
+        res?;
+
+        Ok(())
+    }
+
+    async fn get(&self, key: &str, range: Option<Range<u64>>) -> Result<BlobStream> {
+        let mut file = File::open(self.path_of(key)?)
+            .await
+            .map_err(|ex| match ex.kind() {
+                ErrorKind::NotFound => Error::BlobNotFound {
+                    key: key.to_string(),
+                },
+                _ => ex.into(),
+            })?;
+
+        let stream: BlobStream = match range {
+            Some(range) => {
+                file.seek(SeekFrom::Start(range.start)).await?;
+                let len = range.end.saturating_sub(range.start);
+                Box::pin(ReaderStream::new(file.take(len)))
+            }
+            None => Box::pin(ReaderStream::new(file)),
+        };
+
+        Ok(stream)
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        match fs::remove_file(self.path_of(key)?).await {
+            Err(ex) if ex.kind() != ErrorKind::NotFound => Err(ex.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+// region:    --- Tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::blob_store::read_to_string;
+    use anyhow::Result;
+
+    #[tokio::test]
+    async fn test_fs_put_get_delete_ok() -> Result<()> {
+        // -- Setup & Fixtures
+        let fx_root = std::env::temp_dir().join(format!("blobs-{}", Uuid::new_v4()));
+        let fx_src = std::env::temp_dir().join(format!("blob-{}", Uuid::new_v4()));
+        let fx_key = "test/blob-01";
+        let fx_content = "hello blob store";
+        fs::write(&fx_src, fx_content).await?;
+        let store = FsBlobStore::new(&fx_root);
+
+        // -- Exec
+        store
+            .put_file(fx_key, &fx_src, fx_content.len() as u64, "")
+            .await?;
+        let all = read_to_string(store.get(fx_key, None).await?).await?;
+        let part = read_to_string(store.get(fx_key, Some(6..10)).await?).await?;
+        store.delete(fx_key).await?;
+        let res = store.get(fx_key, None).await;
+
+        // -- Check
+        assert_eq!(all, fx_content);
+        assert_eq!(part, "blob");
+        assert!(
+            matches!(res, Err(Error::BlobNotFound { .. })),
+            "BlobNotFound not matching"
+        );
+        assert!(
+            matches!(
+                store.get("../etc/passwd", None).await,
+                Err(Error::KeyInvalid(_))
+            ),
+            "KeyInvalid not matching"
+        );
+
+        // -- Clean
+        fs::remove_file(&fx_src).await?;
+        fs::remove_dir_all(&fx_root).await?;
+
+        Ok(())
+    }
+}
+// endregion: --- Tests
@@ -0,0 +1,80 @@
+//! Storage of the blobs (e.g., the content of the attachments), behind the `BlobStore` trait:
+//!
+//! - `FsBlobStore` keeps them in a local directory (the default).
+//! - `S3BlobStore` keeps them in a bucket of an S3 compatible service (AWS S3, MinIO, ...),
+//!   when `S3_BUCKET` is set (see `config`).
+//!
+//! The keys are generated by the model (e.g., `attachments/<uuid>`), see `check_key`.
+
+// region:    --- Modules
+
+mod error;
+mod fs;
+mod s3;
+
+pub use self::error::{Error, Result};
+pub use self::fs::FsBlobStore;
+pub use self::s3::{S3BlobStore, S3Config};
+
+use crate::config;
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::Stream;
+use std::ops::Range;
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::Arc;
+
+// endregion: --- Modules
+
+pub type BlobStream = Pin<Box<dyn Stream<Item = std::io::Result<Bytes>> + Send>>;
+
+#[async_trait]
+pub trait BlobStore: Send + Sync {
+    /// Stores the content of the local file under `key`, replacing the blob with the same key.
+    /// `sha256` is the hex digest of the content.
+    async fn put_file(&self, key: &str, path: &Path, size: u64, sha256: &str) -> Result<()>;
+
+    /// Content of the blob, or only its bytes in `range` (not empty).
+    async fn get(&self, key: &str, range: Option<Range<u64>>) -> Result<BlobStream>;
+
+    /// Does nothing if there is no blob with this key.
+    async fn delete(&self, key: &str) -> Result<()>;
+}
+
+/// The S3 store when configured, otherwise the local one.
+pub fn new_blob_store() -> Result<Arc<dyn BlobStore>> {
+    let store: Arc<dyn BlobStore> = match &config().S3 {
+        Some(s3_config) => Arc::new(S3BlobStore::new(s3_config.clone())?),
+        None => Arc::new(FsBlobStore::new(&config().BLOB_DIR)),
+    };
+
+    Ok(store)
+}
+
+/// Keys are relative paths of `[a-zA-Z0-9_.-]` segments (no `..`), valid for both stores.
+fn check_key(key: &str) -> Result<()> {
+    let valid = !key.is_empty()
+        && key.split('/').all(|segment| {
+            !segment.is_empty()
+                && !segment.starts_with('.')
+                && segment
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-'))
+        });
+
+    if valid {
+        Ok(())
+    } else {
+        Err(Error::KeyInvalid(key.to_string()))
+    }
+}
+
+/// Content of the blob as a string.
+#[cfg(test)]
+pub(in crate::model) async fn read_to_string(stream: BlobStream) -> anyhow::Result<String> {
+    use futures::TryStreamExt;
+
+    let chunks: Vec<Bytes> = stream.try_collect().await?;
+    Ok(String::from_utf8(chunks.concat())?)
+}
@@ -0,0 +1,631 @@
+//! Change events
+//!
+//! - The base create/update/delete write a `ChangeEvent` per changed row in the
+//!   `change_event` log table and `pg_notify` them (as json arrays), in the same
+//!   transaction as the change, so the events are only seen once the change is committed.
+//! - The log is bounded (last `EVENT_LOG_MAX` events, pruned by the `scheduler`), enough
+//!   for clients to resume after a short disconnection.
+//! - The `ModelManager` owns a `PgListener` background task forwarding the notifications
+//!   to a broadcast channel, so that the web layer can fan them out to clients.
+//! - Consumers must go through `ChangeEvent::for_ctx` which does the access check.
+
+use crate::config;
+use crate::ctx::Ctx;
+use crate::model::base::{self, DbBmc};
+use crate::model::task::Task;
+use crate::model::{Error, ModelManager, Result};
+use modql::field::{Fields, HasFields};
+use sea_query::{Expr, Iden, Order, PostgresQueryBuilder, Query};
+use sea_query_binder::SqlxBinder;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use sqlx::postgres::PgListener;
+use sqlx::{FromRow, PgConnection};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{broadcast, Mutex};
+use tokio::task::JoinHandle;
+use tracing::{debug, error};
+
+/// Postgres channel the changes are notified on.
+const CHANGE_CHANNEL: &str = "model_change";
+
+/// pg_notify fails above 8000 bytes. Above that, the events are sent in several
+/// notifications, and the data of a too big event is not sent (it is still in the log table).
+const PAYLOAD_MAX_BYTES: usize = 7900;
+
+const BROADCAST_CAPACITY: usize = 1024;
+
+/// Number of events kept in the `change_event` table.
+const EVENT_LOG_MAX: i64 = 10_000;
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type, strum_macros::AsRefStr,
+)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "varchar", rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum ChangeOp {
+    Create,
+    Update,
+    Delete,
+}
+
+impl From<ChangeOp> for sea_query::Value {
+    fn from(val: ChangeOp) -> Self {
+        val.as_ref().into()
+    }
+}
+
+#[derive(Debug, Clone, Fields, FromRow, Serialize, Deserialize)]
+pub struct ChangeEvent {
+    /// Id in the event log, increasing.
+    pub id: i64,
+    /// Table of the entity (e.g., "task").
+    pub entity: String,
+    pub op: ChangeOp,
+    pub entity_id: i64,
+    /// Workspace of the entity, if it has one.
+    pub workspace_id: Option<i64>,
+    /// The row after the change (before for a delete), `Null` in a notification
+    /// too big to be sent.
+    pub data: Value,
+}
+
+#[derive(Iden)]
+enum ChangeEventIden {
+    Id,
+    Entity,
+    Op,
+    EntityId,
+    WorkspaceId,
+    Data,
+}
+
+/// A change event to record, see `record_change_events`.
+pub(in crate::model) struct ChangeEventForCreate {
+    pub op: ChangeOp,
+    pub entity_id: i64,
+    pub workspace_id: Option<i64>,
+    pub data: Value,
+}
+
+/// Logs the change events with a single multi-row insert, and notifies them with a
+/// single statement, on the transaction connection.
+/// The notifications are arrays of events, each under `PAYLOAD_MAX_BYTES`.
+pub(in crate::model) async fn record_change_events(
+    con: &mut PgConnection,
+    entity: &'static str,
+    events_c: Vec<ChangeEventForCreate>,
+) -> Result<()> {
+    if events_c.is_empty() {
+        return Ok(());
+    }
+
+    // -- Log the events
+    let mut query = Query::insert();
+    query.into_table(ChangeEventBmc::table_ref()).columns([
+        ChangeEventIden::Entity,
+        ChangeEventIden::Op,
+        ChangeEventIden::EntityId,
+        ChangeEventIden::WorkspaceId,
+        ChangeEventIden::Data,
+    ]);
+    for event_c in events_c.iter() {
+        query.values([
+            entity.into(),
+            event_c.op.into(),
+            event_c.entity_id.into(),
+            event_c.workspace_id.into(),
+            event_c.data.clone().into(),
+        ])?;
+    }
+    query.returning(Query::returning().columns([ChangeEventIden::Id]));
+
+    // postgres returns the inserted rows in the order of the values
+    let (sql, values) = query.build_sqlx(PostgresQueryBuilder);
+    let ids = sqlx::query_scalar_with::<_, i64, _>(&sql, values)
+        .fetch_all(&mut *con)
+        .await?;
+
+    // -- Notify the events
+    let events = ids
+        .into_iter()
+        .zip(events_c)
+        .map(|(id, event_c)| ChangeEvent {
+            id,
+            entity: entity.to_string(),
+            op: event_c.op,
+            entity_id: event_c.entity_id,
+            workspace_id: event_c.workspace_id,
+            data: event_c.data,
+        });
+    let payloads = notify_payloads(events)?;
+
+    sqlx::query(
+        "SELECT pg_notify($1, payload)
+         FROM unnest($2::text[]) WITH ORDINALITY AS p(payload, ord) ORDER BY ord",
+    )
+    .bind(CHANGE_CHANNEL)
+    .bind(payloads)
+    .execute(con)
+    .await?;
+
+    Ok(())
+}
+
+/// The events as json arrays, each under `PAYLOAD_MAX_BYTES`.
+/// The data of an event too big on its own is not sent (it is still in the log table).
+fn notify_payloads(events: impl Iterator<Item = ChangeEvent>) -> Result<Vec<String>> {
+    let to_json = |event: &ChangeEvent| {
+        serde_json::to_string(event).map_err(|_| Error::ChangeEventFailSerialize)
+    };
+
+    let mut payloads = Vec::new();
+    let mut payload = String::new();
+    for mut event in events {
+        let mut json = to_json(&event)?;
+        // (the 2 bytes of the brackets)
+        if json.len() + 2 > PAYLOAD_MAX_BYTES {
+            event.data = Value::Null;
+            json = to_json(&event)?;
+        }
+
+        if !payload.is_empty() && payload.len() + json.len() + 2 > PAYLOAD_MAX_BYTES {
+            payloads.push(format!("[{payload}]"));
+            payload.clear();
+        }
+        if !payload.is_empty() {
+            payload.push(',');
+        }
+        payload.push_str(&json);
+    }
+    if !payload.is_empty() {
+        payloads.push(format!("[{payload}]"));
+    }
+
+    Ok(payloads)
+}
+
+// region:    --- ChangeEventBmc
+
+pub struct ChangeEventBmc;
+
+impl DbBmc for ChangeEventBmc {
+    const TABLE: &'static str = "change_event";
+
+    /// The one of the entity.
+    fn has_workspace() -> bool {
+        true
+    }
+}
+
+impl ChangeEventBmc {
+    /// Returns the (at most `limit`) events logged after `after_id` in the workspace
+    /// of the ctx, oldest first.
+    /// Fails with `ChangeEventsPruned` if some of those events are not in the log anymore.
+    /// Note: Not access checked, see `ChangeEvent::for_ctx`.
+    pub async fn list_after(
+        ctx: &Ctx,
+        mm: &ModelManager,
+        after_id: i64,
+        limit: u64,
+    ) -> Result<Vec<ChangeEvent>> {
+        let db = mm.db();
+
+        // -- Check that the log still goes back to after_id
+        let mut query = Query::select();
+        query
+            .from(Self::table_ref())
+            .expr(Expr::col(ChangeEventIden::Id).max());
+        let (sql, values) = query.build_sqlx(PostgresQueryBuilder);
+        let (max_id,) = sqlx::query_as_with::<_, (Option<i64>,), _>(&sql, values)
+            .fetch_one(db)
+            .await?;
+        if max_id.is_some_and(|max_id| after_id < max_id - EVENT_LOG_MAX) {
+            return Err(Error::ChangeEventsPruned { after_id });
+        }
+
+        // -- List the events
+        let mut query = Query::select();
+        query
+            .from(Self::table_ref())
+            .columns(ChangeEvent::field_column_refs())
+            .and_where(Expr::col(ChangeEventIden::Id).gt(after_id))
+            .order_by(ChangeEventIden::Id, Order::Asc)
+            .limit(limit);
+        if let Some(workspace_cond) = base::workspace_cond::<Self>(ctx)? {
+            query.and_where(workspace_cond);
+        }
+
+        let (sql, values) = query.build_sqlx(PostgresQueryBuilder);
+        let events = sqlx::query_as_with::<_, ChangeEvent, _>(&sql, values)
+            .fetch_all(db)
+            .await?;
+
+        Ok(events)
+    }
+
+    /// Deletes the events before the last `EVENT_LOG_MAX`, returns how many.
+    /// Run by the scheduler rather than by the writers, which would contend on the
+    /// same old rows.
+    pub async fn prune(mm: &ModelManager) -> Result<u64> {
+        let sql = r#"
+            DELETE FROM change_event
+            WHERE id <= (SELECT max(id) FROM change_event) - $1"#;
+
+        let count = sqlx::query(sql)
+            .bind(EVENT_LOG_MAX)
+            .execute(mm.db())
+            .await?
+            .rows_affected();
+
+        Ok(count)
+    }
+
+    async fn get_data(mm: &ModelManager, id: i64) -> Result<Value> {
+        let mut query = Query::select();
+        query
+            .from(Self::table_ref())
+            .column(ChangeEventIden::Data)
+            .and_where(Expr::col(ChangeEventIden::Id).eq(id));
+
+        let (sql, values) = query.build_sqlx(PostgresQueryBuilder);
+        let (data,) = sqlx::query_as_with::<_, (Value,), _>(&sql, values)
+            .fetch_optional(mm.db())
+            .await?
+            .ok_or(Error::EntityNotFound {
+                entity: Self::TABLE,
+                id,
+            })?;
+
+        Ok(data)
+    }
+}
+
+// endregion: --- ChangeEventBmc
+
+// region:    --- Access Check
+
+impl ChangeEvent {
+    /// Returns the event as the ctx can see it, or None if it cannot see the entity.
+    ///
+    /// The visibility is decided from the workspace of the event, without reading the
+    /// entity again, so `data` is the row of the change restricted to the entity fields
+    /// (e.g., `Task` without its labels). The data of an event too big to be notified
+    /// is read from the log.
+    /// The events of the other workspaces are never seen.
+    pub async fn for_ctx(self, ctx: &Ctx, mm: &ModelManager) -> Option<ChangeEvent> {
+        let in_workspace = match ctx.workspace_id() {
+            Some(workspace_id) => self.workspace_id == Some(workspace_id),
+            None => ctx.is_root(),
+        };
+        if !in_workspace {
+            return None;
+        }
+
+        let field_names = match self.entity.as_str() {
+            "task" => Task::field_names(),
+            _ => return None,
+        };
+        let data = match self.data {
+            Value::Null => ChangeEventBmc::get_data(mm, self.id).await.ok()?,
+            data => data,
+        };
+
+        Some(ChangeEvent {
+            data: only_fields(data, field_names),
+            ..self
+        })
+    }
+}
+
+fn only_fields(data: Value, field_names: &[&str]) -> Value {
+    match data {
+        Value::Object(map) => Value::Object(
+            map.into_iter()
+                .filter(|(k, _)| field_names.contains(&k.as_str()))
+                .collect::<Map<String, Value>>(),
+        ),
+        other => other,
+    }
+}
+
+// endregion: --- Access Check
+
+// region:    --- ChangeListener
+
+/// Forwards the postgres notifications to a broadcast channel.
+/// The listening task is started on first subscribe (and restarted if it ended),
+/// and aborted when the last `ModelManager` clone is dropped.
+#[derive(Clone)]
+pub(in crate::model) struct ChangeListener {
+    sender: broadcast::Sender<ChangeEvent>,
+    task: Arc<Mutex<Option<ListenerTask>>>,
+}
+
+struct ListenerTask(JoinHandle<()>);
+
+impl Drop for ListenerTask {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+impl ChangeListener {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(BROADCAST_CAPACITY);
+        ChangeListener {
+            sender,
+            task: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Returns a receiver of all the change events committed from now on.
+    pub async fn subscribe(&self) -> Result<broadcast::Receiver<ChangeEvent>> {
+        let mut task = self.task.lock().await;
+
+        let running = task.as_ref().is_some_and(|t| !t.0.is_finished());
+        if !running {
+            // Note: a dedicated connection, a pool connection would be held forever.
+            let mut listener = PgListener::connect(&config().DB_URL)
+                .await
+                .map_err(|ex| Error::ChangeListenerFail(ex.to_string()))?;
+            listener
+                .listen(CHANGE_CHANNEL)
+                .await
+                .map_err(|ex| Error::ChangeListenerFail(ex.to_string()))?;
+
+            let sender = self.sender.clone();
+            *task = Some(ListenerTask(tokio::spawn(listen_loop(listener, sender))));
+        }
+
+        Ok(self.sender.subscribe())
+    }
+
+    /// Stops the listening task (restarted by a next subscribe).
+    pub async fn close(&self) {
+        self.task.lock().await.take();
+    }
+}
+
+async fn listen_loop(mut listener: PgListener, sender: broadcast::Sender<ChangeEvent>) {
+    loop {
+        // recv reconnects by itself when the connection is lost
+        match listener.recv().await {
+            Ok(notification) => {
+                match serde_json::from_str::<Vec<ChangeEvent>>(notification.payload()) {
+                    // no receiver is not an error
+                    Ok(events) => {
+                        for event in events {
+                            let _ = sender.send(event);
+                        }
+                    }
+                    Err(ex) => error!("{:<12} - change event bad payload: {ex}", "LISTENER"),
+                }
+            }
+            Err(ex) => {
+                debug!("{:<12} - change listener error: {ex}", "LISTENER");
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+        }
+    }
+}
+
+// endregion: --- ChangeListener
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::_dev_utils;
+    use crate::ctx::Ctx;
+    use crate::model::task::TaskBmc;
+    use anyhow::Result;
+    use serde_json::json;
+    use serial_test::serial;
+
+    #[serial]
+    #[tokio::test]
+    async fn test_subscribe_changes_ok() -> Result<()> {
+        // -- Setup & Fixtures
+        _dev_utils::init_test().await;
+        // own ModelManager, as waiting for the events lets the connection go back to the
+        // pool, where it would be unusable from the next test runtime.
+        let mm = ModelManager::new().await?;
+        let ctx = _dev_utils::demo_ctx();
+        let fx_title = "test_subscribe_changes_ok-task 01";
+        let mut rx = mm.subscribe_changes().await?;
+
+        // -- Exec
+        let fx_task = _dev_utils::seed_tasks(&ctx, &mm, &[fx_title])
+            .await?
+            .remove(0);
+        TaskBmc::delete(&ctx, &mm, fx_task.id).await?;
+
+        // -- Check
+        let mut events = Vec::new();
+        while events.len() < 2 {
+            let event = tokio::time::timeout(Duration::from_secs(5), rx.recv()).await??;
+            if event.entity_id == fx_task.id {
+                events.push(event);
+            }
+        }
+        assert_eq!(events[0].op, ChangeOp::Create);
+        assert_eq!(events[0].entity, "task");
+        assert_eq!(events[0].data["title"], fx_title);
+        assert_eq!(events[1].op, ChangeOp::Delete);
+
+        Ok(())
+    }
+
+    #[serial]
+    #[tokio::test]
+    async fn test_list_after_ok() -> Result<()> {
+        // -- Setup & Fixtures
+        let mm = _dev_utils::init_test().await;
+        let ctx = _dev_utils::demo_ctx();
+        let fx_titles = &["test_list_after_ok-task 01", "test_list_after_ok-task 02"];
+        let fx_tasks = _dev_utils::seed_tasks(&ctx, &mm, fx_titles).await?;
+        for task in fx_tasks.iter() {
+            TaskBmc::delete(&ctx, &mm, task.id).await?;
+        }
+
+        // -- Exec
+        let events = ChangeEventBmc::list_after(&ctx, &mm, 0, 1000).await?;
+
+        // -- Check
+        let events: Vec<ChangeEvent> = events
+            .into_iter()
+            .filter(|e| fx_tasks.iter().any(|t| t.id == e.entity_id))
+            .collect();
+        let ops: Vec<ChangeOp> = events.iter().map(|e| e.op).collect();
+        assert_eq!(
+            ops,
+            [
+                ChangeOp::Create,
+                ChangeOp::Create,
+                ChangeOp::Delete,
+                ChangeOp::Delete
+            ]
+        );
+        assert!(events.windows(2).all(|w| w[0].id < w[1].id));
+
+        // delete is visible with the former task fields only
+        let event = events[2].clone().for_ctx(&ctx, &mm).await.unwrap();
+        assert_eq!(event.data["title"], fx_titles[0]);
+        assert!(event.data.get("version").is_some());
+
+        Ok(())
+    }
+
+    #[serial]
+    #[tokio::test]
+    async fn test_for_ctx_ok() -> Result<()> {
+        // -- Setup & Fixtures
+        let mm = _dev_utils::init_test().await;
+        let ctx = _dev_utils::demo_ctx();
+        let fx_other_ctx = Ctx::root_ctx_in(_dev_utils::DEMO_WORKSPACE_ID + 1);
+        // (of a task which does not exist, the visibility is from the event only)
+        let fx_event = ChangeEvent {
+            id: 0,
+            entity: "task".to_string(),
+            op: ChangeOp::Update,
+            entity_id: 0,
+            workspace_id: Some(_dev_utils::DEMO_WORKSPACE_ID),
+            data: json!({"id": 0, "title": "test_for_ctx_ok", "search_tsv": "'test'"}),
+        };
+
+        // -- Exec
+        let event = fx_event.clone().for_ctx(&ctx, &mm).await;
+        let other_event = fx_event.for_ctx(&fx_other_ctx, &mm).await;
+
+        // -- Check
+        let event = event.expect("visible in its workspace");
+        assert_eq!(event.data, json!({"id": 0, "title": "test_for_ctx_ok"}));
+        assert!(other_event.is_none(), "not visible in another workspace");
+
+        Ok(())
+    }
+
+    #[serial]
+    #[tokio::test]
+    async fn test_list_after_err_pruned() -> Result<()> {
+        // -- Setup & Fixtures
+        let mm = _dev_utils::init_test().await;
+        let ctx = _dev_utils::demo_ctx();
+        let fx_title = "test_list_after_err_pruned-task 01";
+        let fx_task = _dev_utils::seed_tasks(&ctx, &mm, &[fx_title])
+            .await?
+            .remove(0);
+        let fx_last_id = ChangeEventBmc::list_after(&ctx, &mm, 0, 1000)
+            .await?
+            .last()
+            .map(|e| e.id)
+            .unwrap_or_default();
+
+        // -- Exec
+        let res = ChangeEventBmc::list_after(&ctx, &mm, fx_last_id - EVENT_LOG_MAX - 1, 1).await;
+
+        // -- Check
+        assert!(
+            matches!(res, Err(Error::ChangeEventsPruned { .. })),
+            "ChangeEventsPruned not matching"
+        );
+
+        // -- Clean
+        TaskBmc::delete(&ctx, &mm, fx_task.id).await?;
+
+        Ok(())
+    }
+
+    #[serial]
+    #[tokio::test]
+    async fn test_prune_ok() -> Result<()> {
+        // -- Setup & Fixtures
+        let mm = _dev_utils::init_test().await;
+        // events of no workspace, not seen by the other tests
+        sqlx::query(
+            "INSERT INTO change_event (entity, op, entity_id, data)
+             SELECT 'test_prune_ok', 'create', n, 'null' FROM generate_series(1, $1) n",
+        )
+        .bind(EVENT_LOG_MAX + 5)
+        .execute(mm.db())
+        .await?;
+
+        // -- Exec
+        let pruned = ChangeEventBmc::prune(&mm).await?;
+
+        // -- Check
+        assert!(pruned >= 5, "pruned {pruned}");
+        let (count, last_entity_id): (i64, Option<i64>) = sqlx::query_as(
+            "SELECT count(*), max(entity_id) FILTER (WHERE entity = 'test_prune_ok')
+             FROM change_event",
+        )
+        .fetch_one(mm.db())
+        .await?;
+        assert!(count <= EVENT_LOG_MAX, "count {count}");
+        assert_eq!(last_entity_id, Some(EVENT_LOG_MAX + 5));
+
+        // -- Clean
+        sqlx::query("DELETE FROM change_event WHERE entity = 'test_prune_ok'")
+            .execute(mm.db())
+            .await?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_notify_payloads_chunks_ok() -> Result<()> {
+        // -- Setup & Fixtures
+        let fx_event = |id: i64, data: Value| ChangeEvent {
+            id,
+            entity: "task".to_string(),
+            op: ChangeOp::Update,
+            entity_id: id,
+            workspace_id: Some(1000),
+            data,
+        };
+        let fx_title = "x".repeat(1000);
+        let mut fx_events: Vec<ChangeEvent> = (1..=20)
+            .map(|id| fx_event(id, serde_json::json!({ "title": fx_title })))
+            .collect();
+        fx_events.push(fx_event(21, Value::String("x".repeat(PAYLOAD_MAX_BYTES))));
+
+        // -- Exec
+        let payloads = notify_payloads(fx_events.into_iter())?;
+
+        // -- Check
+        assert!(payloads.len() > 1, "payloads {}", payloads.len());
+        let mut events = Vec::new();
+        for payload in payloads {
+            assert!(payload.len() <= PAYLOAD_MAX_BYTES, "len {}", payload.len());
+            events.extend(serde_json::from_str::<Vec<ChangeEvent>>(&payload)?);
+        }
+        let ids: Vec<i64> = events.iter().map(|e| e.id).collect();
+        assert_eq!(ids, (1..=21).collect::<Vec<_>>());
+        assert_eq!(events[0].data["title"], fx_title);
+        assert_eq!(events[20].data, Value::Null);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,331 @@
+use crate::ctx::Ctx;
+use crate::model::audit_event::AuditEventBmc;
+use crate::model::base::{self, DbBmc};
+use crate::model::task::TaskBmc;
+use crate::model::ModelManager;
+use crate::model::{Error, Result};
+use crate::utils::{now_utc, serialize_time};
+use modql::field::Fields;
+use modql::filter::{FilterNodes, ListOptions, OpValsInt64};
+use sea_query::{Expr, Iden, PostgresQueryBuilder, Query};
+use sea_query_binder::SqlxBinder;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::FromRow;
+use std::collections::HashMap;
+use time::OffsetDateTime;
+
+#[derive(Debug, Clone, Fields, FromRow, Serialize)]
+pub struct Comment {
+    pub id: i64,
+    pub task_id: i64,
+    /// User of the ctx which added the comment.
+    pub author_id: i64,
+    /// Markdown
+    pub body: String,
+    #[serde(serialize_with = "serialize_time")]
+    pub ctime: OffsetDateTime,
+    /// Last edit (or `ctime`).
+    #[serde(serialize_with = "serialize_time")]
+    pub mtime: OffsetDateTime,
+}
+
+#[derive(Deserialize)]
+pub struct CommentForCreate {
+    pub task_id: i64,
+    pub body: String,
+}
+
+#[derive(Deserialize)]
+pub struct CommentForUpdate {
+    pub body: String,
+}
+
+/// `CommentForCreate` with the author from the ctx, in the workspace of the task.
+#[derive(Fields)]
+struct CommentForInsert {
+    workspace_id: i64,
+    task_id: i64,
+    author_id: i64,
+    body: String,
+}
+
+#[derive(Fields)]
+struct CommentForEdit {
+    body: String,
+    mtime: OffsetDateTime,
+}
+
+#[derive(FilterNodes, Deserialize, Default, Debug)]
+pub struct CommentFilter {
+    id: Option<OpValsInt64>,
+
+    task_id: Option<OpValsInt64>,
+    author_id: Option<OpValsInt64>,
+}
+
+#[derive(Iden)]
+enum CommentIden {
+    TaskId,
+}
+
+pub struct CommentBmc;
+
+impl DbBmc for CommentBmc {
+    const TABLE: &'static str = "comment";
+
+    fn has_workspace() -> bool {
+        true
+    }
+}
+
+impl CommentBmc {
+    /// Adds the comment to the task, authored by the user of the ctx.
+    pub async fn create(
+        ctx: &Ctx,
+        mm: &ModelManager,
+        comment_c: CommentForCreate,
+    ) -> Result<Comment> {
+        // also for the EntityNotFound error
+        let task = TaskBmc::get(ctx, mm, comment_c.task_id).await?;
+
+        let comment_i = CommentForInsert {
+            workspace_id: task.workspace_id,
+            task_id: comment_c.task_id,
+            author_id: ctx.user_id(),
+            body: comment_c.body,
+        };
+
+        base::create_returning::<Self, _, _>(ctx, mm, comment_i).await
+    }
+
+    pub async fn get(ctx: &Ctx, mm: &ModelManager, id: i64) -> Result<Comment> {
+        base::get::<Self, _>(ctx, mm, id).await
+    }
+
+    /// Paginated with the list options, oldest first by default.
+    pub async fn list(
+        ctx: &Ctx,
+        mm: &ModelManager,
+        filters: Option<Vec<CommentFilter>>,
+        list_options: Option<ListOptions>,
+    ) -> Result<Vec<Comment>> {
+        base::list::<Self, _, _>(ctx, mm, filters, list_options).await
+    }
+
+    /// Fails with `CommentNotAuthor` if the user of the ctx is not the author.
+    pub async fn update(
+        ctx: &Ctx,
+        mm: &ModelManager,
+        id: i64,
+        comment_u: CommentForUpdate,
+    ) -> Result<Comment> {
+        let comment = Self::get(ctx, mm, id).await?;
+        if comment.author_id != ctx.user_id() {
+            AuditEventBmc::record_denied(ctx, mm, "edit_comment", json!({ "comment_id": id }))
+                .await;
+            return Err(Error::CommentNotAuthor {
+                id,
+                user_id: ctx.user_id(),
+            });
+        }
+
+        let comment_e = CommentForEdit {
+            body: comment_u.body,
+            mtime: now_utc(),
+        };
+
+        base::update_returning::<Self, _, _>(ctx, mm, id, None, comment_e).await
+    }
+
+    /// Fails with `CommentNotAuthor` if the user of the ctx is not the author.
+    pub async fn delete(ctx: &Ctx, mm: &ModelManager, id: i64) -> Result<()> {
+        let comment = Self::get(ctx, mm, id).await?;
+        if comment.author_id != ctx.user_id() {
+            AuditEventBmc::record_denied(ctx, mm, "delete_comment", json!({ "comment_id": id }))
+                .await;
+            return Err(Error::CommentNotAuthor {
+                id,
+                user_id: ctx.user_id(),
+            });
+        }
+
+        base::delete::<Self>(ctx, mm, id).await
+    }
+
+    /// Number of comments of each of the tasks, by task id (absent when none),
+    /// with a single query.
+    pub async fn count_by_task_ids(
+        _ctx: &Ctx,
+        mm: &ModelManager,
+        task_ids: Vec<i64>,
+    ) -> Result<HashMap<i64, i64>> {
+        let mut query = Query::select();
+        query
+            .from(Self::table_ref())
+            .column(CommentIden::TaskId)
+            .expr(Expr::col(CommentIden::TaskId).count())
+            .and_where(Expr::col(CommentIden::TaskId).is_in(task_ids))
+            .group_by_col(CommentIden::TaskId);
+
+        let (sql, values) = query.build_sqlx(PostgresQueryBuilder);
+        let counts = sqlx::query_as_with::<_, (i64, i64), _>(&sql, values)
+            .fetch_all(mm.db())
+            .await?;
+
+        Ok(counts.into_iter().collect())
+    }
+}
+
+// region:    --- Tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::_dev_utils;
+    use anyhow::Result;
+    use modql::filter::OpValInt64;
+    use serial_test::serial;
+
+    #[serial]
+    #[tokio::test]
+    async fn test_create_list_and_count_ok() -> Result<()> {
+        // -- Setup & Fixtures
+        let mm = _dev_utils::init_test().await;
+        let ctx = _dev_utils::demo_ctx();
+        let fx_tasks = _dev_utils::seed_tasks(
+            &ctx,
+            &mm,
+            &[
+                "test_create_list_and_count_ok-task 01",
+                "test_create_list_and_count_ok-task 02",
+            ],
+        )
+        .await?;
+        let fx_task_id = fx_tasks[0].id;
+        let fx_bodies = ["comment 01", "comment 02", "comment 03"];
+
+        // -- Exec
+        for body in fx_bodies {
+            let comment_c = CommentForCreate {
+                task_id: fx_task_id,
+                body: body.to_string(),
+            };
+            CommentBmc::create(&ctx, &mm, comment_c).await?;
+        }
+
+        // -- Check
+        let filter = CommentFilter {
+            task_id: Some(OpValInt64::Eq(fx_task_id).into()),
+            ..Default::default()
+        };
+        let page = ListOptions {
+            limit: Some(2),
+            offset: Some(1),
+            order_bys: Some("id".into()),
+        };
+        let comments = CommentBmc::list(&ctx, &mm, Some(vec![filter]), Some(page)).await?;
+        let bodies: Vec<&str> = comments.iter().map(|c| c.body.as_str()).collect();
+        assert_eq!(bodies, &fx_bodies[1..]);
+        assert_eq!(comments[0].author_id, ctx.user_id());
+
+        let tasks = TaskBmc::list_by_ids(&ctx, &mm, vec![fx_tasks[0].id, fx_tasks[1].id]).await?;
+        let counts: Vec<i64> = tasks.iter().map(|t| t.comment_count).collect();
+        assert_eq!(counts, [3, 0]);
+
+        // -- Clean
+        for task in fx_tasks {
+            TaskBmc::delete(&ctx, &mm, task.id).await?;
+        }
+
+        Ok(())
+    }
+
+    #[serial]
+    #[tokio::test]
+    async fn test_update_err_not_author() -> Result<()> {
+        // -- Setup & Fixtures
+        let mm = _dev_utils::init_test().await;
+        let root_ctx = _dev_utils::demo_ctx();
+        let fx_ctx = Ctx::new(1000, Some(_dev_utils::DEMO_WORKSPACE_ID))?;
+        let fx_task = _dev_utils::seed_tasks(&root_ctx, &mm, &["test_update_err_not_author"])
+            .await?
+            .remove(0);
+        let fx_comment = CommentBmc::create(
+            &fx_ctx,
+            &mm,
+            CommentForCreate {
+                task_id: fx_task.id,
+                body: "original".to_string(),
+            },
+        )
+        .await?;
+        let fx_comment_u = || CommentForUpdate {
+            body: "edited".to_string(),
+        };
+
+        // -- Exec
+        let res = CommentBmc::update(&root_ctx, &mm, fx_comment.id, fx_comment_u()).await;
+        let comment = CommentBmc::update(&fx_ctx, &mm, fx_comment.id, fx_comment_u()).await?;
+
+        // -- Check
+        assert!(
+            matches!(res, Err(Error::CommentNotAuthor { id, user_id: 0 }) if id == fx_comment.id),
+            "CommentNotAuthor not matching"
+        );
+        assert_eq!(comment.body, "edited");
+        assert!(comment.mtime > fx_comment.mtime);
+
+        // -- Clean
+        TaskBmc::delete(&root_ctx, &mm, fx_task.id).await?;
+        let res = CommentBmc::get(&root_ctx, &mm, fx_comment.id).await;
+        assert!(
+            matches!(res, Err(Error::EntityNotFound { .. })),
+            "comment should be deleted with its task"
+        );
+
+        Ok(())
+    }
+
+    #[serial]
+    #[tokio::test]
+    async fn test_delete_err_not_author() -> Result<()> {
+        // -- Setup & Fixtures
+        let mm = _dev_utils::init_test().await;
+        let root_ctx = _dev_utils::demo_ctx();
+        let fx_ctx = Ctx::new(1000, Some(_dev_utils::DEMO_WORKSPACE_ID))?;
+        let fx_task = _dev_utils::seed_tasks(&root_ctx, &mm, &["test_delete_err_not_author"])
+            .await?
+            .remove(0);
+        let fx_comment = CommentBmc::create(
+            &fx_ctx,
+            &mm,
+            CommentForCreate {
+                task_id: fx_task.id,
+                body: "to delete".to_string(),
+            },
+        )
+        .await?;
+
+        // -- Exec
+        let res = CommentBmc::delete(&root_ctx, &mm, fx_comment.id).await;
+        CommentBmc::get(&root_ctx, &mm, fx_comment.id).await?;
+        CommentBmc::delete(&fx_ctx, &mm, fx_comment.id).await?;
+
+        // -- Check
+        assert!(
+            matches!(res, Err(Error::CommentNotAuthor { id, user_id: 0 }) if id == fx_comment.id),
+            "CommentNotAuthor not matching"
+        );
+        let res = CommentBmc::get(&root_ctx, &mm, fx_comment.id).await;
+        assert!(
+            matches!(res, Err(Error::EntityNotFound { .. })),
+            "comment should be deleted by its author"
+        );
+
+        // -- Clean
+        TaskBmc::delete(&root_ctx, &mm, fx_task.id).await?;
+
+        Ok(())
+    }
+}
+// endregion: --- Tests
@@ -2,7 +2,10 @@ use derive_more::From;
 use serde::Serialize;
 use serde_with::{serde_as, DisplayFromStr};
 
-use crate::{crypt, model::store};
+use crate::{
+    crypt,
+    model::{blob_store, store},
+};
 
 pub type Result<T> = core::result::Result<T, Error>;
 
@@ -13,16 +16,78 @@ pub enum Error {
         entity: &'static str,
         id: i64,
     },
+    EntityVersionConflict {
+        entity: &'static str,
+        id: i64,
+        current_version: i64,
+    },
+    // -- Workspaces
+    /// A user ctx without workspace, for a Bmc with workspace (see `DbBmc::has_workspace`).
+    CtxNoWorkspace {
+        user_id: i64,
+    },
+    // -- Task graph
+    TaskParentCycle {
+        id: i64,
+        parent_id: i64,
+    },
+    TaskBlockerCycle {
+        id: i64,
+        blocker_id: i64,
+    },
+    TaskHasOpenBlockers {
+        id: i64,
+        blocker_ids: Vec<i64>,
+    },
+    // -- Admin
+    AdminRequired {
+        user_id: i64,
+    },
+    // -- Comments
+    CommentNotAuthor {
+        id: i64,
+        user_id: i64,
+    },
+    // -- Attachments
+    AttachmentQuotaExceeded {
+        quota: i64,
+        used: i64,
+    },
+    AttachmentFailUpload(String),
+    UpsertKeyMissing {
+        entity: &'static str,
+        key: String,
+    },
     ListLimitOverMax {
         max: i64,
         actual: i64,
     },
+    BulkCreateOverMax {
+        max: usize,
+        actual: usize,
+    },
+    /// A bulk update/delete filter (or one of its groups) which would match all the rows.
+    BulkFilterEmpty {
+        entity: &'static str,
+    },
+    // -- Change events
+    ChangeEventFailSerialize,
+    ChangeListenerFail(String),
+    ChangeEventsPruned {
+        after_id: i64,
+    },
+    // -- Readiness
+    SchemaNotApplied {
+        missing_tables: Vec<String>,
+    },
     // -- Modules
     // instead of manually implmenting From, we can use derive_more::From trait
     #[from]
     Crypt(crypt::Error),
     #[from]
     Store(store::Error),
+    #[from]
+    BlobStore(blob_store::Error),
 
     // -- Externals
 
@@ -0,0 +1,362 @@
+use crate::ctx::Ctx;
+use crate::model::base::{self, CommonIden, DbBmc};
+use crate::model::task::TaskBmc;
+use crate::model::ModelManager;
+use crate::model::Result;
+use modql::field::{Fields, HasFields};
+use modql::filter::{
+    FilterNodes, IntoSeaError, ListOptions, OpValValue, OpValsInt64, OpValsString, SeaResult,
+};
+use modql::SIden;
+use sea_query::{
+    ColumnRef, Condition, ConditionExpression, Expr, Iden, OnConflict, PostgresQueryBuilder, Query,
+};
+use sea_query_binder::SqlxBinder;
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgConnection};
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Fields, FromRow, Serialize)]
+pub struct Label {
+    pub id: i64,
+    pub name: String,
+    pub color: Option<String>,
+}
+
+#[derive(Fields, Default, Deserialize)]
+pub struct LabelForCreate {
+    pub name: String,
+    pub color: Option<String>,
+}
+
+#[derive(FilterNodes, Deserialize, Default, Debug)]
+pub struct LabelFilter {
+    id: Option<OpValsInt64>,
+
+    name: Option<OpValsString>,
+}
+
+/// The many-to-many table between the tasks and the labels.
+#[derive(Iden)]
+enum TaskLabel {
+    Table,
+    TaskId,
+    LabelId,
+}
+
+#[derive(Iden)]
+enum LabelIden {
+    #[iden = "label"]
+    Table,
+    Id,
+}
+
+/// A label with the task it is attached to.
+#[derive(FromRow)]
+struct TaskLabelRow {
+    task_id: i64,
+    #[sqlx(flatten)]
+    label: Label,
+}
+
+pub struct LabelBmc;
+
+impl DbBmc for LabelBmc {
+    const TABLE: &'static str = "label";
+
+    fn has_workspace() -> bool {
+        true
+    }
+}
+
+impl LabelBmc {
+    pub async fn create(ctx: &Ctx, mm: &ModelManager, label_c: LabelForCreate) -> Result<Label> {
+        base::create_returning::<Self, _, _>(ctx, mm, label_c).await
+    }
+
+    pub async fn get(ctx: &Ctx, mm: &ModelManager, id: i64) -> Result<Label> {
+        base::get::<Self, _>(ctx, mm, id).await
+    }
+
+    pub async fn list(
+        ctx: &Ctx,
+        mm: &ModelManager,
+        filters: Option<Vec<LabelFilter>>,
+        list_options: Option<ListOptions>,
+    ) -> Result<Vec<Label>> {
+        base::list::<Self, _, _>(ctx, mm, filters, list_options).await
+    }
+
+    /// Also detaches the label from all its tasks.
+    pub async fn delete(ctx: &Ctx, mm: &ModelManager, id: i64) -> Result<()> {
+        base::delete::<Self>(ctx, mm, id).await
+    }
+
+    /// Attaches the label to the task. Does nothing if it already is.
+    pub async fn attach(ctx: &Ctx, mm: &ModelManager, task_id: i64, label_id: i64) -> Result<()> {
+        // for the EntityNotFound errors
+        TaskBmc::get(ctx, mm, task_id).await?;
+        Self::get(ctx, mm, label_id).await?;
+
+        let mut query = Query::insert();
+        query
+            .into_table(TaskLabel::Table)
+            .columns([TaskLabel::TaskId, TaskLabel::LabelId])
+            .values([task_id.into(), label_id.into()])?
+            .on_conflict(
+                OnConflict::columns([TaskLabel::TaskId, TaskLabel::LabelId])
+                    .do_nothing()
+                    .to_owned(),
+            );
+
+        let (sql, values) = query.build_sqlx(PostgresQueryBuilder);
+        sqlx::query_with(&sql, values).execute(mm.db()).await?;
+
+        Ok(())
+    }
+
+    /// Detaches the label from the task. Does nothing if it is not attached.
+    pub async fn detach(ctx: &Ctx, mm: &ModelManager, task_id: i64, label_id: i64) -> Result<()> {
+        // for the EntityNotFound error
+        TaskBmc::get(ctx, mm, task_id).await?;
+
+        let mut query = Query::delete();
+        query
+            .from_table(TaskLabel::Table)
+            .and_where(Expr::col(TaskLabel::TaskId).eq(task_id))
+            .and_where(Expr::col(TaskLabel::LabelId).eq(label_id));
+
+        let (sql, values) = query.build_sqlx(PostgresQueryBuilder);
+        sqlx::query_with(&sql, values).execute(mm.db()).await?;
+
+        Ok(())
+    }
+
+    /// Attaches the labels of `from_task_id` to `to_task_id` too, in the transaction
+    /// of `con` (e.g., of the creation of `to_task_id`).
+    pub async fn copy_attached_in(
+        _ctx: &Ctx,
+        con: &mut PgConnection,
+        from_task_id: i64,
+        to_task_id: i64,
+    ) -> Result<()> {
+        let mut select = Query::select();
+        select
+            .expr(Expr::val(to_task_id))
+            .column(TaskLabel::LabelId)
+            .from(TaskLabel::Table)
+            .and_where(Expr::col(TaskLabel::TaskId).eq(from_task_id));
+
+        let mut query = Query::insert();
+        query
+            .into_table(TaskLabel::Table)
+            .columns([TaskLabel::TaskId, TaskLabel::LabelId])
+            .select_from(select)?
+            .on_conflict(
+                OnConflict::columns([TaskLabel::TaskId, TaskLabel::LabelId])
+                    .do_nothing()
+                    .to_owned(),
+            );
+
+        let (sql, values) = query.build_sqlx(PostgresQueryBuilder);
+        sqlx::query_with(&sql, values).execute(con).await?;
+
+        Ok(())
+    }
+
+    /// Labels of each of the tasks, by task id, with a single query.
+    pub async fn list_by_task_ids(
+        _ctx: &Ctx,
+        mm: &ModelManager,
+        task_ids: Vec<i64>,
+    ) -> Result<HashMap<i64, Vec<Label>>> {
+        let mut query = Query::select();
+        query
+            .from(LabelIden::Table)
+            .column((TaskLabel::Table, TaskLabel::TaskId))
+            .columns(Label::field_column_refs_with_rel(LabelIden::Table))
+            .inner_join(
+                TaskLabel::Table,
+                Expr::col((TaskLabel::Table, TaskLabel::LabelId))
+                    .equals((LabelIden::Table, LabelIden::Id)),
+            )
+            .and_where(Expr::col((TaskLabel::Table, TaskLabel::TaskId)).is_in(task_ids))
+            .order_by((LabelIden::Table, LabelIden::Id), sea_query::Order::Asc);
+
+        let (sql, values) = query.build_sqlx(PostgresQueryBuilder);
+        let rows = sqlx::query_as_with::<_, TaskLabelRow, _>(&sql, values)
+            .fetch_all(mm.db())
+            .await?;
+
+        let mut labels_by_task: HashMap<i64, Vec<Label>> = HashMap::new();
+        for TaskLabelRow { task_id, label } in rows {
+            labels_by_task.entry(task_id).or_default().push(label);
+        }
+
+        Ok(labels_by_task)
+    }
+}
+
+// region:    --- Task Filters
+
+/// Task has any of the labels:
+/// `{"label_ids": {"$in": [1000, 1001]}}` (or `{"label_ids": {"$eq": 1000}}`).
+/// For `#[modql(to_sea_condition_fn = "..")]` on the task filter.
+pub(in crate::model) fn task_label_ids_any(
+    _col: &ColumnRef,
+    op_value: OpValValue,
+) -> SeaResult<ConditionExpression> {
+    let label_ids = label_ids_of(op_value)?;
+    let exists = Expr::exists(task_label_exists(|label_id| label_id.is_in(label_ids)));
+
+    Ok(exists.into())
+}
+
+/// Task has all of the labels: `{"label_ids_all": {"$in": [1000, 1001]}}`.
+/// For `#[modql(to_sea_condition_fn = "..")]` on the task filter.
+pub(in crate::model) fn task_label_ids_all(
+    _col: &ColumnRef,
+    op_value: OpValValue,
+) -> SeaResult<ConditionExpression> {
+    let mut cond = Condition::all();
+    for label_id in label_ids_of(op_value)? {
+        cond = cond.add(Expr::exists(task_label_exists(|col| col.eq(label_id))));
+    }
+
+    Ok(cond.into())
+}
+
+/// `SELECT 1 FROM task_label WHERE task_id = task.id AND <label_id cond>`
+fn task_label_exists(
+    label_cond: impl FnOnce(Expr) -> sea_query::SimpleExpr,
+) -> sea_query::SelectStatement {
+    Query::select()
+        .expr(Expr::val(1))
+        .from(TaskLabel::Table)
+        .and_where(
+            Expr::col((TaskLabel::Table, TaskLabel::TaskId))
+                .equals((SIden(TaskBmc::TABLE), CommonIden::Id)),
+        )
+        .and_where(label_cond(Expr::col((
+            TaskLabel::Table,
+            TaskLabel::LabelId,
+        ))))
+        .to_owned()
+}
+
+fn label_ids_of(op_value: OpValValue) -> SeaResult<Vec<i64>> {
+    let values = match op_value {
+        OpValValue::Eq(value) => vec![value],
+        // modql 0.3 parses the `$in` of a `OpValsValue` as a `NotIn`
+        // (so `$notIn` can't be told apart, and is not documented)
+        OpValValue::In(values) | OpValValue::NotIn(values) => values,
+        _ => {
+            return Err(IntoSeaError::Custom(
+                "label filters only support $eq and $in".to_string(),
+            ))
+        }
+    };
+
+    values
+        .into_iter()
+        .map(|value| {
+            value.as_i64().ok_or_else(|| {
+                IntoSeaError::Custom(format!("label id must be an integer: {value}"))
+            })
+        })
+        .collect()
+}
+
+// endregion: --- Task Filters
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::_dev_utils;
+    use crate::model::task::TaskFilter;
+    use anyhow::Result;
+    use serde_json::json;
+    use serial_test::serial;
+
+    #[serial]
+    #[tokio::test]
+    async fn test_attach_and_filter_ok() -> Result<()> {
+        // -- Setup & Fixtures
+        let mm = _dev_utils::init_test().await;
+        let ctx = _dev_utils::demo_ctx();
+        let fx_tasks = _dev_utils::seed_tasks(
+            &ctx,
+            &mm,
+            &[
+                "test_attach_and_filter_ok-task 01",
+                "test_attach_and_filter_ok-task 02",
+                "test_attach_and_filter_ok-task 03",
+            ],
+        )
+        .await?;
+        let mut fx_labels = Vec::new();
+        for name in [
+            "test_attach_and_filter_ok-bug",
+            "test_attach_and_filter_ok-ui",
+        ] {
+            let label_c = LabelForCreate {
+                name: name.to_string(),
+                ..Default::default()
+            };
+            fx_labels.push(LabelBmc::create(&ctx, &mm, label_c).await?);
+        }
+        let (bug, ui) = (fx_labels[0].id, fx_labels[1].id);
+
+        // -- Exec
+        LabelBmc::attach(&ctx, &mm, fx_tasks[0].id, bug).await?;
+        LabelBmc::attach(&ctx, &mm, fx_tasks[0].id, bug).await?; // already attached
+        LabelBmc::attach(&ctx, &mm, fx_tasks[0].id, ui).await?;
+        LabelBmc::attach(&ctx, &mm, fx_tasks[1].id, ui).await?;
+
+        // -- Check
+        let list = |filter: serde_json::Value| {
+            let ctx = &ctx;
+            let mm = &mm;
+            async move {
+                let filters: Vec<TaskFilter> = serde_json::from_value(json!([filter]))?;
+                let ids: Vec<i64> = TaskBmc::list(ctx, mm, Some(filters), None)
+                    .await?
+                    .iter()
+                    .map(|t| t.id)
+                    .collect();
+                anyhow::Ok(ids)
+            }
+        };
+        let prefix = json!({"$startsWith": "test_attach_and_filter_ok"});
+        let any = list(json!({"title": prefix, "label_ids": {"$in": [bug, ui]}})).await?;
+        assert_eq!(any, [fx_tasks[0].id, fx_tasks[1].id]);
+        let all = list(json!({"title": prefix, "label_ids_all": {"$in": [bug, ui]}})).await?;
+        assert_eq!(all, [fx_tasks[0].id]);
+
+        let task = TaskBmc::get(&ctx, &mm, fx_tasks[0].id).await?;
+        let names: Vec<&str> = task.labels.iter().map(|l| l.name.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "test_attach_and_filter_ok-bug",
+                "test_attach_and_filter_ok-ui"
+            ]
+        );
+
+        // -- Exec & Check
+        LabelBmc::detach(&ctx, &mm, fx_tasks[0].id, bug).await?;
+        let task = TaskBmc::get(&ctx, &mm, fx_tasks[0].id).await?;
+        let ids: Vec<i64> = task.labels.iter().map(|l| l.id).collect();
+        assert_eq!(ids, [ui]);
+
+        // -- Clean
+        for task in fx_tasks.iter() {
+            TaskBmc::delete(&ctx, &mm, task.id).await?;
+        }
+        for label in fx_labels.iter() {
+            LabelBmc::delete(&ctx, &mm, label.id).await?;
+        }
+
+        Ok(())
+    }
+}
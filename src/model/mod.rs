@@ -18,20 +18,64 @@
 
 // region:    --- Modules
 
+pub mod attachment;
+pub mod audit_event;
 mod base;
+pub mod blob_store;
+pub mod change_event;
+pub mod comment;
 mod error;
+pub mod label;
+mod modql_utils;
 mod store;
 pub mod task;
+pub mod task_dependency;
+pub mod task_history;
+pub mod task_recurrence;
 pub mod user;
+pub mod workspace;
 
+use self::blob_store::{new_blob_store, BlobStore};
+use self::change_event::{ChangeEvent, ChangeListener};
 pub use self::error::{Error, Result};
 use self::store::{new_db_pool, Db};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
 
 // endregion: --- Modules
 
+/// Tables of `sql/dev_initial/01-create-schema.sql`, checked by `ModelManager::check_db`.
+const SCHEMA_TABLES: &[&str] = &[
+    "user",
+    "workspace",
+    "workspace_member",
+    "task",
+    "task_dependency",
+    "label",
+    "task_label",
+    "comment",
+    "attachment",
+    "blob_orphan",
+    "task_history",
+    "change_event",
+    "audit_event",
+];
+
+/// Statistics of the db pool, for the metrics.
+pub struct PoolStats {
+    pub max: u32,
+    pub in_use: u32,
+    pub idle: u32,
+    /// Time to acquire a connection, None when it timed out.
+    pub acquire_wait: Option<Duration>,
+}
+
 #[derive(Clone)]
 pub struct ModelManager {
     db: Db,
+    blob_store: Arc<dyn BlobStore>,
+    change_listener: ChangeListener,
 }
 
 impl ModelManager {
@@ -40,9 +84,73 @@ impl ModelManager {
         // is automatically mapped to Error from model.
         // ? is equivalent to map_err(|e| Error::from(e))?;
         let db = new_db_pool().await?;
+        let blob_store = new_blob_store()?;
 
         // FIXME: - TBC
-        Ok(ModelManager { db })
+        Ok(ModelManager {
+            db,
+            blob_store,
+            change_listener: ChangeListener::new(),
+        })
+    }
+
+    /// Returns a receiver of the `ChangeEvent` committed from now on
+    /// (for the Bmcs with change events).
+    pub async fn subscribe_changes(&self) -> Result<broadcast::Receiver<ChangeEvent>> {
+        self.change_listener.subscribe().await
+    }
+
+    /// Fails if the db is not reachable, or if the schema is not applied (a table is missing).
+    pub async fn check_db(&self) -> Result<()> {
+        self.check_tables(SCHEMA_TABLES).await
+    }
+
+    async fn check_tables(&self, schema_tables: &[&str]) -> Result<()> {
+        let tables: Vec<String> = sqlx::query_scalar(
+            "SELECT table_name::text FROM information_schema.tables
+             WHERE table_schema = current_schema() AND table_name = ANY($1)",
+        )
+        .bind(schema_tables)
+        .fetch_all(&self.db)
+        .await?;
+
+        let missing_tables: Vec<String> = schema_tables
+            .iter()
+            .filter(|table| !tables.iter().any(|t| t == *table))
+            .map(|table| table.to_string())
+            .collect();
+        if !missing_tables.is_empty() {
+            return Err(Error::SchemaNotApplied { missing_tables });
+        }
+
+        Ok(())
+    }
+
+    /// Note: the acquire wait is measured by acquiring (and releasing) a connection,
+    /// waiting at most `acquire_timeout`.
+    pub async fn pool_stats(&self, acquire_timeout: Duration) -> PoolStats {
+        let size = self.db.size();
+        let idle = self.db.num_idle() as u32;
+
+        let start = Instant::now();
+        let acquire_wait = match tokio::time::timeout(acquire_timeout, self.db.acquire()).await {
+            Ok(Ok(_con)) => Some(start.elapsed()),
+            _ => None,
+        };
+
+        PoolStats {
+            max: self.db.options().get_max_connections(),
+            in_use: size.saturating_sub(idle),
+            idle,
+            acquire_wait,
+        }
+    }
+
+    /// Stops the change listener and closes the db pool, waiting for the connections
+    /// in use to be released. The model calls fail (sqlx `PoolClosed`) from then on.
+    pub async fn close(&self) {
+        self.change_listener.close().await;
+        self.db.close().await;
     }
 
     /// only accessible within the model module
@@ -50,4 +158,53 @@ impl ModelManager {
     pub(in crate::model) fn db(&self) -> &Db {
         &self.db
     }
+
+    /// Same as the db, only for the model layer.
+    pub(in crate::model) fn blob_store(&self) -> &dyn BlobStore {
+        self.blob_store.as_ref()
+    }
+}
+
+// region:    --- Tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::_dev_utils;
+    use anyhow::Result;
+    use serial_test::serial;
+
+    #[serial]
+    #[tokio::test]
+    async fn test_check_db_ok() -> Result<()> {
+        // -- Setup & Fixtures
+        let mm = _dev_utils::init_test().await;
+
+        // -- Exec
+        mm.check_db().await?;
+
+        Ok(())
+    }
+
+    #[serial]
+    #[tokio::test]
+    async fn test_check_db_err_schema_not_applied() -> Result<()> {
+        // -- Setup & Fixtures
+        let mm = _dev_utils::init_test().await;
+        let fx_tables = &["task", "no_such_table"];
+
+        // -- Exec
+        let res = mm.check_tables(fx_tables).await;
+
+        // -- Check
+        assert!(
+            matches!(
+                &res,
+                Err(Error::SchemaNotApplied { missing_tables }) if missing_tables == &["no_such_table"]
+            ),
+            "SchemaNotApplied not matching"
+        );
+
+        Ok(())
+    }
 }
+// endregion: --- Tests
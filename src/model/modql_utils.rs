@@ -0,0 +1,15 @@
+//! Helpers for the modql filters.
+
+use crate::utils::parse_utc;
+use modql::filter::{IntoSeaError, SeaResult};
+
+/// Converts the Rfc3339 json string of a filter into a time sea value, for the
+/// `OffsetDateTime` columns: `#[modql(to_sea_value_fn = "time_to_sea_value")]`.
+pub fn time_to_sea_value(json_value: serde_json::Value) -> SeaResult<sea_query::Value> {
+    let moment = json_value
+        .as_str()
+        .ok_or_else(|| IntoSeaError::Custom(format!("time must be a string: {json_value}")))?;
+    let time = parse_utc(moment).map_err(|ex| IntoSeaError::Custom(ex.to_string()))?;
+
+    Ok(time.into())
+}
@@ -1,43 +1,241 @@
+use crate::config;
 use crate::ctx::Ctx;
+use crate::model::comment::CommentBmc;
+use crate::model::label::{task_label_ids_all, task_label_ids_any, Label, LabelBmc};
+use crate::model::modql_utils::time_to_sea_value;
+use crate::model::task_dependency::TaskDependencyBmc;
+use crate::model::task_recurrence::Recurrence;
 use crate::model::ModelManager;
 use crate::model::{Error, Result};
-use modql::field::Fields;
-use modql::filter::{FilterNodes, ListOptions, OpValsBool, OpValsInt64, OpValsString};
+use crate::utils::{deserialize_time_opt, serialize_time_opt};
+use modql::field::{Field, Fields, HasFields};
+use modql::filter::{
+    FilterGroups, FilterNodes, ListOptions, OpValInt64, OpValsBool, OpValsInt64, OpValsString,
+    OpValsValue,
+};
+use sea_query::{Condition, Expr, Iden, Order, PostgresQueryBuilder, Query, SimpleExpr, Value};
+use sea_query_binder::SqlxBinder;
 use serde::{Deserialize, Serialize};
-use sqlx::FromRow;
+use sqlx::{FromRow, PgConnection};
+use time::OffsetDateTime;
 
-use crate::model::base;
+use crate::model::base::{self, DbBmc};
+
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Serialize,
+    Deserialize,
+    sqlx::Type,
+    strum_macros::AsRefStr,
+)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "task_priority", rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum TaskPriority {
+    Low,
+    #[default]
+    Medium,
+    High,
+    Urgent,
+}
+
+/// Needs `#[field(cast_as = "task_priority")]` to be written in the enum column.
+impl From<TaskPriority> for sea_query::Value {
+    fn from(val: TaskPriority) -> Self {
+        val.as_ref().into()
+    }
+}
+
+impl sea_query::Nullable for TaskPriority {
+    fn null() -> sea_query::Value {
+        sea_query::Value::String(None)
+    }
+}
 
 #[derive(Debug, Clone, Fields, FromRow, Serialize)]
 pub struct Task {
     pub id: i64,
+    pub workspace_id: i64,
     pub title: String,
+    pub parent_id: Option<i64>,
+    /// Markdown
+    pub description: Option<String>,
+    #[field(cast_as = "task_priority")]
+    pub priority: TaskPriority,
+    #[serde(serialize_with = "serialize_time_opt")]
+    pub due_at: Option<OffsetDateTime>,
     pub done: bool,
+    /// Set when `done` becomes true (by the db, see the `task_done_at` trigger).
+    #[serde(serialize_with = "serialize_time_opt")]
+    pub done_at: Option<OffsetDateTime>,
+    pub external_id: Option<String>,
+    /// Rule of the next occurrences, see `Recurrence`.
+    pub recurrence: Option<String>,
+    /// Occurrence this task was generated from, see `TaskRecurrenceBmc`.
+    pub recurrence_prev_id: Option<i64>,
+    pub version: i64,
+    /// Loaded by the `TaskBmc` reads, with one query for all the tasks.
+    #[field(skip)]
+    #[sqlx(skip)]
+    pub labels: Vec<Label>,
+    /// Loaded with the labels.
+    #[field(skip)]
+    #[sqlx(skip)]
+    pub comment_count: i64,
 }
 
-#[derive(Fields, Deserialize)]
+#[derive(Fields, Default, Deserialize)]
 pub struct TaskForCreate {
     pub title: String,
+    pub parent_id: Option<i64>,
+    pub description: Option<String>,
+    #[field(cast_as = "task_priority")]
+    pub priority: Option<TaskPriority>,
+    #[serde(default, deserialize_with = "deserialize_time_opt")]
+    pub due_at: Option<OffsetDateTime>,
+    pub recurrence: Option<Recurrence>,
 }
 
 #[derive(Fields, Default, Deserialize)]
 pub struct TaskForUpdate {
     pub title: Option<String>,
+    pub description: Option<String>,
+    #[field(cast_as = "task_priority")]
+    pub priority: Option<TaskPriority>,
+    #[serde(default, deserialize_with = "deserialize_time_opt")]
+    pub due_at: Option<OffsetDateTime>,
+    pub recurrence: Option<Recurrence>,
+    pub done: Option<bool>,
+}
+
+/// Task identified by the key of an external tracker, see `TaskBmc::upsert`.
+#[derive(Fields, Default, Deserialize)]
+pub struct TaskForUpsert {
+    pub external_id: String,
+    pub title: String,
+    pub description: Option<String>,
+    #[field(cast_as = "task_priority")]
+    pub priority: Option<TaskPriority>,
+    #[serde(default, deserialize_with = "deserialize_time_opt")]
+    pub due_at: Option<OffsetDateTime>,
+    pub recurrence: Option<Recurrence>,
     pub done: Option<bool>,
 }
 
+/// The time filters take Rfc3339 strings, e.g., `{"due_at": {"$lt": "2024-03-01T00:00:00Z"}}`.
 #[derive(FilterNodes, Deserialize, Default, Debug)]
 pub struct TaskFilter {
     id: Option<OpValsInt64>,
 
     title: Option<OpValsString>,
+    parent_id: Option<OpValsInt64>,
+    description: Option<OpValsString>,
+    #[modql(cast_as = "task_priority")]
+    priority: Option<OpValsString>,
+    #[modql(to_sea_value_fn = "time_to_sea_value")]
+    due_at: Option<OpValsValue>,
     done: Option<OpValsBool>,
+    #[modql(to_sea_value_fn = "time_to_sea_value")]
+    done_at: Option<OpValsValue>,
+    external_id: Option<OpValsString>,
+    recurrence: Option<OpValsString>,
+    /// Has any of the labels, see `task_label_ids_any`.
+    #[modql(to_sea_condition_fn = "task_label_ids_any")]
+    label_ids: Option<OpValsValue>,
+    /// Has all of the labels, see `task_label_ids_all`.
+    #[modql(to_sea_condition_fn = "task_label_ids_all")]
+    label_ids_all: Option<OpValsValue>,
+}
+
+/// The parent of a task, as a sea value so that `None` is written as `NULL`
+/// (see `TaskBmc::set_parent`).
+#[derive(Fields)]
+struct TaskForParent {
+    parent_id: sea_query::Value,
+}
+
+/// Subtasks and blockers of a task, see `TaskBmc::get_graph`.
+#[derive(Debug, Serialize)]
+pub struct TaskGraph {
+    pub task: Task,
+    /// Subtasks, with their subtasks and so on (the tree is given by their `parent_id`).
+    pub subtasks: Vec<Task>,
+    /// Blockers, with their blockers and so on.
+    pub blockers: Vec<Task>,
+}
+
+#[derive(Iden)]
+enum TaskIden {
+    ExternalId,
+    Rank,
+    Snippet,
+}
+
+/// Task matching a `TaskBmc::search`.
+#[derive(Debug, FromRow, Serialize)]
+pub struct TaskSearchHit {
+    #[serde(flatten)]
+    #[sqlx(flatten)]
+    pub task: Task,
+    pub rank: f32,
+    /// Title and description with the matching words highlighted with `<b>..</b>`.
+    pub snippet: String,
 }
 
 pub struct TaskBmc;
 
-impl base::DbBmc for TaskBmc {
+impl DbBmc for TaskBmc {
     const TABLE: &'static str = "task";
+
+    fn has_workspace() -> bool {
+        true
+    }
+
+    fn has_version() -> bool {
+        true
+    }
+
+    fn has_history() -> bool {
+        true
+    }
+
+    fn has_change_events() -> bool {
+        true
+    }
+
+    fn row_json_excluded() -> &'static [&'static str] {
+        &["search", "recurred_at"]
+    }
+
+    /// Setting `done` requires no open blockers, and setting `parent_id`
+    /// must not make a cycle (for all the write paths).
+    async fn check_update(
+        _ctx: &Ctx,
+        con: &mut PgConnection,
+        ids: &[i64],
+        fields: &[Field],
+    ) -> Result<()> {
+        for field in fields {
+            match (field.iden.to_string().as_str(), &field.value) {
+                ("done", SimpleExpr::Value(Value::Bool(Some(true)))) => {
+                    check_no_open_blockers(con, ids).await?
+                }
+                ("parent_id", SimpleExpr::Value(Value::BigInt(Some(parent_id)))) => {
+                    check_no_parent_cycle(con, ids, *parent_id).await?
+                }
+                _ => (),
+            }
+        }
+
+        Ok(())
+    }
 }
 
 impl TaskBmc {
@@ -51,12 +249,26 @@ impl TaskBmc {
         //         .fetch_one(db)
         //         .await?;
         // Ok(id)
+        check_parents_visible(ctx, mm, [&task_c]).await?;
         base::create::<Self, _>(ctx, mm, task_c).await
     }
 
+    /// Creates the task and returns it, in a single round-trip.
+    pub async fn create_returning(
+        ctx: &Ctx,
+        mm: &ModelManager,
+        task_c: TaskForCreate,
+    ) -> Result<Task> {
+        check_parents_visible(ctx, mm, [&task_c]).await?;
+        base::create_returning::<Self, _, _>(ctx, mm, task_c).await
+    }
+
     pub async fn get(ctx: &Ctx, mm: &ModelManager, id: i64) -> Result<Task> {
         // compiler understands that _ is Task
-        base::get::<Self, _>(ctx, mm, id).await
+        let mut task = base::get::<Self, _>(ctx, mm, id).await?;
+        load_relations(ctx, mm, vec![&mut task]).await?;
+
+        Ok(task)
     }
 
     pub async fn list(
@@ -70,16 +282,60 @@ impl TaskBmc {
         // let tasks: Vec<Task> = sqlx::query_as("SELECT * FROM task ORDER BY id")
         //     .fetch_all(db)
         //     .await?;
-        base::list::<Self, _, _>(ctx, mm, filters, list_options).await
+        let mut tasks = base::list::<Self, _, _>(ctx, mm, filters, list_options).await?;
+        load_relations(ctx, mm, tasks.iter_mut().collect()).await?;
+
+        Ok(tasks)
+    }
+
+    /// Tasks with the given ids, ordered by id.
+    pub async fn list_by_ids(ctx: &Ctx, mm: &ModelManager, ids: Vec<i64>) -> Result<Vec<Task>> {
+        if ids.is_empty() {
+            return Ok(Vec::new());
+        }
+        let list_options = ListOptions {
+            limit: Some(ids.len() as i64),
+            offset: None,
+            order_bys: Some("id".into()),
+        };
+        let filter = TaskFilter {
+            id: Some(OpValInt64::In(ids).into()),
+            ..Default::default()
+        };
+
+        let mut tasks =
+            base::list::<Self, _, _>(ctx, mm, Some(vec![filter]), Some(list_options)).await?;
+        load_relations(ctx, mm, tasks.iter_mut().collect()).await?;
+
+        Ok(tasks)
     }
 
+    /// When `version` is given, fails with `EntityVersionConflict` if the task
+    /// was updated in between.
+    /// Fails with `TaskHasOpenBlockers` when setting done a task which blockers are
+    /// not all done (unless disabled in the config).
     pub async fn update(
         ctx: &Ctx,
         mm: &ModelManager,
         id: i64,
+        version: Option<i64>,
         task_u: TaskForUpdate,
     ) -> Result<()> {
-        base::update::<Self, _>(ctx, mm, id, task_u).await
+        base::update::<Self, _>(ctx, mm, id, version, task_u).await
+    }
+
+    /// Same as `update`, but returns the updated task, in a single round-trip.
+    pub async fn update_returning(
+        ctx: &Ctx,
+        mm: &ModelManager,
+        id: i64,
+        version: Option<i64>,
+        task_u: TaskForUpdate,
+    ) -> Result<Task> {
+        let mut task = base::update_returning::<Self, _, _>(ctx, mm, id, version, task_u).await?;
+        load_relations(ctx, mm, vec![&mut task]).await?;
+
+        Ok(task)
     }
 
     pub async fn delete(ctx: &Ctx, mm: &ModelManager, id: i64) -> Result<()> {
@@ -97,8 +353,329 @@ impl TaskBmc {
         // Ok(())
         base::delete::<Self>(ctx, mm, id).await
     }
+
+    /// Moves the task under `parent_id` (or to the top level when `None`).
+    /// Fails with `TaskParentCycle` if the parent is the task or one of its subtasks.
+    pub async fn set_parent(
+        ctx: &Ctx,
+        mm: &ModelManager,
+        id: i64,
+        parent_id: Option<i64>,
+    ) -> Result<Task> {
+        if let Some(parent_id) = parent_id {
+            // for the EntityNotFound error
+            Self::get(ctx, mm, parent_id).await?;
+        }
+
+        // (the cycle check is in the transaction of the update, see `check_update`)
+        let task_p = TaskForParent {
+            parent_id: parent_id.into(),
+        };
+        let mut task = base::update_returning::<Self, _, _>(ctx, mm, id, None, task_p).await?;
+        load_relations(ctx, mm, vec![&mut task]).await?;
+
+        Ok(task)
+    }
+
+    /// The task with all its subtasks and all its blockers (transitively).
+    pub async fn get_graph(ctx: &Ctx, mm: &ModelManager, id: i64) -> Result<TaskGraph> {
+        let task = Self::get(ctx, mm, id).await?;
+
+        let subtask_ids = Self::subtask_ids(mm, id).await?;
+        let subtasks = Self::list_by_ids(ctx, mm, subtask_ids).await?;
+        let blocker_ids = TaskDependencyBmc::list_blocker_ids(ctx, mm, id).await?;
+        let blockers = Self::list_by_ids(ctx, mm, blocker_ids).await?;
+
+        Ok(TaskGraph {
+            task,
+            subtasks,
+            blockers,
+        })
+    }
+
+    /// Ids of the subtasks, their subtasks and so on, ordered by id.
+    async fn subtask_ids(mm: &ModelManager, id: i64) -> Result<Vec<i64>> {
+        let mut con = mm.db().acquire().await?;
+        subtask_ids_of(&mut con, id).await
+    }
+
+    /// Creates the task, or updates the one with the same `external_id`.
+    /// Returns the task, with `true` when it was created.
+    pub async fn upsert(
+        ctx: &Ctx,
+        mm: &ModelManager,
+        task_up: TaskForUpsert,
+    ) -> Result<(Task, bool)> {
+        let (mut task, inserted) =
+            base::upsert::<Self, _, _>(ctx, mm, TaskIden::ExternalId, task_up).await?;
+        load_relations(ctx, mm, vec![&mut task]).await?;
+
+        Ok((task, inserted))
+    }
+
+    /// Full text search on the title and description, most relevant first
+    /// (title matches weigh more).
+    /// `search` words must all match, `"..."` is a phrase, and `word*` a prefix.
+    /// The hits are further restricted by `filters`, and `list_options` apply after
+    /// the ranking (limit, offset, secondary order).
+    pub async fn search(
+        ctx: &Ctx,
+        mm: &ModelManager,
+        search: &str,
+        filters: Option<Vec<TaskFilter>>,
+        list_options: Option<ListOptions>,
+    ) -> Result<Vec<TaskSearchHit>> {
+        let db = mm.db();
+
+        let Some(tsquery) = tsquery_text(search) else {
+            return Ok(Vec::new());
+        };
+        let tsquery = Expr::cust_with_values("to_tsquery('english', $1)", [tsquery]);
+
+        // -- Build query
+        let mut cond =
+            Condition::all().add(Expr::cust_with_expr("\"search\" @@ $1", tsquery.clone()));
+        if let Some(filters) = filters {
+            let filters: FilterGroups = filters.into();
+            let filter_cond: Condition = filters.try_into()?;
+            cond = cond.add(filter_cond);
+        }
+        if let Some(workspace_cond) = base::workspace_cond::<Self>(ctx)? {
+            cond = cond.add(workspace_cond);
+        }
+
+        let mut query = Query::select();
+        query
+            .from(Self::table_ref())
+            .columns(Task::field_column_refs())
+            .expr_as(
+                Expr::cust_with_expr("ts_rank(\"search\", $1)", tsquery.clone()),
+                TaskIden::Rank,
+            )
+            .expr_as(
+                Expr::cust_with_expr(
+                    "ts_headline('english', concat_ws(' ', \"title\", \"description\"), $1)",
+                    tsquery,
+                ),
+                TaskIden::Snippet,
+            )
+            .cond_where(cond)
+            .order_by(TaskIden::Rank, Order::Desc);
+
+        let list_options = base::finalize_list_options(list_options)?;
+        list_options.apply_to_sea_query(&mut query);
+
+        // -- Exec query
+        let (sql, values) = query.build_sqlx(PostgresQueryBuilder);
+        let mut hits = sqlx::query_as_with::<_, TaskSearchHit, _>(&sql, values)
+            .fetch_all(db)
+            .await?;
+        load_relations(ctx, mm, hits.iter_mut().map(|hit| &mut hit.task).collect()).await?;
+
+        Ok(hits)
+    }
+
+    // -- Bulk
+
+    /// Creates all the tasks atomically, returns their ids in the same order.
+    pub async fn create_many(
+        ctx: &Ctx,
+        mm: &ModelManager,
+        tasks_c: Vec<TaskForCreate>,
+    ) -> Result<Vec<i64>> {
+        check_parents_visible(ctx, mm, &tasks_c).await?;
+        base::create_many::<Self, _>(ctx, mm, tasks_c).await
+    }
+
+    /// Same as `create_many`, but returns the created tasks, in the same order.
+    pub async fn create_many_returning(
+        ctx: &Ctx,
+        mm: &ModelManager,
+        tasks_c: Vec<TaskForCreate>,
+    ) -> Result<Vec<Task>> {
+        check_parents_visible(ctx, mm, &tasks_c).await?;
+        let mut tasks = base::create_many_returning::<Self, _, _>(ctx, mm, tasks_c).await?;
+        load_relations(ctx, mm, tasks.iter_mut().collect()).await?;
+
+        Ok(tasks)
+    }
+
+    /// Updates all the tasks matching the filters atomically, returns their ids.
+    pub async fn update_many(
+        ctx: &Ctx,
+        mm: &ModelManager,
+        filters: Vec<TaskFilter>,
+        task_u: TaskForUpdate,
+    ) -> Result<Vec<i64>> {
+        base::update_many::<Self, _, _>(ctx, mm, filters, task_u).await
+    }
+
+    /// Same as `update_many`, but returns the updated tasks, ordered by id.
+    pub async fn update_many_returning(
+        ctx: &Ctx,
+        mm: &ModelManager,
+        filters: Vec<TaskFilter>,
+        task_u: TaskForUpdate,
+    ) -> Result<Vec<Task>> {
+        let mut tasks =
+            base::update_many_returning::<Self, _, _, _>(ctx, mm, filters, task_u).await?;
+        load_relations(ctx, mm, tasks.iter_mut().collect()).await?;
+
+        Ok(tasks)
+    }
+
+    /// Deletes all the tasks matching the filters atomically, returns their ids.
+    pub async fn delete_many(
+        ctx: &Ctx,
+        mm: &ModelManager,
+        filters: Vec<TaskFilter>,
+    ) -> Result<Vec<i64>> {
+        base::delete_many::<Self, _>(ctx, mm, filters).await
+    }
+}
+
+/// Fails with `TaskHasOpenBlockers` if some of the blockers of the tasks are not done,
+/// when `TASK_DONE_REQUIRES_NO_OPEN_BLOCKERS` is set.
+async fn check_no_open_blockers(con: &mut PgConnection, ids: &[i64]) -> Result<()> {
+    if !config().TASK_DONE_REQUIRES_NO_OPEN_BLOCKERS {
+        return Ok(());
+    }
+
+    let open_blockers = TaskDependencyBmc::open_blocker_ids_of(con, ids).await?;
+    if let Some((id, _)) = open_blockers.first() {
+        let id = *id;
+        let blocker_ids = open_blockers
+            .iter()
+            .filter(|(task_id, _)| *task_id == id)
+            .map(|(_, blocker_id)| *blocker_id)
+            .collect();
+        return Err(Error::TaskHasOpenBlockers { id, blocker_ids });
+    }
+
+    Ok(())
+}
+
+/// Fails with `TaskParentCycle` if the parent is one of the tasks or of their subtasks.
+/// Serializes the parent changes (transaction advisory lock), so that two concurrent
+/// changes can't make a cycle which none of them would see.
+async fn check_no_parent_cycle(con: &mut PgConnection, ids: &[i64], parent_id: i64) -> Result<()> {
+    sqlx::query("SELECT pg_advisory_xact_lock(hashtext('task.parent_id'))")
+        .execute(&mut *con)
+        .await?;
+
+    for &id in ids {
+        if parent_id == id || subtask_ids_of(con, id).await?.contains(&parent_id) {
+            return Err(Error::TaskParentCycle { id, parent_id });
+        }
+    }
+
+    Ok(())
 }
 
+/// Ids of the subtasks, their subtasks and so on, ordered by id.
+async fn subtask_ids_of(con: &mut PgConnection, id: i64) -> Result<Vec<i64>> {
+    // `UNION` rather than `UNION ALL`, so that it would terminate even on a cycle
+    let sql = r#"
+        WITH RECURSIVE subtasks(id) AS (
+            SELECT id FROM task WHERE parent_id = $1
+            UNION
+            SELECT t.id FROM task t
+            JOIN subtasks s ON t.parent_id = s.id
+        )
+        SELECT id FROM subtasks ORDER BY id"#;
+
+    let ids = sqlx::query_scalar::<_, i64>(sql)
+        .bind(id)
+        .fetch_all(con)
+        .await?;
+
+    Ok(ids)
+}
+
+/// Fails with `EntityNotFound` if the parent of one of the tasks to create
+/// is not accessible (e.g., in another workspace).
+async fn check_parents_visible<'a>(
+    ctx: &Ctx,
+    mm: &ModelManager,
+    tasks_c: impl IntoIterator<Item = &'a TaskForCreate>,
+) -> Result<()> {
+    let mut parent_ids: Vec<i64> = tasks_c.into_iter().filter_map(|t| t.parent_id).collect();
+    parent_ids.sort_unstable();
+    parent_ids.dedup();
+    if parent_ids.is_empty() {
+        return Ok(());
+    }
+
+    let parents = TaskBmc::list_by_ids(ctx, mm, parent_ids.clone()).await?;
+    match parent_ids
+        .into_iter()
+        .find(|id| !parents.iter().any(|parent| parent.id == *id))
+    {
+        Some(id) => Err(Error::EntityNotFound {
+            entity: TaskBmc::TABLE,
+            id,
+        }),
+        None => Ok(()),
+    }
+}
+
+/// Sets the labels and the comment counts of the tasks, with a query for each.
+async fn load_relations(ctx: &Ctx, mm: &ModelManager, tasks: Vec<&mut Task>) -> Result<()> {
+    if tasks.is_empty() {
+        return Ok(());
+    }
+
+    let task_ids: Vec<i64> = tasks.iter().map(|task| task.id).collect();
+    let mut labels_by_task = LabelBmc::list_by_task_ids(ctx, mm, task_ids.clone()).await?;
+    let comment_counts = CommentBmc::count_by_task_ids(ctx, mm, task_ids).await?;
+    for task in tasks {
+        task.labels = labels_by_task.remove(&task.id).unwrap_or_default();
+        task.comment_count = comment_counts.get(&task.id).copied().unwrap_or_default();
+    }
+
+    Ok(())
+}
+
+// region:    --- Search
+
+/// Turns the search text into a `to_tsquery` query, `None` when it has no words.
+/// - `buy milk` -> `buy & milk`
+/// - `"buy milk"` -> `(buy <-> milk)`
+/// - `mil*` -> `mil:*`
+fn tsquery_text(search: &str) -> Option<String> {
+    let mut terms = Vec::new();
+
+    // the odd parts are the ones between quotes
+    for (i, part) in search.split('"').enumerate() {
+        let words = part.split_whitespace().filter_map(tsquery_word);
+        if i % 2 == 1 {
+            let words: Vec<String> = words.collect();
+            if !words.is_empty() {
+                terms.push(format!("({})", words.join(" <-> ")));
+            }
+        } else {
+            terms.extend(words);
+        }
+    }
+
+    (!terms.is_empty()).then(|| terms.join(" & "))
+}
+
+/// Only keeps the letters and digits of the word, so that the user can't inject
+/// tsquery operators.
+fn tsquery_word(word: &str) -> Option<String> {
+    let is_prefix = word.ends_with('*');
+    let word: String = word.chars().filter(|c| c.is_alphanumeric()).collect();
+
+    match (word.is_empty(), is_prefix) {
+        (true, _) => None,
+        (false, true) => Some(format!("{word}:*")),
+        (false, false) => Some(word),
+    }
+}
+
+// endregion: --- Search
+
 #[cfg(test)]
 mod tests {
     #![allow(unused)]
@@ -106,6 +683,8 @@ mod tests {
     use crate::_dev_utils;
 
     use super::*;
+    use crate::model::task_history::TaskHistoryBmc;
+    use crate::model::workspace::{WorkspaceBmc, WorkspaceForCreate};
     use anyhow::Result;
     use serde_json::json;
     use serial_test::serial;
@@ -115,12 +694,13 @@ mod tests {
     async fn test_create_ok() -> Result<()> {
         // -- Setup and fixtures
         let mm = _dev_utils::init_test().await;
-        let ctx = Ctx::root_ctx();
+        let ctx = _dev_utils::demo_ctx();
         let fx_title = "test_create_ok title";
 
         // -- Exec
         let task_c = TaskForCreate {
             title: fx_title.to_string(),
+            ..Default::default()
         };
         let id = TaskBmc::create(&ctx, &mm, task_c).await?;
 
@@ -138,7 +718,7 @@ mod tests {
     #[tokio::test]
     async fn test_get_err_not_found() -> Result<()> {
         let mm = _dev_utils::init_test().await;
-        let ctx = Ctx::root_ctx();
+        let ctx = _dev_utils::demo_ctx();
         let fx_id = 100;
 
         let res = TaskBmc::get(&ctx, &mm, fx_id).await;
@@ -160,7 +740,7 @@ mod tests {
     async fn test_list_all_ok() -> Result<()> {
         // -- Setup & Fixtures
         let mm = _dev_utils::init_test().await;
-        let ctx = Ctx::root_ctx();
+        let ctx = _dev_utils::demo_ctx();
         let fx_titles = &["test_list_all_ok-task 01", "test_list_all_ok-task 02"];
         _dev_utils::seed_tasks(&ctx, &mm, fx_titles).await?;
 
@@ -187,7 +767,7 @@ mod tests {
     async fn test_list_by_filter_ok() -> Result<()> {
         // -- Setup & Fixtures
         let mm = _dev_utils::init_test().await;
-        let ctx = Ctx::root_ctx();
+        let ctx = _dev_utils::demo_ctx();
         let fx_titles = &[
             "test_list_by_filter_ok-task 01.a",
             "test_list_by_filter_ok-task 01.b",
@@ -243,7 +823,7 @@ mod tests {
     #[tokio::test]
     async fn test_update_ok() -> Result<()> {
         let mm = _dev_utils::init_test().await;
-        let ctx = Ctx::root_ctx();
+        let ctx = _dev_utils::demo_ctx();
         let fx_title = "test_update_ok-task 01";
         let fx_title_new = "test_update_ok-task 01-new";
         let fx_task = _dev_utils::seed_tasks(&ctx, &mm, &[fx_title])
@@ -254,6 +834,7 @@ mod tests {
             &ctx,
             &mm,
             fx_task.id,
+            None,
             TaskForUpdate {
                 title: Some(fx_title_new.to_string()),
                 ..Default::default()
@@ -263,13 +844,76 @@ mod tests {
 
         let task = TaskBmc::get(&ctx, &mm, fx_task.id).await?;
         assert_eq!(task.title, fx_title_new);
+        assert_eq!(task.version, fx_task.version + 1);
         Ok(())
     }
+
+    #[serial]
+    #[tokio::test]
+    async fn test_update_err_version_conflict() -> Result<()> {
+        // -- Setup & Fixtures
+        let mm = _dev_utils::init_test().await;
+        let ctx = _dev_utils::demo_ctx();
+        let fx_title = "test_update_err_version_conflict-task 01";
+        let fx_task = _dev_utils::seed_tasks(&ctx, &mm, &[fx_title])
+            .await?
+            .remove(0);
+        let fx_version = fx_task.version;
+
+        // first update with the right version goes through
+        TaskBmc::update(
+            &ctx,
+            &mm,
+            fx_task.id,
+            Some(fx_version),
+            TaskForUpdate {
+                title: Some(format!("{fx_title}-first")),
+                ..Default::default()
+            },
+        )
+        .await?;
+
+        // -- Exec
+        // second update still expects the original version
+        let res = TaskBmc::update(
+            &ctx,
+            &mm,
+            fx_task.id,
+            Some(fx_version),
+            TaskForUpdate {
+                title: Some(format!("{fx_title}-second")),
+                ..Default::default()
+            },
+        )
+        .await;
+
+        // -- Check
+        let current = fx_version + 1;
+        assert!(
+            matches!(
+                res,
+                Err(Error::EntityVersionConflict {
+                    entity: "task",
+                    current_version,
+                    ..
+                }) if current_version == current
+            ),
+            "EntityVersionConflict not matching"
+        );
+        let task = TaskBmc::get(&ctx, &mm, fx_task.id).await?;
+        assert_eq!(task.title, format!("{fx_title}-first"));
+
+        // -- Clean
+        TaskBmc::delete(&ctx, &mm, fx_task.id).await?;
+
+        Ok(())
+    }
+
     #[serial]
     #[tokio::test]
     async fn test_delete_err_not_found() -> Result<()> {
         let mm = _dev_utils::init_test().await;
-        let ctx = Ctx::root_ctx();
+        let ctx = _dev_utils::demo_ctx();
         let fx_id = 100;
 
         let res = TaskBmc::delete(&ctx, &mm, fx_id).await;
@@ -285,4 +929,634 @@ mod tests {
         );
         Ok(())
     }
+
+    #[serial]
+    #[tokio::test]
+    async fn test_create_many_ok() -> Result<()> {
+        // -- Setup & Fixtures
+        let mm = _dev_utils::init_test().await;
+        let ctx = _dev_utils::demo_ctx();
+        let fx_titles = ["test_create_many_ok-task 01", "test_create_many_ok-task 02"];
+
+        // -- Exec
+        let tasks_c = fx_titles
+            .iter()
+            .map(|title| TaskForCreate {
+                title: title.to_string(),
+                ..Default::default()
+            })
+            .collect();
+        let ids = TaskBmc::create_many(&ctx, &mm, tasks_c).await?;
+
+        // -- Check
+        let tasks = TaskBmc::list_by_ids(&ctx, &mm, ids.clone()).await?;
+        let titles: Vec<&str> = tasks.iter().map(|t| t.title.as_str()).collect();
+        assert_eq!(titles, fx_titles);
+        assert!(tasks.iter().all(|t| !t.done && t.version == 0));
+
+        // -- Clean
+        for id in ids {
+            TaskBmc::delete(&ctx, &mm, id).await?;
+        }
+
+        Ok(())
+    }
+
+    #[serial]
+    #[tokio::test]
+    async fn test_create_many_and_update_many_returning_ok() -> Result<()> {
+        // -- Setup & Fixtures
+        let mm = _dev_utils::init_test().await;
+        let ctx = _dev_utils::demo_ctx();
+        let fx_titles = [
+            "test_many_returning_ok-task 02",
+            "test_many_returning_ok-task 01",
+        ];
+        let fx_tasks_c = fx_titles
+            .iter()
+            .map(|title| TaskForCreate {
+                title: title.to_string(),
+                ..Default::default()
+            })
+            .collect();
+        let fx_filters: Vec<TaskFilter> = serde_json::from_value(json!([{
+            "title": {"$startsWith": "test_many_returning_ok"}
+        }]))?;
+
+        // -- Exec
+        let created = TaskBmc::create_many_returning(&ctx, &mm, fx_tasks_c).await?;
+        let updated = TaskBmc::update_many_returning(
+            &ctx,
+            &mm,
+            fx_filters,
+            TaskForUpdate {
+                done: Some(true),
+                ..Default::default()
+            },
+        )
+        .await?;
+
+        // -- Check
+        let titles: Vec<&str> = created.iter().map(|t| t.title.as_str()).collect();
+        assert_eq!(titles, fx_titles);
+        assert!(created.iter().all(|t| !t.done && t.version == 0));
+        let ids: Vec<i64> = updated.iter().map(|t| t.id).collect();
+        assert_eq!(ids, [created[0].id, created[1].id]);
+        assert!(updated.iter().all(|t| t.done && t.version == 1));
+
+        // -- Clean
+        for task in created {
+            TaskBmc::delete(&ctx, &mm, task.id).await?;
+        }
+
+        Ok(())
+    }
+
+    #[serial]
+    #[tokio::test]
+    async fn test_update_many_and_delete_many_ok() -> Result<()> {
+        // -- Setup & Fixtures
+        let mm = _dev_utils::init_test().await;
+        let ctx = _dev_utils::demo_ctx();
+        let fx_titles = &[
+            "test_update_many_ok-task 01.a",
+            "test_update_many_ok-task 01.b",
+            "test_update_many_ok-task 02",
+        ];
+        let fx_tasks = _dev_utils::seed_tasks(&ctx, &mm, fx_titles).await?;
+        let fx_filters: Vec<TaskFilter> = serde_json::from_value(json!([{
+            "title": {"$startsWith": "test_update_many_ok-task 01"}
+        }]))?;
+
+        // -- Exec
+        let updated_ids = TaskBmc::update_many(
+            &ctx,
+            &mm,
+            fx_filters,
+            TaskForUpdate {
+                done: Some(true),
+                ..Default::default()
+            },
+        )
+        .await?;
+
+        // -- Check
+        assert_eq!(updated_ids, [fx_tasks[0].id, fx_tasks[1].id]);
+        let tasks =
+            TaskBmc::list_by_ids(&ctx, &mm, fx_tasks.iter().map(|t| t.id).collect()).await?;
+        let done: Vec<bool> = tasks.iter().map(|t| t.done).collect();
+        assert_eq!(done, [true, true, false]);
+        assert_eq!(tasks[0].version, fx_tasks[0].version + 1);
+        assert_eq!(tasks[2].version, fx_tasks[2].version);
+
+        // -- Exec
+        let fx_filters: Vec<TaskFilter> = serde_json::from_value(json!([{
+            "title": {"$startsWith": "test_update_many_ok"}
+        }]))?;
+        let deleted_ids = TaskBmc::delete_many(&ctx, &mm, fx_filters).await?;
+
+        // -- Check
+        let fx_ids: Vec<i64> = fx_tasks.iter().map(|t| t.id).collect();
+        assert_eq!(deleted_ids, fx_ids);
+        assert!(TaskBmc::list_by_ids(&ctx, &mm, fx_ids).await?.is_empty());
+
+        Ok(())
+    }
+
+    #[serial]
+    #[tokio::test]
+    async fn test_update_many_and_delete_many_err_filter_empty() -> Result<()> {
+        // -- Setup & Fixtures
+        let mm = _dev_utils::init_test().await;
+        let ctx = _dev_utils::demo_ctx();
+        let fx_tasks =
+            _dev_utils::seed_tasks(&ctx, &mm, &["test_update_many_err_filter_empty-task 01"])
+                .await?;
+        let fx_filters_cases = [
+            json!([]),
+            json!([{}]),
+            json!([{"id": {"$eq": fx_tasks[0].id}}, {}]),
+            json!([{"title": {}}]),
+        ];
+
+        for fx_filters in fx_filters_cases {
+            let fx_filters: Vec<TaskFilter> = serde_json::from_value(fx_filters)?;
+
+            // -- Exec
+            let res_update = TaskBmc::update_many(
+                &ctx,
+                &mm,
+                fx_filters,
+                TaskForUpdate {
+                    done: Some(true),
+                    ..Default::default()
+                },
+            )
+            .await;
+
+            // -- Check
+            assert!(
+                matches!(res_update, Err(Error::BulkFilterEmpty { entity: "task" })),
+                "BulkFilterEmpty not matching"
+            );
+        }
+        let res_delete =
+            TaskBmc::delete_many(&ctx, &mm, serde_json::from_value(json!([{}]))?).await;
+        assert!(
+            matches!(res_delete, Err(Error::BulkFilterEmpty { entity: "task" })),
+            "BulkFilterEmpty not matching"
+        );
+        assert!(!TaskBmc::get(&ctx, &mm, fx_tasks[0].id).await?.done);
+
+        // -- Clean
+        TaskBmc::delete(&ctx, &mm, fx_tasks[0].id).await?;
+
+        Ok(())
+    }
+
+    #[serial]
+    #[tokio::test]
+    async fn test_create_and_update_returning_ok() -> Result<()> {
+        // -- Setup & Fixtures
+        let mm = _dev_utils::init_test().await;
+        let ctx = _dev_utils::demo_ctx();
+        let fx_title = "test_create_and_update_returning_ok-task 01";
+
+        // -- Exec
+        let task = TaskBmc::create_returning(
+            &ctx,
+            &mm,
+            TaskForCreate {
+                title: fx_title.to_string(),
+                ..Default::default()
+            },
+        )
+        .await?;
+        let task_u = TaskBmc::update_returning(
+            &ctx,
+            &mm,
+            task.id,
+            Some(task.version),
+            TaskForUpdate {
+                done: Some(true),
+                ..Default::default()
+            },
+        )
+        .await?;
+
+        // -- Check
+        assert_eq!(task.title, fx_title);
+        assert!(!task.done);
+        assert_eq!(task_u.id, task.id);
+        assert!(task_u.done);
+        assert_eq!(task_u.version, task.version + 1);
+        let task_db = TaskBmc::get(&ctx, &mm, task.id).await?;
+        assert_eq!(task_db.version, task_u.version);
+
+        // -- Clean
+        TaskBmc::delete(&ctx, &mm, task.id).await?;
+
+        Ok(())
+    }
+
+    #[serial]
+    #[tokio::test]
+    async fn test_upsert_ok() -> Result<()> {
+        // -- Setup & Fixtures
+        let mm = _dev_utils::init_test().await;
+        let ctx = _dev_utils::demo_ctx();
+        let fx_external_id = "test_upsert_ok-ext 01";
+        let fx_title = "test_upsert_ok-task 01";
+        let fx_title_new = "test_upsert_ok-task 01-new";
+
+        // -- Exec
+        let (task, inserted) = TaskBmc::upsert(
+            &ctx,
+            &mm,
+            TaskForUpsert {
+                external_id: fx_external_id.to_string(),
+                title: fx_title.to_string(),
+                ..Default::default()
+            },
+        )
+        .await?;
+        let (task_u, inserted_u) = TaskBmc::upsert(
+            &ctx,
+            &mm,
+            TaskForUpsert {
+                external_id: fx_external_id.to_string(),
+                title: fx_title_new.to_string(),
+                done: Some(true),
+                ..Default::default()
+            },
+        )
+        .await?;
+
+        // -- Check
+        assert!(inserted);
+        assert_eq!(task.external_id.as_deref(), Some(fx_external_id));
+        assert!(!inserted_u);
+        assert_eq!(task_u.id, task.id);
+        assert_eq!(task_u.title, fx_title_new);
+        assert!(task_u.done);
+        assert_eq!(task_u.version, task.version + 1);
+
+        // -- Clean
+        TaskBmc::delete(&ctx, &mm, task.id).await?;
+
+        Ok(())
+    }
+
+    #[serial]
+    #[tokio::test]
+    async fn test_upsert_concurrent_new_key_ok() -> Result<()> {
+        // -- Setup & Fixtures
+        let mm = _dev_utils::init_test().await;
+        let ctx = _dev_utils::demo_ctx();
+        let fx_external_id = "test_upsert_concurrent_new_key_ok-ext 01";
+        let fx_titles = [
+            "test_upsert_concurrent_new_key_ok-task 01",
+            "test_upsert_concurrent_new_key_ok-task 02",
+        ];
+        let fx_upsert = |title: &str| TaskForUpsert {
+            external_id: fx_external_id.to_string(),
+            title: title.to_string(),
+            ..Default::default()
+        };
+        // (the pool has a single connection)
+        let mm_2 = ModelManager::new().await?;
+        // keeps the first insert uncommitted for a while
+        for sql in [
+            "CREATE FUNCTION test_upsert_slow() RETURNS trigger AS $$
+             BEGIN PERFORM pg_sleep(0.3); RETURN NULL; END $$ LANGUAGE plpgsql"
+                .to_string(),
+            format!(
+                "CREATE TRIGGER test_upsert_slow AFTER INSERT ON task
+                 FOR EACH ROW WHEN (NEW.title = '{}') EXECUTE FUNCTION test_upsert_slow()",
+                fx_titles[0]
+            ),
+        ] {
+            sqlx::query(&sql).execute(mm.db()).await?;
+        }
+
+        // -- Exec
+        let (res_1, res_2) =
+            tokio::join!(TaskBmc::upsert(&ctx, &mm, fx_upsert(fx_titles[0])), async {
+                tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+                TaskBmc::upsert(&ctx, &mm_2, fx_upsert(fx_titles[1])).await
+            },);
+        for sql in [
+            "DROP TRIGGER test_upsert_slow ON task",
+            "DROP FUNCTION test_upsert_slow()",
+        ] {
+            sqlx::query(sql).execute(mm.db()).await?;
+        }
+        let ((task_1, inserted_1), (task_2, inserted_2)) = (res_1?, res_2?);
+
+        // -- Check
+        assert!(inserted_1);
+        assert!(!inserted_2);
+        assert_eq!(task_2.id, task_1.id);
+        let revisions = TaskHistoryBmc::list_for(&ctx, &mm, "task", task_1.id).await?;
+        let ops: Vec<&str> = revisions.iter().map(|r| r.op.as_str()).collect();
+        assert_eq!(ops, ["create", "update"]);
+        // the update diff is against the inserted row
+        assert_eq!(revisions[1].diff["title"]["old"], fx_titles[0]);
+        assert_eq!(revisions[1].diff["title"]["new"], fx_titles[1]);
+
+        // -- Clean
+        TaskBmc::delete(&ctx, &mm, task_1.id).await?;
+
+        Ok(())
+    }
+
+    #[serial]
+    #[tokio::test]
+    async fn test_search_ok() -> Result<()> {
+        // -- Setup & Fixtures
+        let mm = _dev_utils::init_test().await;
+        let ctx = _dev_utils::demo_ctx();
+        let fx_titles = &[
+            "test_search_ok buy milk, then more milk",
+            "test_search_ok buy almond milk",
+            "test_search_ok sell bread",
+        ];
+        let fx_tasks = _dev_utils::seed_tasks(&ctx, &mm, fx_titles).await?;
+
+        // -- Exec & Check
+        // all the words
+        let hits = TaskBmc::search(&ctx, &mm, "buy milk", None, None).await?;
+        let ids: Vec<i64> = hits.iter().map(|h| h.task.id).collect();
+        assert_eq!(ids, [fx_tasks[0].id, fx_tasks[1].id], "most relevant first");
+        assert!(hits[0].rank > hits[1].rank);
+        assert!(hits[0].snippet.contains("<b>milk</b>"));
+
+        // phrase
+        let hits = TaskBmc::search(&ctx, &mm, "\"buy milk\"", None, None).await?;
+        let ids: Vec<i64> = hits.iter().map(|h| h.task.id).collect();
+        assert_eq!(ids, [fx_tasks[0].id]);
+
+        // prefix, with a filter
+        let filters: Vec<TaskFilter> = serde_json::from_value(json!([{
+            "title": {"$contains": "almond"}
+        }]))?;
+        let hits = TaskBmc::search(&ctx, &mm, "alm*", Some(filters), None).await?;
+        let ids: Vec<i64> = hits.iter().map(|h| h.task.id).collect();
+        assert_eq!(ids, [fx_tasks[1].id]);
+
+        // no words
+        let hits = TaskBmc::search(&ctx, &mm, "\" & | !", None, None).await?;
+        assert!(hits.is_empty());
+
+        // -- Clean
+        for task in fx_tasks.iter() {
+            TaskBmc::delete(&ctx, &mm, task.id).await?;
+        }
+
+        Ok(())
+    }
+
+    #[serial]
+    #[tokio::test]
+    async fn test_done_at_ok() -> Result<()> {
+        // -- Setup & Fixtures
+        let mm = _dev_utils::init_test().await;
+        let ctx = _dev_utils::demo_ctx();
+        let fx_task = _dev_utils::seed_tasks(&ctx, &mm, &["test_done_at_ok-task 01"])
+            .await?
+            .remove(0);
+        let fx_done = |done| TaskForUpdate {
+            done: Some(done),
+            ..Default::default()
+        };
+
+        // -- Exec & Check
+        assert!(fx_task.done_at.is_none());
+        let task = TaskBmc::update_returning(&ctx, &mm, fx_task.id, None, fx_done(true)).await?;
+        let done_at = task.done_at.ok_or(anyhow::anyhow!("done_at not set"))?;
+
+        // stays the same while done
+        let task = TaskBmc::update_returning(&ctx, &mm, fx_task.id, None, fx_done(true)).await?;
+        assert_eq!(task.done_at, Some(done_at));
+
+        // cleared when not done anymore
+        let task = TaskBmc::update_returning(&ctx, &mm, fx_task.id, None, fx_done(false)).await?;
+        assert!(task.done_at.is_none());
+
+        // -- Clean
+        TaskBmc::delete(&ctx, &mm, fx_task.id).await?;
+
+        Ok(())
+    }
+
+    #[serial]
+    #[tokio::test]
+    async fn test_list_by_priority_and_due_at_ok() -> Result<()> {
+        // -- Setup & Fixtures
+        let mm = _dev_utils::init_test().await;
+        let ctx = _dev_utils::demo_ctx();
+        let fx_tasks_c: Vec<TaskForCreate> = serde_json::from_value(json!([
+            {"title": "test_list_by_priority-task 01", "priority": "low",
+             "due_at": "2024-01-01T00:00:00Z"},
+            {"title": "test_list_by_priority-task 02", "priority": "urgent",
+             "due_at": "2024-02-01T00:00:00Z"},
+            {"title": "test_list_by_priority-task 03", "priority": "high",
+             "due_at": "2024-03-01T00:00:00Z"},
+            {"title": "test_list_by_priority-task 04", "priority": "high"},
+        ]))?;
+        let fx_ids = TaskBmc::create_many(&ctx, &mm, fx_tasks_c).await?;
+
+        // -- Exec
+        let filters: Vec<TaskFilter> = serde_json::from_value(json!([{
+            "title": {"$startsWith": "test_list_by_priority"},
+            "priority": {"$gte": "high"},
+            "due_at": {"$lt": "2024-03-01T00:00:00Z"},
+        }]))?;
+        let tasks = TaskBmc::list(&ctx, &mm, Some(filters), None).await?;
+
+        // -- Check
+        let ids: Vec<i64> = tasks.iter().map(|t| t.id).collect();
+        assert_eq!(ids, [fx_ids[1]]);
+        assert_eq!(tasks[0].priority, TaskPriority::Urgent);
+        assert_eq!(
+            serde_json::to_value(&tasks[0])?["due_at"],
+            json!("2024-02-01T00:00:00Z")
+        );
+
+        // -- Exec
+        let filters: Vec<TaskFilter> = serde_json::from_value(json!([{
+            "title": {"$startsWith": "test_list_by_priority"},
+        }]))?;
+        let list_options = serde_json::from_value(json!({
+            "order_bys": ["!priority", "due_at"]
+        }))?;
+        let tasks = TaskBmc::list(&ctx, &mm, Some(filters), Some(list_options)).await?;
+
+        // -- Check
+        let ids: Vec<i64> = tasks.iter().map(|t| t.id).collect();
+        assert_eq!(ids, [fx_ids[1], fx_ids[2], fx_ids[3], fx_ids[0]]);
+
+        // -- Clean
+        for id in fx_ids {
+            TaskBmc::delete(&ctx, &mm, id).await?;
+        }
+
+        Ok(())
+    }
+
+    #[serial]
+    #[tokio::test]
+    async fn test_set_parent_and_graph_ok() -> Result<()> {
+        // -- Setup & Fixtures
+        let mm = _dev_utils::init_test().await;
+        let ctx = _dev_utils::demo_ctx();
+        let fx_tasks = _dev_utils::seed_tasks(
+            &ctx,
+            &mm,
+            &[
+                "test_set_parent_and_graph_ok-task 01",
+                "test_set_parent_and_graph_ok-task 01.1",
+                "test_set_parent_and_graph_ok-task 01.1.1",
+            ],
+        )
+        .await?;
+        let ids: Vec<i64> = fx_tasks.iter().map(|t| t.id).collect();
+
+        // -- Exec
+        TaskBmc::set_parent(&ctx, &mm, ids[1], Some(ids[0])).await?;
+        let task = TaskBmc::set_parent(&ctx, &mm, ids[2], Some(ids[1])).await?;
+        let res = TaskBmc::set_parent(&ctx, &mm, ids[0], Some(ids[2])).await;
+
+        // -- Check
+        assert_eq!(task.parent_id, Some(ids[1]));
+        assert!(
+            matches!(res, Err(Error::TaskParentCycle { id, parent_id })
+                if id == ids[0] && parent_id == ids[2]),
+            "TaskParentCycle not matching"
+        );
+        let graph = TaskBmc::get_graph(&ctx, &mm, ids[0]).await?;
+        let subtask_ids: Vec<i64> = graph.subtasks.iter().map(|t| t.id).collect();
+        assert_eq!(subtask_ids, [ids[1], ids[2]]);
+        assert!(graph.blockers.is_empty());
+
+        // -- Exec & Check
+        // back to the top level
+        let task = TaskBmc::set_parent(&ctx, &mm, ids[2], None).await?;
+        assert_eq!(task.parent_id, None);
+
+        // -- Clean
+        for id in ids {
+            TaskBmc::delete(&ctx, &mm, id).await?;
+        }
+
+        Ok(())
+    }
+
+    #[serial]
+    #[tokio::test]
+    async fn test_workspace_isolation_ok() -> Result<()> {
+        // -- Setup & Fixtures
+        let mm = _dev_utils::init_test().await;
+        let ctx_a = _dev_utils::demo_ctx();
+        let fx_workspace_b = WorkspaceBmc::create(
+            &Ctx::root_ctx(),
+            &mm,
+            WorkspaceForCreate {
+                name: "test_workspace_isolation_ok-workspace b".to_string(),
+            },
+        )
+        .await?;
+        let ctx_b = Ctx::root_ctx_in(fx_workspace_b.id);
+        let fx_external_id = "test_workspace_isolation_ok-ext 01";
+        let fx_task_up = || TaskForUpsert {
+            external_id: fx_external_id.to_string(),
+            title: "test_workspace_isolation_ok-task".to_string(),
+            ..Default::default()
+        };
+        let (fx_task_a, _) = TaskBmc::upsert(&ctx_a, &mm, fx_task_up()).await?;
+        let fx_filter = || TaskFilter {
+            id: Some(OpValInt64::Eq(fx_task_a.id).into()),
+            ..Default::default()
+        };
+
+        // -- Exec & Check
+        // the same external id is another task in another workspace
+        let (task_b, inserted_b) = TaskBmc::upsert(&ctx_b, &mm, fx_task_up()).await?;
+        assert!(inserted_b);
+        assert_ne!(task_b.id, fx_task_a.id);
+        assert_eq!(task_b.workspace_id, fx_workspace_b.id);
+
+        // not readable
+        let res = TaskBmc::get(&ctx_b, &mm, fx_task_a.id).await;
+        assert!(
+            matches!(res, Err(Error::EntityNotFound { id, .. }) if id == fx_task_a.id),
+            "get should be EntityNotFound"
+        );
+        let tasks = TaskBmc::list(&ctx_b, &mm, None, None).await?;
+        assert_eq!(tasks.iter().map(|t| t.id).collect::<Vec<_>>(), [task_b.id]);
+        let hits = TaskBmc::search(&ctx_b, &mm, "isolation", None, None).await?;
+        assert_eq!(
+            hits.iter().map(|h| h.task.id).collect::<Vec<_>>(),
+            [task_b.id]
+        );
+        let history = TaskHistoryBmc::list_for(&ctx_b, &mm, "task", fx_task_a.id).await?;
+        assert!(history.is_empty());
+
+        // not writable
+        let fx_task_u = || TaskForUpdate {
+            title: Some("test_workspace_isolation_ok-task updated".to_string()),
+            ..Default::default()
+        };
+        let res = TaskBmc::update(&ctx_b, &mm, fx_task_a.id, None, fx_task_u()).await;
+        assert!(
+            matches!(res, Err(Error::EntityNotFound { .. })),
+            "update should be EntityNotFound"
+        );
+        let res = TaskBmc::update(&ctx_b, &mm, fx_task_a.id, Some(0), fx_task_u()).await;
+        assert!(
+            matches!(res, Err(Error::EntityNotFound { .. })),
+            "versioned update should be EntityNotFound"
+        );
+        let res = TaskBmc::delete(&ctx_b, &mm, fx_task_a.id).await;
+        assert!(
+            matches!(res, Err(Error::EntityNotFound { .. })),
+            "delete should be EntityNotFound"
+        );
+        let ids = TaskBmc::update_many(&ctx_b, &mm, vec![fx_filter()], fx_task_u()).await?;
+        assert!(ids.is_empty());
+        let ids = TaskBmc::delete_many(&ctx_b, &mm, vec![fx_filter()]).await?;
+        assert!(ids.is_empty());
+        let res = TaskBmc::create(
+            &ctx_b,
+            &mm,
+            TaskForCreate {
+                title: "test_workspace_isolation_ok-subtask".to_string(),
+                parent_id: Some(fx_task_a.id),
+                ..Default::default()
+            },
+        )
+        .await;
+        assert!(
+            matches!(res, Err(Error::EntityNotFound { .. })),
+            "create under a parent of another workspace should be EntityNotFound"
+        );
+
+        // untouched in its workspace
+        let task_a = TaskBmc::get(&ctx_a, &mm, fx_task_a.id).await?;
+        assert_eq!(task_a.title, fx_task_a.title);
+        assert_eq!(task_a.version, fx_task_a.version);
+
+        // a user ctx without workspace sees nothing
+        let res = TaskBmc::list(&Ctx::new(1000, None)?, &mm, None, None).await;
+        assert!(
+            matches!(res, Err(Error::CtxNoWorkspace { user_id: 1000 })),
+            "CtxNoWorkspace not matching"
+        );
+
+        // -- Clean
+        TaskBmc::delete(&ctx_a, &mm, fx_task_a.id).await?;
+        TaskBmc::delete(&ctx_b, &mm, task_b.id).await?;
+
+        Ok(())
+    }
 }
@@ -0,0 +1,301 @@
+use crate::ctx::Ctx;
+use crate::model::base::DbBmc;
+use crate::model::task::TaskBmc;
+use crate::model::ModelManager;
+use crate::model::{Error, Result};
+use sea_query::{Expr, Iden, OnConflict, PostgresQueryBuilder, Query};
+use sea_query_binder::SqlxBinder;
+
+/// `task_id` is blocked by `blocker_id`.
+#[derive(Iden)]
+enum TaskDependencyIden {
+    TaskId,
+    BlockerId,
+}
+
+pub struct TaskDependencyBmc;
+
+impl DbBmc for TaskDependencyBmc {
+    const TABLE: &'static str = "task_dependency";
+}
+
+impl TaskDependencyBmc {
+    /// Makes `blocker_id` a blocker of the task. Does nothing if it already is.
+    /// Fails with `TaskBlockerCycle` if the blocker is the task, or is (transitively)
+    /// blocked by it.
+    pub async fn link(ctx: &Ctx, mm: &ModelManager, task_id: i64, blocker_id: i64) -> Result<()> {
+        // for the EntityNotFound errors
+        TaskBmc::get(ctx, mm, task_id).await?;
+        TaskBmc::get(ctx, mm, blocker_id).await?;
+
+        let cycle_err = Error::TaskBlockerCycle {
+            id: task_id,
+            blocker_id,
+        };
+        if task_id == blocker_id {
+            return Err(cycle_err);
+        }
+
+        // Serializes the links, so that two concurrent links can't make a cycle
+        // which none of them would see.
+        let mut tx = mm.db().begin().await?;
+        sqlx::query("LOCK TABLE task_dependency IN SHARE ROW EXCLUSIVE MODE")
+            .execute(&mut *tx)
+            .await?;
+
+        let blocker_blockers = Self::blocker_ids_of(&mut tx, blocker_id).await?;
+        if blocker_blockers.contains(&task_id) {
+            tx.rollback().await?;
+            return Err(cycle_err);
+        }
+
+        let mut query = Query::insert();
+        query
+            .into_table(Self::table_ref())
+            .columns([TaskDependencyIden::TaskId, TaskDependencyIden::BlockerId])
+            .values([task_id.into(), blocker_id.into()])?
+            .on_conflict(
+                OnConflict::columns([TaskDependencyIden::TaskId, TaskDependencyIden::BlockerId])
+                    .do_nothing()
+                    .to_owned(),
+            );
+
+        let (sql, values) = query.build_sqlx(PostgresQueryBuilder);
+        sqlx::query_with(&sql, values).execute(&mut *tx).await?;
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// Removes `blocker_id` from the blockers of the task. Does nothing if it is not one.
+    pub async fn unlink(ctx: &Ctx, mm: &ModelManager, task_id: i64, blocker_id: i64) -> Result<()> {
+        // for the EntityNotFound error
+        TaskBmc::get(ctx, mm, task_id).await?;
+
+        let mut query = Query::delete();
+        query
+            .from_table(Self::table_ref())
+            .and_where(Expr::col(TaskDependencyIden::TaskId).eq(task_id))
+            .and_where(Expr::col(TaskDependencyIden::BlockerId).eq(blocker_id));
+
+        let (sql, values) = query.build_sqlx(PostgresQueryBuilder);
+        sqlx::query_with(&sql, values).execute(mm.db()).await?;
+
+        Ok(())
+    }
+
+    /// Ids of the blockers of the task, with the blockers of the blockers and so on.
+    pub async fn list_blocker_ids(_ctx: &Ctx, mm: &ModelManager, task_id: i64) -> Result<Vec<i64>> {
+        let mut con = mm.db().acquire().await?;
+        Self::blocker_ids_of(&mut con, task_id).await
+    }
+
+    /// Direct blockers of the tasks which are not done yet (and not among `task_ids`,
+    /// when they are set done together), as `(task_id, blocker_id)` ordered by task.
+    /// Locks all the blockers (`FOR SHARE`), so that none can be reopened until the
+    /// end of the transaction.
+    pub async fn open_blocker_ids_of(
+        con: &mut sqlx::PgConnection,
+        task_ids: &[i64],
+    ) -> Result<Vec<(i64, i64)>> {
+        let sql = r#"
+            SELECT d.task_id, d.blocker_id, t.done FROM task_dependency d
+            JOIN task t ON t.id = d.blocker_id
+            WHERE d.task_id = ANY($1)
+            ORDER BY d.task_id, d.blocker_id
+            FOR SHARE OF t"#;
+
+        let blockers = sqlx::query_as::<_, (i64, i64, bool)>(sql)
+            .bind(task_ids)
+            .fetch_all(con)
+            .await?;
+
+        Ok(blockers
+            .into_iter()
+            .filter(|(_, blocker_id, done)| !done && !task_ids.contains(blocker_id))
+            .map(|(task_id, blocker_id, _)| (task_id, blocker_id))
+            .collect())
+    }
+
+    /// Transitive blockers, ordered by id.
+    /// (`UNION` rather than `UNION ALL`, so that it would terminate even on a cycle)
+    async fn blocker_ids_of(con: &mut sqlx::PgConnection, task_id: i64) -> Result<Vec<i64>> {
+        let sql = r#"
+            WITH RECURSIVE blockers(id) AS (
+                SELECT blocker_id FROM task_dependency WHERE task_id = $1
+                UNION
+                SELECT d.blocker_id FROM task_dependency d
+                JOIN blockers b ON d.task_id = b.id
+            )
+            SELECT id FROM blockers ORDER BY id"#;
+
+        let ids = sqlx::query_scalar::<_, i64>(sql)
+            .bind(task_id)
+            .fetch_all(con)
+            .await?;
+
+        Ok(ids)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::_dev_utils;
+    use crate::model::task::{TaskFilter, TaskForUpdate, TaskForUpsert};
+    use anyhow::Result;
+    use serde_json::json;
+    use serial_test::serial;
+
+    #[serial]
+    #[tokio::test]
+    async fn test_link_err_cycle() -> Result<()> {
+        // -- Setup & Fixtures
+        let mm = _dev_utils::init_test().await;
+        let ctx = _dev_utils::demo_ctx();
+        let fx_tasks = _dev_utils::seed_tasks(
+            &ctx,
+            &mm,
+            &[
+                "test_link_err_cycle-task 01",
+                "test_link_err_cycle-task 02",
+                "test_link_err_cycle-task 03",
+            ],
+        )
+        .await?;
+        let ids: Vec<i64> = fx_tasks.iter().map(|t| t.id).collect();
+        // 01 blocked by 02, blocked by 03
+        TaskDependencyBmc::link(&ctx, &mm, ids[0], ids[1]).await?;
+        TaskDependencyBmc::link(&ctx, &mm, ids[1], ids[2]).await?;
+
+        // -- Exec
+        let res = TaskDependencyBmc::link(&ctx, &mm, ids[2], ids[0]).await;
+
+        // -- Check
+        assert!(
+            matches!(res, Err(Error::TaskBlockerCycle { id, blocker_id })
+                if id == ids[2] && blocker_id == ids[0]),
+            "TaskBlockerCycle not matching"
+        );
+        let blocker_ids = TaskDependencyBmc::list_blocker_ids(&ctx, &mm, ids[0]).await?;
+        assert_eq!(blocker_ids, [ids[1], ids[2]]);
+
+        // -- Clean
+        for id in ids {
+            TaskBmc::delete(&ctx, &mm, id).await?;
+        }
+
+        Ok(())
+    }
+
+    #[serial]
+    #[tokio::test]
+    async fn test_update_done_err_open_blockers() -> Result<()> {
+        // -- Setup & Fixtures
+        let mm = _dev_utils::init_test().await;
+        let ctx = _dev_utils::demo_ctx();
+        let fx_tasks = _dev_utils::seed_tasks(
+            &ctx,
+            &mm,
+            &[
+                "test_update_done_err_open_blockers-task 01",
+                "test_update_done_err_open_blockers-task 02",
+            ],
+        )
+        .await?;
+        let (task_id, blocker_id) = (fx_tasks[0].id, fx_tasks[1].id);
+        TaskDependencyBmc::link(&ctx, &mm, task_id, blocker_id).await?;
+        let fx_done = || TaskForUpdate {
+            done: Some(true),
+            ..Default::default()
+        };
+
+        // -- Exec
+        let res = TaskBmc::update(&ctx, &mm, task_id, None, fx_done()).await;
+
+        // -- Check
+        assert!(
+            matches!(&res, Err(Error::TaskHasOpenBlockers { id, blocker_ids })
+                if *id == task_id && blocker_ids == &[blocker_id]),
+            "TaskHasOpenBlockers not matching"
+        );
+
+        // -- Exec & Check
+        // once the blocker is done
+        TaskBmc::update(&ctx, &mm, blocker_id, None, fx_done()).await?;
+        let task = TaskBmc::update_returning(&ctx, &mm, task_id, None, fx_done()).await?;
+        assert!(task.done);
+
+        // -- Clean
+        for task in fx_tasks.iter() {
+            TaskBmc::delete(&ctx, &mm, task.id).await?;
+        }
+
+        Ok(())
+    }
+
+    #[serial]
+    #[tokio::test]
+    async fn test_update_many_and_upsert_done_err_open_blockers() -> Result<()> {
+        // -- Setup & Fixtures
+        let mm = _dev_utils::init_test().await;
+        let ctx = _dev_utils::demo_ctx();
+        let fx_external_id = "test_update_many_and_upsert_done-ext 01";
+        let fx_upsert_done = || TaskForUpsert {
+            external_id: fx_external_id.to_string(),
+            title: "test_update_many_and_upsert_done-task 01".to_string(),
+            done: Some(true),
+            ..Default::default()
+        };
+        let fx_done = || TaskForUpdate {
+            done: Some(true),
+            ..Default::default()
+        };
+        let fx_filter = |ids: &[i64]| -> Result<TaskFilter> {
+            Ok(serde_json::from_value(json!({ "id": { "$in": ids } }))?)
+        };
+        let (task, _) = TaskBmc::upsert(
+            &ctx,
+            &mm,
+            TaskForUpsert {
+                done: None,
+                ..fx_upsert_done()
+            },
+        )
+        .await?;
+        let fx_blockers =
+            _dev_utils::seed_tasks(&ctx, &mm, &["test_update_many_and_upsert_done-blocker 01"])
+                .await?;
+        let blocker_id = fx_blockers[0].id;
+        TaskDependencyBmc::link(&ctx, &mm, task.id, blocker_id).await?;
+
+        // -- Exec
+        let res_many =
+            TaskBmc::update_many(&ctx, &mm, vec![fx_filter(&[task.id])?], fx_done()).await;
+        let res_upsert = TaskBmc::upsert(&ctx, &mm, fx_upsert_done()).await;
+
+        // -- Check
+        for res in [res_many.map(|_| ()), res_upsert.map(|_| ())] {
+            assert!(
+                matches!(&res, Err(Error::TaskHasOpenBlockers { id, blocker_ids })
+                    if *id == task.id && blocker_ids == &[blocker_id]),
+                "TaskHasOpenBlockers not matching"
+            );
+        }
+        assert!(!TaskBmc::get(&ctx, &mm, task.id).await?.done);
+
+        // -- Exec & Check
+        // the blocker set done in the same update
+        let ids = vec![task.id, blocker_id];
+        let updated_ids =
+            TaskBmc::update_many(&ctx, &mm, vec![fx_filter(&ids)?], fx_done()).await?;
+        assert_eq!(updated_ids.len(), 2);
+
+        // -- Clean
+        for id in ids {
+            TaskBmc::delete(&ctx, &mm, id).await?;
+        }
+
+        Ok(())
+    }
+}
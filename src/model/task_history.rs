@@ -0,0 +1,230 @@
+use crate::ctx::Ctx;
+use crate::model::base::{self, DbBmc};
+use crate::model::change_event::ChangeOp;
+use crate::model::ModelManager;
+use crate::model::Result;
+use crate::utils::serialize_time;
+use modql::field::{Fields, HasFields};
+use sea_query::{Expr, Iden, Order, PostgresQueryBuilder, Query};
+use sea_query_binder::SqlxBinder;
+use serde::Serialize;
+use serde_json::{Map, Value};
+use sqlx::{FromRow, PgConnection};
+use time::OffsetDateTime;
+
+/// One revision of an entity, as recorded by the base create/update/delete.
+#[derive(Debug, Clone, Fields, FromRow, Serialize)]
+pub struct TaskHistory {
+    pub id: i64,
+    pub entity: String,
+    pub entity_id: i64,
+    pub op: String,
+    pub user_id: i64,
+    #[serde(serialize_with = "serialize_time")]
+    pub ctime: OffsetDateTime,
+    /// Changed fields, as `{"field": {"old": .., "new": ..}}`
+    pub diff: Value,
+}
+
+/// A revision to record, see `TaskHistoryBmc::record`.
+pub(in crate::model) struct TaskHistoryForCreate {
+    pub entity_id: i64,
+    pub workspace_id: Option<i64>,
+    pub op: ChangeOp,
+    pub diff: Value,
+}
+
+#[derive(Iden)]
+enum TaskHistoryIden {
+    Id,
+    Entity,
+    EntityId,
+    WorkspaceId,
+    Op,
+    UserId,
+    Diff,
+}
+
+pub struct TaskHistoryBmc;
+
+impl DbBmc for TaskHistoryBmc {
+    const TABLE: &'static str = "task_history";
+
+    /// The one of the entity, so that the history is not visible from the other workspaces.
+    fn has_workspace() -> bool {
+        true
+    }
+}
+
+impl TaskHistoryBmc {
+    /// Records the revisions with a single multi-row insert. Takes the connection of the
+    /// transaction doing the changes so that the history is only written if they are.
+    pub(in crate::model) async fn record(
+        ctx: &Ctx,
+        con: &mut PgConnection,
+        entity: &'static str,
+        revisions: Vec<TaskHistoryForCreate>,
+    ) -> Result<()> {
+        if revisions.is_empty() {
+            return Ok(());
+        }
+
+        let mut query = Query::insert();
+        query.into_table(Self::table_ref()).columns([
+            TaskHistoryIden::Entity,
+            TaskHistoryIden::EntityId,
+            TaskHistoryIden::WorkspaceId,
+            TaskHistoryIden::Op,
+            TaskHistoryIden::UserId,
+            TaskHistoryIden::Diff,
+        ]);
+        for revision in revisions {
+            query.values([
+                entity.into(),
+                revision.entity_id.into(),
+                revision.workspace_id.into(),
+                revision.op.into(),
+                ctx.user_id().into(),
+                revision.diff.into(),
+            ])?;
+        }
+
+        let (sql, values) = query.build_sqlx(PostgresQueryBuilder);
+        sqlx::query_with(&sql, values).execute(con).await?;
+
+        Ok(())
+    }
+
+    /// Returns the revisions of an entity, oldest first.
+    pub async fn list_for(
+        ctx: &Ctx,
+        mm: &ModelManager,
+        entity: &str,
+        entity_id: i64,
+    ) -> Result<Vec<TaskHistory>> {
+        let db = mm.db();
+
+        let mut query = Query::select();
+        query
+            .from(Self::table_ref())
+            .columns(TaskHistory::field_column_refs())
+            .and_where(Expr::col(TaskHistoryIden::Entity).eq(entity))
+            .and_where(Expr::col(TaskHistoryIden::EntityId).eq(entity_id))
+            .order_by(TaskHistoryIden::Id, Order::Asc);
+        if let Some(workspace_cond) = base::workspace_cond::<Self>(ctx)? {
+            query.and_where(workspace_cond);
+        }
+
+        let (sql, values) = query.build_sqlx(PostgresQueryBuilder);
+        let revisions = sqlx::query_as_with::<_, TaskHistory, _>(&sql, values)
+            .fetch_all(db)
+            .await?;
+
+        Ok(revisions)
+    }
+}
+
+// region:    --- Diffs
+
+/// Diff of the fields set at creation: `{"field": {"new": ..}}`
+pub(in crate::model) fn diff_created(field_names: &[String], new_row: &Value) -> Value {
+    let mut diff = Map::new();
+    for name in field_names {
+        let new = new_row.get(name).cloned().unwrap_or(Value::Null);
+        diff.insert(name.clone(), json_change(None, Some(new)));
+    }
+    Value::Object(diff)
+}
+
+/// Diff of the updated fields which value actually changed:
+/// `{"field": {"old": .., "new": ..}}`
+pub(in crate::model) fn diff_updated(
+    field_names: &[String],
+    old_row: &Value,
+    new_row: &Value,
+) -> Value {
+    let mut diff = Map::new();
+    for name in field_names {
+        let old = old_row.get(name).cloned().unwrap_or(Value::Null);
+        let new = new_row.get(name).cloned().unwrap_or(Value::Null);
+        if old != new {
+            diff.insert(name.clone(), json_change(Some(old), Some(new)));
+        }
+    }
+    Value::Object(diff)
+}
+
+/// Diff of a deleted row, all its columns: `{"column": {"old": ..}}`
+pub(in crate::model) fn diff_deleted(old_row: &Value) -> Value {
+    let mut diff = Map::new();
+    if let Some(old_row) = old_row.as_object() {
+        for (name, old) in old_row {
+            diff.insert(name.clone(), json_change(Some(old.clone()), None));
+        }
+    }
+    Value::Object(diff)
+}
+
+fn json_change(old: Option<Value>, new: Option<Value>) -> Value {
+    let mut change = Map::new();
+    if let Some(old) = old {
+        change.insert("old".to_string(), old);
+    }
+    if let Some(new) = new {
+        change.insert("new".to_string(), new);
+    }
+    Value::Object(change)
+}
+
+// endregion: --- Diffs
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::_dev_utils;
+    use crate::model::task::{TaskBmc, TaskForUpdate};
+    use anyhow::Result;
+    use serde_json::json;
+    use serial_test::serial;
+
+    #[serial]
+    #[tokio::test]
+    async fn test_list_for_task_ok() -> Result<()> {
+        // -- Setup & Fixtures
+        let mm = _dev_utils::init_test().await;
+        let ctx = _dev_utils::demo_ctx();
+        let fx_title = "test_list_for_task_ok-task 01";
+        let fx_task = _dev_utils::seed_tasks(&ctx, &mm, &[fx_title])
+            .await?
+            .remove(0);
+
+        // -- Exec
+        TaskBmc::update(
+            &ctx,
+            &mm,
+            fx_task.id,
+            None,
+            TaskForUpdate {
+                title: Some(fx_title.to_string()), // unchanged, not in diff
+                done: Some(true),
+                ..Default::default()
+            },
+        )
+        .await?;
+        TaskBmc::delete(&ctx, &mm, fx_task.id).await?;
+
+        // -- Check
+        let revisions = TaskHistoryBmc::list_for(&ctx, &mm, "task", fx_task.id).await?;
+        let ops: Vec<&str> = revisions.iter().map(|r| r.op.as_str()).collect();
+        assert_eq!(ops, ["create", "update", "delete"]);
+        assert_eq!(revisions[0].diff, json!({"title": {"new": fx_title}}));
+        assert_eq!(
+            revisions[1].diff,
+            json!({"done": {"old": false, "new": true}})
+        );
+        assert_eq!(revisions[2].diff["title"], json!({"old": fx_title}));
+        assert!(revisions.iter().all(|r| r.user_id == ctx.user_id()));
+
+        Ok(())
+    }
+}
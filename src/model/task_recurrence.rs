@@ -0,0 +1,628 @@
+//! Recurring tasks.
+//!
+//! A task with a `recurrence` rule gets its next occurrence (a copy with the next due
+//! date, linked by `recurrence_prev_id`) generated by `TaskRecurrenceBmc::materialize_next`,
+//! run periodically by the `scheduler`.
+//!
+//! Each task gets at most one next occurrence:
+//! - `recurrence_prev_id` is unique, so a second insert for the same task fails,
+//! - `recurred_at` is set on the task once done, so that it is not generated again,
+//!   even when the next occurrence is deleted.
+//! - the occurrence, its labels and `recurred_at` are written in one transaction
+//!   (with the task locked), so that a failure in between is retried as a whole.
+
+use crate::ctx::Ctx;
+use crate::model::base::{self, CommonIden, DbBmc};
+use crate::model::label::LabelBmc;
+use crate::model::task::{Task, TaskBmc, TaskPriority};
+use crate::model::ModelManager;
+use crate::model::Result;
+use modql::field::{Fields, HasFields};
+use sea_query::{Expr, Iden, Order, PostgresQueryBuilder, Query};
+use sea_query_binder::SqlxBinder;
+use serde::{Deserialize, Serialize};
+use sqlx::PgConnection;
+use std::fmt;
+use std::str::FromStr;
+use time::{Date, Duration, Month, OffsetDateTime, Weekday};
+
+/// Above that, the weekly rules would take too long to evaluate.
+const INTERVAL_MAX: u32 = 1000;
+
+// region:    --- Recurrence
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Freq {
+    Daily,
+    Weekly,
+    Monthly,
+}
+
+/// Subset of the iCalendar RRULE: `FREQ=DAILY|WEEKLY|MONTHLY`, with an optional
+/// `INTERVAL` and, for the weekly rules only, `BYDAY`.
+/// e.g., `FREQ=WEEKLY;INTERVAL=2;BYDAY=MO,TH` (every other week, on Monday and Thursday).
+///
+/// Written as its canonical string in the `recurrence` column.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Recurrence {
+    pub freq: Freq,
+    pub interval: u32,
+    /// Sorted from Monday, empty for the day of the week of the previous occurrence.
+    pub by_day: Vec<Weekday>,
+}
+
+impl Recurrence {
+    /// The occurrence following the one at `time`, at the same time of day.
+    /// The monthly occurrences are clamped to the end of the shorter months
+    /// (e.g., Jan 31, Feb 28, Mar 28).
+    /// `None` if out of the supported dates.
+    pub fn next_after(&self, time: OffsetDateTime) -> Option<OffsetDateTime> {
+        let interval = self.interval as i64;
+        match self.freq {
+            Freq::Daily => time.checked_add(Duration::days(interval)),
+            Freq::Weekly if self.by_day.is_empty() => time.checked_add(Duration::weeks(interval)),
+            Freq::Weekly => {
+                let week_start = monday_of(time.date());
+                // the next matching day is at most in the next week of the interval
+                (1..=7 * (interval + 1))
+                    .map(|days| time.checked_add(Duration::days(days)))
+                    .find(|next| match next {
+                        Some(next) => {
+                            let weeks = (monday_of(next.date()) - week_start).whole_weeks();
+                            weeks % interval == 0 && self.by_day.contains(&next.weekday())
+                        }
+                        None => true,
+                    })
+                    .flatten()
+            }
+            Freq::Monthly => {
+                let months = time.year() as i64 * 12 + time.month() as i64 - 1 + interval;
+                let year = i32::try_from(months / 12).ok()?;
+                let month = Month::try_from((months % 12 + 1) as u8).ok()?;
+                let day = time.day().min(month.length(year));
+                let date = Date::from_calendar_date(year, month, day).ok()?;
+                Some(time.replace_date(date))
+            }
+        }
+    }
+
+    /// The first occurrence following the one at `time` which is after `now`
+    /// (the missed ones are skipped).
+    pub fn next_after_now(
+        &self,
+        time: OffsetDateTime,
+        now: OffsetDateTime,
+    ) -> Option<OffsetDateTime> {
+        let mut next = self.next_after(time)?;
+        while next <= now {
+            next = self.next_after(next)?;
+        }
+        Some(next)
+    }
+}
+
+fn monday_of(date: Date) -> Date {
+    date - Duration::days(date.weekday().number_days_from_monday() as i64)
+}
+
+const WEEKDAYS: [(&str, Weekday); 7] = [
+    ("MO", Weekday::Monday),
+    ("TU", Weekday::Tuesday),
+    ("WE", Weekday::Wednesday),
+    ("TH", Weekday::Thursday),
+    ("FR", Weekday::Friday),
+    ("SA", Weekday::Saturday),
+    ("SU", Weekday::Sunday),
+];
+
+impl FromStr for Recurrence {
+    type Err = String;
+
+    fn from_str(rule: &str) -> core::result::Result<Self, Self::Err> {
+        let rule = rule.trim();
+        let rule = rule.strip_prefix("RRULE:").unwrap_or(rule);
+
+        let mut freq = None;
+        let mut interval = 1;
+        let mut by_day = Vec::new();
+        for part in rule.split(';').filter(|part| !part.is_empty()) {
+            let (key, value) = part
+                .split_once('=')
+                .ok_or_else(|| format!("recurrence part '{part}' is not KEY=VALUE"))?;
+            match key {
+                "FREQ" => {
+                    freq = Some(match value {
+                        "DAILY" => Freq::Daily,
+                        "WEEKLY" => Freq::Weekly,
+                        "MONTHLY" => Freq::Monthly,
+                        _ => {
+                            return Err(format!(
+                                "recurrence FREQ '{value}' not supported (DAILY, WEEKLY or MONTHLY)"
+                            ))
+                        }
+                    })
+                }
+                "INTERVAL" => {
+                    interval = value
+                        .parse()
+                        .ok()
+                        .filter(|interval| (1..=INTERVAL_MAX).contains(interval))
+                        .ok_or_else(|| {
+                            format!("recurrence INTERVAL '{value}' not in 1..={INTERVAL_MAX}")
+                        })?
+                }
+                "BYDAY" => {
+                    for day in value.split(',') {
+                        let (_, weekday) = WEEKDAYS
+                            .iter()
+                            .find(|(name, _)| *name == day)
+                            .ok_or_else(|| {
+                                format!("recurrence BYDAY '{day}' not a day (MO..SU)")
+                            })?;
+                        by_day.push(*weekday);
+                    }
+                }
+                _ => return Err(format!("recurrence part '{key}' not supported")),
+            }
+        }
+
+        let freq = freq.ok_or("recurrence FREQ missing")?;
+        if !by_day.is_empty() && freq != Freq::Weekly {
+            return Err("recurrence BYDAY only supported with FREQ=WEEKLY".to_string());
+        }
+        by_day.sort_by_key(|day| day.number_days_from_monday());
+        by_day.dedup();
+
+        Ok(Self {
+            freq,
+            interval,
+            by_day,
+        })
+    }
+}
+
+impl fmt::Display for Recurrence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let freq = match self.freq {
+            Freq::Daily => "DAILY",
+            Freq::Weekly => "WEEKLY",
+            Freq::Monthly => "MONTHLY",
+        };
+        write!(f, "FREQ={freq}")?;
+        if self.interval != 1 {
+            write!(f, ";INTERVAL={}", self.interval)?;
+        }
+        if !self.by_day.is_empty() {
+            let days: Vec<&str> = self
+                .by_day
+                .iter()
+                .filter_map(|day| WEEKDAYS.iter().find(|(_, d)| d == day))
+                .map(|(name, _)| *name)
+                .collect();
+            write!(f, ";BYDAY={}", days.join(","))?;
+        }
+        Ok(())
+    }
+}
+
+impl TryFrom<String> for Recurrence {
+    type Error = String;
+
+    fn try_from(val: String) -> core::result::Result<Self, Self::Error> {
+        val.parse()
+    }
+}
+
+impl From<Recurrence> for String {
+    fn from(val: Recurrence) -> Self {
+        val.to_string()
+    }
+}
+
+impl From<Recurrence> for sea_query::Value {
+    fn from(val: Recurrence) -> Self {
+        val.to_string().into()
+    }
+}
+
+impl sea_query::Nullable for Recurrence {
+    fn null() -> sea_query::Value {
+        sea_query::Value::String(None)
+    }
+}
+
+// endregion: --- Recurrence
+
+// region:    --- TaskRecurrenceBmc
+
+#[derive(Iden)]
+enum TaskRecurrenceIden {
+    Recurrence,
+    RecurredAt,
+}
+
+/// The next occurrence of a recurring task.
+#[derive(Fields)]
+struct TaskForOccurrence {
+    workspace_id: i64,
+    title: String,
+    parent_id: Option<i64>,
+    description: Option<String>,
+    #[field(cast_as = "task_priority")]
+    priority: TaskPriority,
+    due_at: Option<OffsetDateTime>,
+    recurrence: Recurrence,
+    recurrence_prev_id: i64,
+}
+
+pub struct TaskRecurrenceBmc;
+
+impl DbBmc for TaskRecurrenceBmc {
+    const TABLE: &'static str = "task";
+}
+
+impl TaskRecurrenceBmc {
+    /// Generates the next occurrence of the recurring tasks which are done,
+    /// or which next occurrence is due before `now + lead`.
+    /// The next occurrence gets the next due date after `now`, the labels of the task,
+    /// and the same recurrence.
+    /// Only the tasks of the workspace of the ctx (all of them for the root ctx).
+    /// Returns the generated occurrences.
+    pub async fn materialize_next(
+        ctx: &Ctx,
+        mm: &ModelManager,
+        now: OffsetDateTime,
+        lead: Duration,
+    ) -> Result<Vec<Task>> {
+        let mut occurrences = Vec::new();
+        for task in Self::list_pending(ctx, mm).await? {
+            // invalid rules can only come from a manual edit of the db, and never recur
+            let Some(recurrence) = task.recurrence.as_deref().and_then(|r| r.parse().ok()) else {
+                continue;
+            };
+            // without due date, the next occurrence is counted from the completion
+            let Some(next_due) = task
+                .due_at
+                .or(task.done_at)
+                .and_then(|time| Recurrence::next_after_now(&recurrence, time, now))
+            else {
+                continue;
+            };
+            if !task.done && next_due > now + lead {
+                continue;
+            }
+
+            if let Some(occurrence) =
+                Self::create_occurrence(ctx, mm, &task, recurrence, next_due).await?
+            {
+                occurrences.push(occurrence);
+            }
+        }
+
+        Ok(occurrences)
+    }
+
+    /// Recurring tasks which next occurrence is not generated yet, ordered by id.
+    async fn list_pending(ctx: &Ctx, mm: &ModelManager) -> Result<Vec<Task>> {
+        let mut query = Query::select();
+        query
+            .from(Self::table_ref())
+            .columns(Task::field_column_refs())
+            .and_where(Expr::col(TaskRecurrenceIden::Recurrence).is_not_null())
+            .and_where(Expr::col(TaskRecurrenceIden::RecurredAt).is_null())
+            .order_by(CommonIden::Id, Order::Asc);
+        if let Some(workspace_cond) = base::workspace_cond::<TaskBmc>(ctx)? {
+            query.and_where(workspace_cond);
+        }
+
+        let (sql, values) = query.build_sqlx(PostgresQueryBuilder);
+        let tasks = sqlx::query_as_with::<_, Task, _>(&sql, values)
+            .fetch_all(mm.db())
+            .await?;
+
+        Ok(tasks)
+    }
+
+    /// Creates the next occurrence with the labels of the task, and marks the task
+    /// recurred, in one transaction.
+    /// `None` when the task was recurred meanwhile (e.g., by another instance), or when
+    /// its next occurrence already exists.
+    async fn create_occurrence(
+        ctx: &Ctx,
+        mm: &ModelManager,
+        task: &Task,
+        recurrence: Recurrence,
+        due_at: OffsetDateTime,
+    ) -> Result<Option<Task>> {
+        let mut tx = mm.db().begin().await?;
+
+        // -- Lock the task, and check it is still pending
+        let pending: Option<(bool,)> = sqlx::query_as(
+            "SELECT EXISTS (SELECT 1 FROM task WHERE recurrence_prev_id = $1)
+             FROM task WHERE id = $1 AND recurred_at IS NULL FOR UPDATE",
+        )
+        .bind(task.id)
+        .fetch_optional(&mut *tx)
+        .await?;
+        let Some((has_next,)) = pending else {
+            return Ok(None);
+        };
+        if has_next {
+            Self::mark_recurred(&mut tx, task.id).await?;
+            tx.commit().await?;
+            return Ok(None);
+        }
+
+        // -- Create the occurrence
+        let occurrence_c = TaskForOccurrence {
+            workspace_id: task.workspace_id,
+            title: task.title.clone(),
+            parent_id: task.parent_id,
+            description: task.description.clone(),
+            priority: task.priority,
+            due_at: Some(due_at),
+            recurrence,
+            recurrence_prev_id: task.id,
+        };
+        let mut occurrence =
+            base::create_returning_in::<TaskBmc, _, Task>(ctx, &mut tx, occurrence_c).await?;
+        LabelBmc::copy_attached_in(ctx, &mut tx, task.id, occurrence.id).await?;
+        Self::mark_recurred(&mut tx, task.id).await?;
+        tx.commit().await?;
+
+        let mut labels = LabelBmc::list_by_task_ids(ctx, mm, vec![occurrence.id]).await?;
+        occurrence.labels = labels.remove(&occurrence.id).unwrap_or_default();
+
+        Ok(Some(occurrence))
+    }
+
+    async fn mark_recurred(con: &mut PgConnection, id: i64) -> Result<()> {
+        let mut query = Query::update();
+        query
+            .table(Self::table_ref())
+            .value(TaskRecurrenceIden::RecurredAt, Expr::current_timestamp())
+            .and_where(Expr::col(CommonIden::Id).eq(id));
+
+        let (sql, values) = query.build_sqlx(PostgresQueryBuilder);
+        sqlx::query_with(&sql, values).execute(con).await?;
+
+        Ok(())
+    }
+}
+
+// endregion: --- TaskRecurrenceBmc
+
+// region:    --- Tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::_dev_utils;
+    use crate::model::label::LabelForCreate;
+    use crate::model::task::{TaskForCreate, TaskForUpdate};
+    use crate::utils::parse_utc;
+    use anyhow::Result;
+    use serial_test::serial;
+
+    #[test]
+    fn test_recurrence_parse_and_next_ok() -> Result<()> {
+        // -- Setup & Fixtures
+        // a Wednesday
+        let fx_time = parse_utc("2024-01-31T09:00:00Z")?;
+        let fx_cases = [
+            ("FREQ=DAILY", "FREQ=DAILY", "2024-02-01T09:00:00Z"),
+            (
+                "RRULE:FREQ=DAILY;INTERVAL=3",
+                "FREQ=DAILY;INTERVAL=3",
+                "2024-02-03T09:00:00Z",
+            ),
+            (
+                "FREQ=WEEKLY;BYDAY=MO,TH",
+                "FREQ=WEEKLY;BYDAY=MO,TH",
+                "2024-02-01T09:00:00Z",
+            ),
+            (
+                "FREQ=WEEKLY;INTERVAL=2;BYDAY=WE,MO",
+                "FREQ=WEEKLY;INTERVAL=2;BYDAY=MO,WE",
+                "2024-02-12T09:00:00Z",
+            ),
+            ("FREQ=MONTHLY", "FREQ=MONTHLY", "2024-02-29T09:00:00Z"),
+        ];
+
+        // -- Exec & Check
+        for (rule, canonical, next) in fx_cases {
+            let recurrence: Recurrence = rule.parse().map_err(anyhow::Error::msg)?;
+            assert_eq!(recurrence.to_string(), canonical);
+            assert_eq!(
+                recurrence.next_after(fx_time),
+                Some(parse_utc(next)?),
+                "{rule}"
+            );
+        }
+        for rule in [
+            "FREQ=YEARLY",
+            "FREQ=DAILY;BYDAY=MO",
+            "INTERVAL=2",
+            "FREQ=DAILY;INTERVAL=0",
+        ] {
+            assert!(rule.parse::<Recurrence>().is_err(), "{rule} should fail");
+        }
+
+        Ok(())
+    }
+
+    #[serial]
+    #[tokio::test]
+    async fn test_materialize_next_ok() -> Result<()> {
+        // -- Setup & Fixtures
+        let mm = _dev_utils::init_test().await;
+        let ctx = _dev_utils::demo_ctx();
+        let fx_now = parse_utc("2024-03-10T12:00:00Z")?;
+        let fx_lead = Duration::days(1);
+        let fx_label = LabelBmc::create(
+            &ctx,
+            &mm,
+            LabelForCreate {
+                name: "test_materialize_next_ok-label".to_string(),
+                color: None,
+            },
+        )
+        .await?;
+        // done: next right away, missed occurrences skipped
+        let fx_done = TaskBmc::create_returning(
+            &ctx,
+            &mm,
+            TaskForCreate {
+                title: "test_materialize_next_ok-task done".to_string(),
+                due_at: Some(parse_utc("2024-03-01T09:00:00Z")?),
+                recurrence: Some("FREQ=DAILY".parse().map_err(anyhow::Error::msg)?),
+                ..Default::default()
+            },
+        )
+        .await?;
+        LabelBmc::attach(&ctx, &mm, fx_done.id, fx_label.id).await?;
+        TaskBmc::update(
+            &ctx,
+            &mm,
+            fx_done.id,
+            None,
+            TaskForUpdate {
+                done: Some(true),
+                ..Default::default()
+            },
+        )
+        .await?;
+        // not done, next due within the lead
+        let fx_soon = TaskBmc::create_returning(
+            &ctx,
+            &mm,
+            TaskForCreate {
+                title: "test_materialize_next_ok-task soon".to_string(),
+                due_at: Some(parse_utc("2024-03-04T11:00:00Z")?),
+                recurrence: Some("FREQ=WEEKLY".parse().map_err(anyhow::Error::msg)?),
+                ..Default::default()
+            },
+        )
+        .await?;
+        // not done, next due after the lead
+        let fx_later = TaskBmc::create_returning(
+            &ctx,
+            &mm,
+            TaskForCreate {
+                title: "test_materialize_next_ok-task later".to_string(),
+                due_at: Some(parse_utc("2024-03-10T13:00:00Z")?),
+                recurrence: Some("FREQ=WEEKLY".parse().map_err(anyhow::Error::msg)?),
+                ..Default::default()
+            },
+        )
+        .await?;
+
+        // -- Exec
+        let occurrences = TaskRecurrenceBmc::materialize_next(&ctx, &mm, fx_now, fx_lead).await?;
+        let occurrences_again =
+            TaskRecurrenceBmc::materialize_next(&ctx, &mm, fx_now, fx_lead).await?;
+
+        // -- Check
+        assert_eq!(occurrences.len(), 2);
+        let (next_done, next_soon) = (&occurrences[0], &occurrences[1]);
+        assert_eq!(next_done.recurrence_prev_id, Some(fx_done.id));
+        assert_eq!(next_done.title, fx_done.title);
+        assert_eq!(next_done.due_at, Some(parse_utc("2024-03-11T09:00:00Z")?));
+        assert!(!next_done.done);
+        assert_eq!(next_done.recurrence.as_deref(), Some("FREQ=DAILY"));
+        assert_eq!(next_done.labels.len(), 1);
+        assert_eq!(next_soon.recurrence_prev_id, Some(fx_soon.id));
+        assert_eq!(next_soon.due_at, Some(parse_utc("2024-03-11T11:00:00Z")?));
+        assert!(occurrences_again.is_empty(), "should be idempotent");
+
+        // not generated again once deleted
+        TaskBmc::delete(&ctx, &mm, next_done.id).await?;
+        let occurrences_again =
+            TaskRecurrenceBmc::materialize_next(&ctx, &mm, fx_now, fx_lead).await?;
+        assert!(
+            occurrences_again.is_empty(),
+            "should not regenerate deleted"
+        );
+
+        // -- Clean
+        for id in [next_soon.id, fx_done.id, fx_soon.id, fx_later.id] {
+            TaskBmc::delete(&ctx, &mm, id).await?;
+        }
+        LabelBmc::delete(&ctx, &mm, fx_label.id).await?;
+
+        Ok(())
+    }
+
+    #[serial]
+    #[tokio::test]
+    async fn test_materialize_next_label_copy_fail_retry_ok() -> Result<()> {
+        // -- Setup & Fixtures
+        let mm = _dev_utils::init_test().await;
+        let ctx = _dev_utils::demo_ctx();
+        let fx_now = parse_utc("2024-03-10T12:00:00Z")?;
+        let fx_lead = Duration::days(1);
+        let fx_label = LabelBmc::create(
+            &ctx,
+            &mm,
+            LabelForCreate {
+                name: "test_materialize_next_label_copy_fail-label".to_string(),
+                color: None,
+            },
+        )
+        .await?;
+        let fx_task = TaskBmc::create_returning(
+            &ctx,
+            &mm,
+            TaskForCreate {
+                title: "test_materialize_next_label_copy_fail-task".to_string(),
+                // next due within the lead
+                due_at: Some(parse_utc("2024-03-10T11:00:00Z")?),
+                recurrence: Some("FREQ=DAILY".parse().map_err(anyhow::Error::msg)?),
+                ..Default::default()
+            },
+        )
+        .await?;
+        LabelBmc::attach(&ctx, &mm, fx_task.id, fx_label.id).await?;
+        // fails the label copy, the attach of the label to another task
+        for sql in [
+            "CREATE FUNCTION test_fail_label_copy() RETURNS trigger AS $$
+             BEGIN RAISE EXCEPTION 'label copy fail'; END $$ LANGUAGE plpgsql"
+                .to_string(),
+            format!(
+                "CREATE TRIGGER test_fail_label_copy BEFORE INSERT ON task_label
+                 FOR EACH ROW WHEN (NEW.label_id = {} AND NEW.task_id <> {})
+                 EXECUTE FUNCTION test_fail_label_copy()",
+                fx_label.id, fx_task.id
+            ),
+        ] {
+            sqlx::query(&sql).execute(mm.db()).await?;
+        }
+
+        // -- Exec
+        let res = TaskRecurrenceBmc::materialize_next(&ctx, &mm, fx_now, fx_lead).await;
+        for sql in [
+            "DROP TRIGGER test_fail_label_copy ON task_label",
+            "DROP FUNCTION test_fail_label_copy()",
+        ] {
+            sqlx::query(sql).execute(mm.db()).await?;
+        }
+        let occurrences = TaskRecurrenceBmc::materialize_next(&ctx, &mm, fx_now, fx_lead).await?;
+
+        // -- Check
+        assert!(res.is_err(), "the label copy should fail");
+        assert_eq!(occurrences.len(), 1);
+        let occurrence = &occurrences[0];
+        assert_eq!(occurrence.recurrence_prev_id, Some(fx_task.id));
+        let label_ids: Vec<i64> = occurrence.labels.iter().map(|l| l.id).collect();
+        assert_eq!(label_ids, [fx_label.id]);
+
+        // -- Clean
+        for id in [occurrence.id, fx_task.id] {
+            TaskBmc::delete(&ctx, &mm, id).await?;
+        }
+        LabelBmc::delete(&ctx, &mm, fx_label.id).await?;
+
+        Ok(())
+    }
+}
+// endregion: --- Tests
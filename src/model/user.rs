@@ -1,6 +1,7 @@
 use crate::crypt::{pwd, EncryptContent};
 use crate::ctx::Ctx;
 
+use crate::model::audit_event::{AuditEventBmc, AuditEventForCreate, AuditKind};
 use crate::model::base::{self, DbBmc};
 use crate::model::ModelManager;
 use crate::model::{Error, Result};
@@ -9,6 +10,7 @@ use modql::SIden;
 use sea_query::{Expr, Iden, PostgresQueryBuilder, Query, SimpleExpr};
 use sea_query_binder::SqlxBinder;
 use serde::{Deserialize, Serialize};
+use serde_json::json;
 use sqlx::postgres::PgRow;
 use sqlx::FromRow;
 use uuid::Uuid;
@@ -61,6 +63,7 @@ enum UserIden {
     Id,
     Username,
     Pwd,
+    Admin,
 }
 
 pub struct UserBmc;
@@ -103,7 +106,6 @@ impl UserBmc {
     }
 
     pub async fn update_pwd(ctx: &Ctx, mm: &ModelManager, id: i64, pwd_clear: &str) -> Result<()> {
-        let db = mm.db();
         let user: UserForLogin = Self::get(ctx, mm, id).await?;
         let pwd = pwd::encrypt_pwd(&EncryptContent {
             content: pwd_clear.to_string(),
@@ -119,13 +121,43 @@ impl UserBmc {
 
         // -- exec query
         let (sql, values) = query.build_sqlx(PostgresQueryBuilder);
+        // the password change and its audit event, all or nothing
+        let mut tx = mm.db().begin().await?;
         let _count = sqlx::query_with(&sql, values)
-            .execute(db)
+            .execute(&mut *tx)
             .await?
             .rows_affected();
 
+        AuditEventBmc::record_in(
+            ctx,
+            &mut tx,
+            AuditEventForCreate {
+                kind: AuditKind::PwdChange,
+                user_id: Some(id),
+                detail: json!({ "by_user_id": ctx.user_id() }),
+            },
+        )
+        .await?;
+        tx.commit().await?;
+
         Ok(())
     }
+
+    /// False when the user does not exist.
+    pub async fn is_admin(_ctx: &Ctx, mm: &ModelManager, id: i64) -> Result<bool> {
+        let mut query = Query::select();
+        query
+            .from(Self::table_ref())
+            .column(UserIden::Admin)
+            .and_where(Expr::col(UserIden::Id).eq(id));
+
+        let (sql, values) = query.build_sqlx(PostgresQueryBuilder);
+        let admin = sqlx::query_as_with::<_, (bool,), _>(&sql, values)
+            .fetch_optional(mm.db())
+            .await?;
+
+        Ok(admin.is_some_and(|(admin,)| admin))
+    }
 }
 
 #[cfg(test)]
@@ -0,0 +1,134 @@
+//! Workspaces, the tenants of the data.
+//!
+//! - The rows of the Bmcs with `DbBmc::has_workspace` belong to a workspace, and the base
+//!   functions only read and write the ones of the workspace of the ctx.
+//! - A user only gets a ctx in the workspaces it is a member of (see `web::mw_auth`).
+
+use crate::ctx::Ctx;
+use crate::model::base::{self, DbBmc};
+use crate::model::ModelManager;
+use crate::model::Result;
+use modql::field::{Fields, HasFields};
+use sea_query::{Expr, Iden, OnConflict, Order, PostgresQueryBuilder, Query};
+use sea_query_binder::SqlxBinder;
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+
+#[derive(Debug, Clone, Fields, FromRow, Serialize)]
+pub struct Workspace {
+    pub id: i64,
+    pub name: String,
+}
+
+#[derive(Fields, Deserialize)]
+pub struct WorkspaceForCreate {
+    pub name: String,
+}
+
+#[derive(Iden)]
+enum WorkspaceMember {
+    Table,
+    WorkspaceId,
+    UserId,
+}
+
+#[derive(Iden)]
+enum WorkspaceIden {
+    #[iden = "workspace"]
+    Table,
+    Id,
+}
+
+pub struct WorkspaceBmc;
+
+impl DbBmc for WorkspaceBmc {
+    const TABLE: &'static str = "workspace";
+}
+
+impl WorkspaceBmc {
+    /// Creates the workspace, with the user of the ctx as member (unless root).
+    pub async fn create(
+        ctx: &Ctx,
+        mm: &ModelManager,
+        workspace_c: WorkspaceForCreate,
+    ) -> Result<Workspace> {
+        let workspace: Workspace =
+            base::create_returning::<Self, _, _>(ctx, mm, workspace_c).await?;
+        if !ctx.is_root() {
+            Self::add_member(ctx, mm, workspace.id, ctx.user_id()).await?;
+        }
+
+        Ok(workspace)
+    }
+
+    /// Does nothing if the user already is a member.
+    pub async fn add_member(
+        _ctx: &Ctx,
+        mm: &ModelManager,
+        workspace_id: i64,
+        user_id: i64,
+    ) -> Result<()> {
+        let mut query = Query::insert();
+        query
+            .into_table(WorkspaceMember::Table)
+            .columns([WorkspaceMember::WorkspaceId, WorkspaceMember::UserId])
+            .values([workspace_id.into(), user_id.into()])?
+            .on_conflict(
+                OnConflict::columns([WorkspaceMember::WorkspaceId, WorkspaceMember::UserId])
+                    .do_nothing()
+                    .to_owned(),
+            );
+
+        let (sql, values) = query.build_sqlx(PostgresQueryBuilder);
+        sqlx::query_with(&sql, values).execute(mm.db()).await?;
+
+        Ok(())
+    }
+
+    pub async fn is_member(
+        _ctx: &Ctx,
+        mm: &ModelManager,
+        workspace_id: i64,
+        user_id: i64,
+    ) -> Result<bool> {
+        let mut query = Query::select();
+        query
+            .from(WorkspaceMember::Table)
+            .column(WorkspaceMember::WorkspaceId)
+            .and_where(Expr::col(WorkspaceMember::WorkspaceId).eq(workspace_id))
+            .and_where(Expr::col(WorkspaceMember::UserId).eq(user_id));
+
+        let (sql, values) = query.build_sqlx(PostgresQueryBuilder);
+        let member = sqlx::query_with(&sql, values)
+            .fetch_optional(mm.db())
+            .await?;
+
+        Ok(member.is_some())
+    }
+
+    /// Workspaces the user is a member of, ordered by id.
+    pub async fn list_for_user(
+        _ctx: &Ctx,
+        mm: &ModelManager,
+        user_id: i64,
+    ) -> Result<Vec<Workspace>> {
+        let mut query = Query::select();
+        query
+            .from(WorkspaceIden::Table)
+            .columns(Workspace::field_column_refs_with_rel(WorkspaceIden::Table))
+            .inner_join(
+                WorkspaceMember::Table,
+                Expr::col((WorkspaceMember::Table, WorkspaceMember::WorkspaceId))
+                    .equals((WorkspaceIden::Table, WorkspaceIden::Id)),
+            )
+            .and_where(Expr::col((WorkspaceMember::Table, WorkspaceMember::UserId)).eq(user_id))
+            .order_by((WorkspaceIden::Table, WorkspaceIden::Id), Order::Asc);
+
+        let (sql, values) = query.build_sqlx(PostgresQueryBuilder);
+        let workspaces = sqlx::query_as_with::<_, Workspace, _>(&sql, values)
+            .fetch_all(mm.db())
+            .await?;
+
+        Ok(workspaces)
+    }
+}
@@ -0,0 +1,117 @@
+//! In-process background jobs, started from `main`.
+//!
+//! Every app instance runs the scheduler, but each tick only runs in the instance
+//! holding the Postgres advisory lock, taken on a dedicated connection (not one
+//! of the pool, which the jobs use).
+
+use crate::config;
+use crate::ctx::Ctx;
+use crate::model::attachment::AttachmentBmc;
+use crate::model::audit_event::AuditEventBmc;
+use crate::model::change_event::ChangeEventBmc;
+use crate::model::task_recurrence::TaskRecurrenceBmc;
+use crate::model::{ModelManager, Result};
+use crate::utils::now_utc;
+use sqlx::{Connection, PgConnection};
+use tokio::task::JoinHandle;
+use tokio::time::MissedTickBehavior;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info};
+
+const TICK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// How long before its due date the next occurrence of a recurring task is generated.
+const RECURRENCE_LEAD: time::Duration = time::Duration::days(1);
+
+/// Key of the advisory lock of the scheduler, shared by all the instances.
+const LOCK_KEY: i64 = 0x7363_6865_6475_6c65; // "schedule"
+
+/// Runs the jobs every `TICK_INTERVAL`, starting now, until `shutdown`.
+/// A running tick is completed, so the returned handle can be awaited before closing the pool.
+pub fn spawn(mm: ModelManager, shutdown: CancellationToken) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(TICK_INTERVAL);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+        let mut lock_con = None;
+
+        loop {
+            tokio::select! {
+                _ = interval.tick() => (),
+                _ = shutdown.cancelled() => break,
+            }
+            if let Err(ex) = tick(&mm, &mut lock_con).await {
+                error!("{:<12} - scheduler tick fail: {ex}", "SCHEDULER");
+                // the connection may be broken, reconnects at the next tick
+                lock_con = None;
+            }
+        }
+
+        // the lock is released with the session
+        if let Some(lock_con) = lock_con {
+            let _ = lock_con.close().await;
+        }
+        debug!("{:<12} - stopped", "SCHEDULER");
+    })
+}
+
+async fn tick(mm: &ModelManager, lock_con: &mut Option<PgConnection>) -> Result<()> {
+    let lock_con = match lock_con {
+        Some(con) => con,
+        None => lock_con.insert(PgConnection::connect(&config().DB_URL).await?),
+    };
+
+    let locked: bool = sqlx::query_scalar("SELECT pg_try_advisory_lock($1)")
+        .bind(LOCK_KEY)
+        .fetch_one(&mut *lock_con)
+        .await?;
+    if !locked {
+        debug!(
+            "{:<12} - tick skipped, run by another instance",
+            "SCHEDULER"
+        );
+        return Ok(());
+    }
+
+    run_jobs(mm).await;
+
+    sqlx::query("SELECT pg_advisory_unlock($1)")
+        .bind(LOCK_KEY)
+        .execute(&mut *lock_con)
+        .await?;
+
+    Ok(())
+}
+
+/// Each job runs even when a previous one fails (its error is logged).
+async fn run_jobs(mm: &ModelManager) {
+    let ctx = Ctx::root_ctx();
+
+    match TaskRecurrenceBmc::materialize_next(&ctx, mm, now_utc(), RECURRENCE_LEAD).await {
+        Ok(occurrences) if !occurrences.is_empty() => info!(
+            "{:<12} - generated {} recurring task occurrences",
+            "SCHEDULER",
+            occurrences.len()
+        ),
+        Ok(_) => (),
+        Err(ex) => error!("{:<12} - recurring tasks job fail: {ex}", "SCHEDULER"),
+    }
+
+    match AttachmentBmc::purge_orphan_blobs(mm).await {
+        Ok(purged) if purged > 0 => info!("{:<12} - purged {purged} orphan blobs", "SCHEDULER"),
+        Ok(_) => (),
+        Err(ex) => error!("{:<12} - orphan blobs job fail: {ex}", "SCHEDULER"),
+    }
+
+    match ChangeEventBmc::prune(mm).await {
+        Ok(pruned) if pruned > 0 => debug!("{:<12} - pruned {pruned} change events", "SCHEDULER"),
+        Ok(_) => (),
+        Err(ex) => error!("{:<12} - change events job fail: {ex}", "SCHEDULER"),
+    }
+
+    let retention = time::Duration::days(config().AUDIT_RETENTION_DAYS);
+    match AuditEventBmc::prune(mm, now_utc() - retention).await {
+        Ok(pruned) if pruned > 0 => info!("{:<12} - pruned {pruned} audit events", "SCHEDULER"),
+        Ok(_) => (),
+        Err(ex) => error!("{:<12} - audit events job fail: {ex}", "SCHEDULER"),
+    }
+}
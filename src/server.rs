@@ -0,0 +1,559 @@
+//! Http server
+//!
+//! - Listens on `HOST:PORT`, with TLS when `TLS` is configured (http/1.1 only),
+//!   and on the `UNIX_SOCKET` too when configured.
+//! - The TLS certificate and key files are checked every `TLS_RELOAD_INTERVAL`,
+//!   and reloaded when they changed, without dropping the connections.
+//!   A reload failure is logged, and the previous certificate kept.
+//! - On shutdown (see `shutdown_on_signal`), stops accepting, lets the in-flight
+//!   requests complete, and waits up to `SHUTDOWN_TIMEOUT_SEC` for the connections
+//!   to close.
+//! - The peer address of the tcp connections is in the request extensions
+//!   (`ConnectInfo<SocketAddr>`), for the request log.
+
+use crate::{config, Error, Result};
+use axum::extract::ConnectInfo;
+use axum::Router;
+use hyper::body::Incoming;
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::Request;
+use hyper_util::rt::TokioIo;
+use rustls::crypto::ring;
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use rustls::ServerConfig;
+use std::fs::File;
+use std::io::BufReader;
+use std::net::SocketAddr;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpListener;
+use tokio_rustls::TlsAcceptor;
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
+use tower::Service;
+use tracing::{debug, error, info, warn};
+
+const TLS_RELOAD_INTERVAL: Duration = Duration::from_secs(10);
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+/// Before accepting again, after an accept error (e.g., too many open files).
+const ACCEPT_ERROR_DELAY: Duration = Duration::from_millis(100);
+
+/// PEM files of the certificate chain and of the private key.
+pub struct TlsConfig {
+    pub cert_path: String,
+    pub key_path: String,
+}
+
+/// Cancels `shutdown` on SIGINT (ctrl-c) or SIGTERM.
+pub fn shutdown_on_signal(shutdown: CancellationToken) {
+    tokio::spawn(async move {
+        let ctrl_c = async {
+            if let Err(ex) = tokio::signal::ctrl_c().await {
+                error!("{:<12} - ctrl-c handler fail: {ex}", "SERVER");
+                std::future::pending::<()>().await;
+            }
+        };
+
+        #[cfg(unix)]
+        let terminate = async {
+            use tokio::signal::unix::{signal, SignalKind};
+            match signal(SignalKind::terminate()) {
+                Ok(mut sigterm) => {
+                    sigterm.recv().await;
+                }
+                Err(ex) => {
+                    error!("{:<12} - sigterm handler fail: {ex}", "SERVER");
+                    std::future::pending::<()>().await;
+                }
+            }
+        };
+        #[cfg(not(unix))]
+        let terminate = std::future::pending::<()>();
+
+        tokio::select! {
+            _ = ctrl_c => info!("{:<12} - ctrl-c received", "SHUTDOWN"),
+            _ = terminate => info!("{:<12} - sigterm received", "SHUTDOWN"),
+        }
+        shutdown.cancel();
+    });
+}
+
+/// Binds the listeners and serves the app on them, until `shutdown` is cancelled
+/// and the connections are drained (or `SHUTDOWN_TIMEOUT_SEC` elapsed).
+/// Fails if a listener can't be bound, or the TLS files can't be loaded.
+pub async fn serve(app: Router, shutdown: CancellationToken) -> Result<()> {
+    let config = config();
+
+    let addr = format!("{}:{}", config.HOST, config.PORT);
+    let tcp_listener = TcpListener::bind(&addr)
+        .await
+        .map_err(|ex| Error::ServerBindFail {
+            addr: addr.clone(),
+            cause: ex.to_string(),
+        })?;
+    let tls_acceptor = match &config.TLS {
+        Some(tls_config) => Some(new_tls_acceptor(tls_config, shutdown.clone())?),
+        None => None,
+    };
+    let scheme = if tls_acceptor.is_some() {
+        "https"
+    } else {
+        "http"
+    };
+    info!("{:<12} - {scheme}://{addr}", "LISTENING");
+
+    let connections = TaskTracker::new();
+    match &config.UNIX_SOCKET {
+        Some(path) => {
+            let unix_listener = bind_unix(path)?;
+            info!("{:<12} - unix:{path}", "LISTENING");
+            tokio::join!(
+                serve_tcp(
+                    tcp_listener,
+                    tls_acceptor,
+                    app.clone(),
+                    &connections,
+                    &shutdown
+                ),
+                serve_unix(unix_listener, app, &connections, &shutdown)
+            );
+            let _ = std::fs::remove_file(path);
+        }
+        None => serve_tcp(tcp_listener, tls_acceptor, app, &connections, &shutdown).await,
+    }
+
+    drain(
+        connections,
+        Duration::from_secs(config.SHUTDOWN_TIMEOUT_SEC),
+    )
+    .await;
+
+    Ok(())
+}
+
+/// Waits for the connections to close, up to `timeout`.
+/// The remaining ones are dropped with the runtime.
+async fn drain(connections: TaskTracker, timeout: Duration) {
+    connections.close();
+    info!(
+        "{:<12} - draining {} connections",
+        "SHUTDOWN",
+        connections.len()
+    );
+
+    if tokio::time::timeout(timeout, connections.wait())
+        .await
+        .is_err()
+    {
+        warn!(
+            "{:<12} - {} connections not drained after {timeout:?}",
+            "SHUTDOWN",
+            connections.len()
+        );
+    }
+}
+
+/// Accepts until `shutdown`.
+async fn serve_tcp(
+    listener: TcpListener,
+    tls_acceptor: Option<TlsAcceptor>,
+    app: Router,
+    connections: &TaskTracker,
+    shutdown: &CancellationToken,
+) {
+    loop {
+        let accepted = tokio::select! {
+            accepted = listener.accept() => accepted,
+            _ = shutdown.cancelled() => break,
+        };
+        let (stream, remote) = match accepted {
+            Ok(accepted) => accepted,
+            Err(ex) => {
+                error!("{:<12} - tcp accept error: {ex}", "SERVER");
+                tokio::time::sleep(ACCEPT_ERROR_DELAY).await;
+                continue;
+            }
+        };
+
+        let Some(tls_acceptor) = tls_acceptor.clone() else {
+            connections.spawn(serve_connection(
+                stream,
+                Some(remote),
+                app.clone(),
+                shutdown.clone(),
+            ));
+            continue;
+        };
+        // the handshake in the connection task, so that a slow client can't block the accepts
+        let app = app.clone();
+        let shutdown = shutdown.clone();
+        connections.spawn(async move {
+            match tokio::time::timeout(TLS_HANDSHAKE_TIMEOUT, tls_acceptor.accept(stream)).await {
+                Ok(Ok(stream)) => serve_connection(stream, Some(remote), app, shutdown).await,
+                Ok(Err(ex)) => debug!("{:<12} - tls handshake error: {ex}", "SERVER"),
+                Err(_) => debug!("{:<12} - tls handshake timeout", "SERVER"),
+            }
+        });
+    }
+}
+
+/// Serves http/1.1 (with upgrades, for the websockets) on the connection.
+/// On `shutdown`, the in-flight request completes, then the connection is closed.
+async fn serve_connection<S>(
+    stream: S,
+    remote: Option<SocketAddr>,
+    app: Router,
+    shutdown: CancellationToken,
+) where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let service = service_fn(move |mut req: Request<Incoming>| {
+        if let Some(remote) = remote {
+            req.extensions_mut().insert(ConnectInfo(remote));
+        }
+        app.clone().call(req)
+    });
+
+    let conn = http1::Builder::new()
+        .serve_connection(TokioIo::new(stream), service)
+        .with_upgrades();
+    tokio::pin!(conn);
+
+    let res = tokio::select! {
+        res = conn.as_mut() => res,
+        _ = shutdown.cancelled() => {
+            conn.as_mut().graceful_shutdown();
+            conn.await
+        }
+    };
+    if let Err(ex) = res {
+        debug!("{:<12} - connection error: {ex}", "SERVER");
+    }
+}
+
+// region:    --- Unix Socket
+
+/// Removes the socket file left by a previous run, if any.
+#[cfg(unix)]
+fn bind_unix(path: &str) -> Result<tokio::net::UnixListener> {
+    let to_bind_error = |ex: std::io::Error| Error::ServerBindFail {
+        addr: format!("unix:{path}"),
+        cause: ex.to_string(),
+    };
+
+    match std::fs::remove_file(path) {
+        Err(ex) if ex.kind() != std::io::ErrorKind::NotFound => return Err(to_bind_error(ex)),
+        _ => (),
+    }
+    tokio::net::UnixListener::bind(path).map_err(to_bind_error)
+}
+
+#[cfg(not(unix))]
+fn bind_unix(path: &str) -> Result<std::convert::Infallible> {
+    Err(Error::ServerBindFail {
+        addr: format!("unix:{path}"),
+        cause: "unix sockets are not supported on this platform".to_string(),
+    })
+}
+
+/// Accepts until `shutdown`.
+#[cfg(unix)]
+async fn serve_unix(
+    listener: tokio::net::UnixListener,
+    app: Router,
+    connections: &TaskTracker,
+    shutdown: &CancellationToken,
+) {
+    loop {
+        let accepted = tokio::select! {
+            accepted = listener.accept() => accepted,
+            _ = shutdown.cancelled() => break,
+        };
+        match accepted {
+            Ok((stream, _)) => {
+                connections.spawn(serve_connection(
+                    stream,
+                    None,
+                    app.clone(),
+                    shutdown.clone(),
+                ));
+            }
+            Err(ex) => {
+                error!("{:<12} - unix accept error: {ex}", "SERVER");
+                tokio::time::sleep(ACCEPT_ERROR_DELAY).await;
+            }
+        }
+    }
+}
+
+#[cfg(not(unix))]
+async fn serve_unix(
+    listener: std::convert::Infallible,
+    _app: Router,
+    _connections: &TaskTracker,
+    _shutdown: &CancellationToken,
+) {
+    match listener {}
+}
+
+// endregion: --- Unix Socket
+
+// region:    --- Tls
+
+/// Also starts the reload of the certificate, until `shutdown`.
+fn new_tls_acceptor(tls_config: &TlsConfig, shutdown: CancellationToken) -> Result<TlsAcceptor> {
+    let resolver = Arc::new(ReloadingCertResolver::load(tls_config)?);
+    tokio::spawn(reload_loop(resolver.clone(), shutdown));
+
+    let mut server_config = ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .map_err(|ex| Error::ServerTlsLoadFail {
+            path: tls_config.cert_path.clone(),
+            cause: ex.to_string(),
+        })?
+        .with_no_client_auth()
+        .with_cert_resolver(resolver);
+    server_config.alpn_protocols = vec![b"http/1.1".to_vec()];
+
+    Ok(TlsAcceptor::from(Arc::new(server_config)))
+}
+
+/// Gives the last loaded certificate to the handshakes.
+#[derive(Debug)]
+struct ReloadingCertResolver {
+    cert_path: String,
+    key_path: String,
+    current: RwLock<Loaded>,
+}
+
+#[derive(Debug)]
+struct Loaded {
+    certified_key: Arc<CertifiedKey>,
+    /// Modification times of the cert and key files.
+    mtimes: (Option<SystemTime>, Option<SystemTime>),
+}
+
+impl ReloadingCertResolver {
+    fn load(tls_config: &TlsConfig) -> Result<Self> {
+        let TlsConfig {
+            cert_path,
+            key_path,
+        } = tls_config;
+        // before the load, so that a change during the load is reloaded
+        let mtimes = file_mtimes(cert_path, key_path);
+        let certified_key = load_certified_key(cert_path, key_path)?;
+
+        Ok(ReloadingCertResolver {
+            cert_path: cert_path.clone(),
+            key_path: key_path.clone(),
+            current: RwLock::new(Loaded {
+                certified_key,
+                mtimes,
+            }),
+        })
+    }
+
+    /// Reloads the files if they changed since the last load.
+    /// Returns true when reloaded.
+    fn reload_if_changed(&self) -> Result<bool> {
+        let mtimes = file_mtimes(&self.cert_path, &self.key_path);
+        if self.read_current().mtimes == mtimes {
+            return Ok(false);
+        }
+
+        let res = load_certified_key(&self.cert_path, &self.key_path);
+        let mut current = self.write_current();
+        // not retried until the files change again
+        current.mtimes = mtimes;
+        current.certified_key = res?;
+
+        Ok(true)
+    }
+
+    // a panic while holding the lock leaves a valid value, so poisoning is ignored
+    fn read_current(&self) -> std::sync::RwLockReadGuard<'_, Loaded> {
+        self.current.read().unwrap_or_else(|ex| ex.into_inner())
+    }
+
+    fn write_current(&self) -> std::sync::RwLockWriteGuard<'_, Loaded> {
+        self.current.write().unwrap_or_else(|ex| ex.into_inner())
+    }
+}
+
+impl ResolvesServerCert for ReloadingCertResolver {
+    fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(self.read_current().certified_key.clone())
+    }
+}
+
+async fn reload_loop(resolver: Arc<ReloadingCertResolver>, shutdown: CancellationToken) {
+    let mut interval = tokio::time::interval(TLS_RELOAD_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = interval.tick() => (),
+            _ = shutdown.cancelled() => break,
+        }
+        match resolver.reload_if_changed() {
+            Ok(true) => info!("{:<12} - tls certificate reloaded", "SERVER"),
+            Ok(false) => (),
+            Err(ex) => error!("{:<12} - tls certificate not reloaded: {ex}", "SERVER"),
+        }
+    }
+}
+
+fn file_mtimes(cert_path: &str, key_path: &str) -> (Option<SystemTime>, Option<SystemTime>) {
+    let mtime = |path: &str| std::fs::metadata(path).and_then(|m| m.modified()).ok();
+    (mtime(cert_path), mtime(key_path))
+}
+
+fn load_certified_key(cert_path: &str, key_path: &str) -> Result<Arc<CertifiedKey>> {
+    let to_load_error = |path: &str, cause: String| Error::ServerTlsLoadFail {
+        path: path.to_string(),
+        cause,
+    };
+
+    let mut cert_reader = BufReader::new(
+        File::open(cert_path).map_err(|ex| to_load_error(cert_path, ex.to_string()))?,
+    );
+    let certs = rustls_pemfile::certs(&mut cert_reader)
+        .collect::<std::io::Result<Vec<_>>>()
+        .map_err(|ex| to_load_error(cert_path, ex.to_string()))?;
+    if certs.is_empty() {
+        return Err(to_load_error(cert_path, "no certificate".to_string()));
+    }
+
+    let mut key_reader =
+        BufReader::new(File::open(key_path).map_err(|ex| to_load_error(key_path, ex.to_string()))?);
+    let key = rustls_pemfile::private_key(&mut key_reader)
+        .map_err(|ex| to_load_error(key_path, ex.to_string()))?
+        .ok_or_else(|| to_load_error(key_path, "no private key".to_string()))?;
+    let signing_key = ring::sign::any_supported_type(&key)
+        .map_err(|ex| to_load_error(key_path, ex.to_string()))?;
+
+    Ok(Arc::new(CertifiedKey::new(certs, signing_key)))
+}
+
+// endregion: --- Tls
+
+// region:    --- Tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use axum::routing::get;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use uuid::Uuid;
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_serve_unix_ok() -> Result<()> {
+        // -- Setup & Fixtures
+        let fx_path = std::env::temp_dir().join(format!("server-{}.sock", Uuid::new_v4()));
+        let fx_path = fx_path.to_string_lossy().to_string();
+        let fx_app = Router::new().route("/hello", get(|| async { "hello unix" }));
+        // a stale socket file is replaced
+        std::fs::write(&fx_path, "")?;
+
+        // -- Exec
+        let listener = bind_unix(&fx_path)?;
+        let server = tokio::spawn(async move {
+            serve_unix(
+                listener,
+                fx_app,
+                &TaskTracker::new(),
+                &CancellationToken::new(),
+            )
+            .await
+        });
+        let mut stream = tokio::net::UnixStream::connect(&fx_path).await?;
+        stream
+            .write_all(b"GET /hello HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await?;
+
+        // -- Check
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{response}");
+        assert!(response.ends_with("hello unix"), "{response}");
+
+        // -- Clean
+        server.abort();
+        std::fs::remove_file(&fx_path)?;
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_serve_unix_shutdown_drain_ok() -> Result<()> {
+        // -- Setup & Fixtures
+        let fx_path = std::env::temp_dir().join(format!("server-{}.sock", Uuid::new_v4()));
+        let fx_path = fx_path.to_string_lossy().to_string();
+        let fx_app = Router::new().route(
+            "/slow",
+            get(|| async {
+                tokio::time::sleep(Duration::from_millis(300)).await;
+                "slow done"
+            }),
+        );
+        let listener = bind_unix(&fx_path)?;
+        let shutdown = CancellationToken::new();
+        let server = tokio::spawn({
+            let shutdown = shutdown.clone();
+            async move {
+                let connections = TaskTracker::new();
+                serve_unix(listener, fx_app, &connections, &shutdown).await;
+                drain(connections, Duration::from_secs(5)).await;
+            }
+        });
+        // keep-alive, so that the server closes the connection
+        let mut stream = tokio::net::UnixStream::connect(&fx_path).await?;
+        stream
+            .write_all(b"GET /slow HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .await?;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        // -- Exec
+        shutdown.cancel();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await?;
+        tokio::time::timeout(Duration::from_secs(5), server).await??;
+
+        // -- Check
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{response}");
+        assert!(response.ends_with("slow done"), "{response}");
+        // not accepting anymore
+        assert!(tokio::net::UnixStream::connect(&fx_path).await.is_err());
+
+        // -- Clean
+        std::fs::remove_file(&fx_path)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_certified_key_err_not_pem() -> Result<()> {
+        // -- Setup & Fixtures
+        let fx_path = std::env::temp_dir().join(format!("cert-{}.pem", Uuid::new_v4()));
+        let fx_path = fx_path.to_string_lossy().to_string();
+        std::fs::write(&fx_path, "not a pem file")?;
+
+        // -- Exec
+        let res = load_certified_key(&fx_path, &fx_path);
+
+        // -- Check
+        assert!(
+            matches!(&res, Err(Error::ServerTlsLoadFail { path, .. }) if *path == fx_path),
+            "ServerTlsLoadFail not matching"
+        );
+
+        // -- Clean
+        std::fs::remove_file(&fx_path)?;
+
+        Ok(())
+    }
+}
+// endregion: --- Tests
@@ -0,0 +1,116 @@
+use reqwest::header::CONTENT_TYPE;
+use reqwest::Client;
+use serde_json::{json, Value};
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
+use tracing::warn;
+
+/// Spans waiting for the exporter, the next ones are dropped.
+const EXPORT_BUFFER: usize = 4096;
+const EXPORT_INTERVAL: Duration = Duration::from_secs(5);
+const BATCH_MAX: usize = 512;
+const EXPORT_TIMEOUT: Duration = Duration::from_secs(10);
+
+enum ExportMsg {
+    Span(Value),
+    Flush(oneshot::Sender<()>),
+}
+
+/// Sends the closed spans to the exporter task.
+pub(super) struct SpanSender(mpsc::Sender<ExportMsg>);
+
+impl SpanSender {
+    /// Never blocks, drops the span when the buffer is full.
+    pub fn send(&self, span: Value) {
+        let _ = self.0.try_send(ExportMsg::Span(span));
+    }
+}
+
+pub struct OtelExporter {
+    sender: mpsc::Sender<ExportMsg>,
+}
+
+impl OtelExporter {
+    pub(super) fn start(endpoint: &str) -> (Self, SpanSender) {
+        let (sender, rx) = mpsc::channel(EXPORT_BUFFER);
+        let url = format!("{}/v1/traces", endpoint.trim_end_matches('/'));
+        tokio::spawn(export_loop(rx, url));
+
+        (
+            OtelExporter {
+                sender: sender.clone(),
+            },
+            SpanSender(sender),
+        )
+    }
+
+    /// Exports the pending spans (waiting when the buffer is full).
+    pub async fn flush(&self) {
+        let (done_tx, done_rx) = oneshot::channel();
+        if self.sender.send(ExportMsg::Flush(done_tx)).await.is_ok() {
+            let _ = done_rx.await;
+        }
+    }
+}
+
+async fn export_loop(mut rx: mpsc::Receiver<ExportMsg>, url: String) {
+    let client = Client::new();
+    let mut interval = tokio::time::interval(EXPORT_INTERVAL);
+    let mut batch = Vec::new();
+
+    loop {
+        tokio::select! {
+            msg = rx.recv() => match msg {
+                Some(ExportMsg::Span(span)) => {
+                    batch.push(span);
+                    if batch.len() >= BATCH_MAX {
+                        export(&client, &url, &mut batch).await;
+                    }
+                }
+                Some(ExportMsg::Flush(done)) => {
+                    export(&client, &url, &mut batch).await;
+                    let _ = done.send(());
+                }
+                None => {
+                    export(&client, &url, &mut batch).await;
+                    break;
+                }
+            },
+            _ = interval.tick() => export(&client, &url, &mut batch).await,
+        }
+    }
+}
+
+/// Posts (and clears) the batch. A failed batch is dropped.
+async fn export(client: &Client, url: &str, batch: &mut Vec<Value>) {
+    if batch.is_empty() {
+        return;
+    }
+
+    let body = json!({
+        "resourceSpans": [{
+            "resource": {
+                "attributes": [
+                    { "key": "service.name", "value": { "stringValue": env!("CARGO_PKG_NAME") } },
+                    { "key": "service.version", "value": { "stringValue": env!("CARGO_PKG_VERSION") } },
+                ]
+            },
+            "scopeSpans": [{
+                "scope": { "name": env!("CARGO_CRATE_NAME") },
+                "spans": std::mem::take(batch),
+            }]
+        }]
+    });
+
+    let res = client
+        .post(url)
+        .header(CONTENT_TYPE, "application/json")
+        .body(body.to_string())
+        .timeout(EXPORT_TIMEOUT)
+        .send()
+        .await
+        .and_then(|res| res.error_for_status());
+    if let Err(ex) = res {
+        warn!("{:<12} - otlp export fail: {ex}", "TELEMETRY");
+    }
+}
@@ -0,0 +1,336 @@
+use super::export::SpanSender;
+use serde_json::{json, Value};
+use std::fmt::Debug;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::Subscriber;
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+/// Name of the span field with the W3C `traceparent` of the remote parent.
+const TRACEPARENT_FIELD: &str = "traceparent";
+
+pub struct OtelLayer {
+    sender: SpanSender,
+    sample_ratio: f64,
+}
+
+impl OtelLayer {
+    pub(super) fn new(sender: SpanSender, sample_ratio: f64) -> Self {
+        OtelLayer {
+            sender,
+            sample_ratio,
+        }
+    }
+
+    fn sample_root(&self) -> bool {
+        self.sample_ratio >= 1.
+            || (self.sample_ratio > 0. && rand::random::<f64>() < self.sample_ratio)
+    }
+}
+
+/// Kept in the extensions of the span.
+struct SpanData {
+    trace_id: [u8; 16],
+    span_id: [u8; 8],
+    parent_span_id: Option<[u8; 8]>,
+    sampled: bool,
+    start: SystemTime,
+    fields: SpanFields,
+}
+
+impl<S> Layer<S> for OtelLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+
+        let mut fields = SpanFields::new(attrs.metadata().name());
+        attrs.record(&mut fields);
+
+        let parent = span.parent().and_then(|parent| {
+            parent
+                .extensions()
+                .get::<SpanData>()
+                .map(|data| (data.trace_id, data.span_id, data.sampled))
+        });
+        let remote_parent = fields
+            .traceparent
+            .take()
+            .and_then(|traceparent| parse_traceparent(&traceparent));
+        let (trace_id, parent_span_id, sampled) = match (parent, remote_parent) {
+            (Some((trace_id, span_id, sampled)), _) => (trace_id, Some(span_id), sampled),
+            (None, Some(remote)) => (remote.trace_id, Some(remote.span_id), remote.sampled),
+            (None, None) => (new_trace_id(), None, self.sample_root()),
+        };
+
+        span.extensions_mut().insert(SpanData {
+            trace_id,
+            span_id: new_span_id(),
+            parent_span_id,
+            sampled,
+            start: SystemTime::now(),
+            fields,
+        });
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let mut extensions = span.extensions_mut();
+        if let Some(data) = extensions.get_mut::<SpanData>() {
+            values.record(&mut data.fields);
+        }
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(&id) else {
+            return;
+        };
+        let Some(data) = span.extensions_mut().remove::<SpanData>() else {
+            return;
+        };
+
+        if data.sampled {
+            self.sender.send(data.into_otlp_json(SystemTime::now()));
+        }
+    }
+}
+
+impl SpanData {
+    /// The span of the OTLP json encoding.
+    fn into_otlp_json(self, end: SystemTime) -> Value {
+        let SpanFields {
+            name,
+            kind,
+            error,
+            attributes,
+            ..
+        } = self.fields;
+
+        let mut span = json!({
+            "traceId": hex::encode(self.trace_id),
+            "spanId": hex::encode(self.span_id),
+            "name": name,
+            "kind": kind,
+            "startTimeUnixNano": unix_nanos(self.start),
+            "endTimeUnixNano": unix_nanos(end),
+            "attributes": attributes,
+            "status": { "code": match error {
+                Some(true) => 2,
+                Some(false) => 1,
+                None => 0,
+            }},
+        });
+        if let Some(parent_span_id) = self.parent_span_id {
+            span["parentSpanId"] = json!(hex::encode(parent_span_id));
+        }
+
+        span
+    }
+}
+
+// region:    --- Fields
+
+struct SpanFields {
+    name: String,
+    /// OTLP `SpanKind`.
+    kind: u8,
+    /// Status, None when unset.
+    error: Option<bool>,
+    traceparent: Option<String>,
+    /// OTLP `KeyValue`s.
+    attributes: Vec<Value>,
+}
+
+impl SpanFields {
+    fn new(name: &str) -> Self {
+        SpanFields {
+            name: name.to_string(),
+            kind: 1,
+            error: None,
+            traceparent: None,
+            attributes: Vec::new(),
+        }
+    }
+
+    /// Replaces the previous value of the attribute, if any.
+    fn set_attribute(&mut self, key: &str, value: Value) {
+        let attribute = json!({ "key": key, "value": value });
+        match self.attributes.iter_mut().find(|a| a["key"] == key) {
+            Some(previous) => *previous = attribute,
+            None => self.attributes.push(attribute),
+        }
+    }
+}
+
+impl Visit for SpanFields {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        match field.name() {
+            "otel.name" => self.name = value.to_string(),
+            "otel.kind" => {
+                self.kind = match value {
+                    "server" => 2,
+                    "client" => 3,
+                    _ => 1,
+                }
+            }
+            "otel.status_code" => self.error = Some(value.eq_ignore_ascii_case("error")),
+            TRACEPARENT_FIELD => self.traceparent = Some(value.to_string()),
+            name => self.set_attribute(name, json!({ "stringValue": value })),
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        self.record_str(field, &format!("{value:?}"));
+    }
+
+    // int64 as a string in the OTLP json encoding
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.set_attribute(field.name(), json!({ "intValue": value.to_string() }));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.set_attribute(field.name(), json!({ "intValue": value.to_string() }));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.set_attribute(field.name(), json!({ "boolValue": value }));
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.set_attribute(field.name(), json!({ "doubleValue": value }));
+    }
+}
+
+// endregion: --- Fields
+
+// region:    --- Trace Context
+
+struct RemoteParent {
+    trace_id: [u8; 16],
+    span_id: [u8; 8],
+    sampled: bool,
+}
+
+/// Parses a W3C `traceparent` (`00-<trace id>-<parent id>-<flags>`).
+/// None when invalid, the trace is then restarted.
+fn parse_traceparent(traceparent: &str) -> Option<RemoteParent> {
+    let mut parts = traceparent.trim().split('-');
+    let version = parts.next()?;
+    let trace_id = parts.next()?;
+    let span_id = parts.next()?;
+    let flags = parts.next()?;
+    // more parts are allowed by the future versions only
+    if version.len() != 2 || version == "ff" || (version == "00" && parts.next().is_some()) {
+        return None;
+    }
+
+    let trace_id: [u8; 16] = decode_hex_id(trace_id)?;
+    let span_id: [u8; 8] = decode_hex_id(span_id)?;
+    let flags: [u8; 1] = decode_hex_id(flags)?;
+
+    Some(RemoteParent {
+        trace_id,
+        span_id,
+        sampled: flags[0] & 0x01 == 0x01,
+    })
+}
+
+/// Lowercase hex, and not all zeros.
+fn decode_hex_id<const N: usize>(hex_id: &str) -> Option<[u8; N]> {
+    if hex_id.len() != N * 2 || hex_id.bytes().any(|b| b.is_ascii_uppercase()) {
+        return None;
+    }
+    let id: [u8; N] = hex::decode(hex_id).ok()?.try_into().ok()?;
+    // the flags can be all zeros
+    (N == 1 || id.iter().any(|b| *b != 0)).then_some(id)
+}
+
+fn new_trace_id() -> [u8; 16] {
+    loop {
+        let id: [u8; 16] = rand::random();
+        if id != [0; 16] {
+            return id;
+        }
+    }
+}
+
+fn new_span_id() -> [u8; 8] {
+    loop {
+        let id: [u8; 8] = rand::random();
+        if id != [0; 8] {
+            return id;
+        }
+    }
+}
+
+// endregion: --- Trace Context
+
+/// As a string, uint64 in the OTLP json encoding.
+fn unix_nanos(time: SystemTime) -> String {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or_default()
+        .to_string()
+}
+
+// region:    --- Tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn test_parse_traceparent_ok() -> Result<()> {
+        // -- Exec
+        let parent = parse_traceparent("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-00")
+            .ok_or_else(|| anyhow::anyhow!("should parse"))?;
+
+        // -- Check
+        assert_eq!(
+            hex::encode(parent.trace_id),
+            "4bf92f3577b34da6a3ce929d0e0e4736"
+        );
+        assert_eq!(hex::encode(parent.span_id), "00f067aa0ba902b7");
+        assert!(!parent.sampled);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_traceparent_err_invalid() -> Result<()> {
+        // -- Setup & Fixtures
+        let fx_traceparents = &[
+            // zero trace id
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+            // zero parent id
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01",
+            // uppercase
+            "00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01",
+            // invalid version
+            "ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            // more parts in version 00
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-extra",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7",
+            "not a traceparent",
+        ];
+
+        // -- Exec & Check
+        for fx_traceparent in fx_traceparents {
+            assert!(
+                parse_traceparent(fx_traceparent).is_none(),
+                "{fx_traceparent} should not parse"
+            );
+        }
+
+        Ok(())
+    }
+}
+// endregion: --- Tests
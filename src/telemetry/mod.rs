@@ -0,0 +1,151 @@
+//! OpenTelemetry traces of the `tracing` spans, exported with OTLP (http/json).
+//!
+//! Design:
+//!
+//! - `OtelLayer` is a `tracing_subscriber` layer giving the W3C trace and span ids to the spans
+//!   (of this crate, see `main`), and sending them to the exporter when they close.
+//! - A root span with a `traceparent` field (the W3C header of the request) continues
+//!   that trace, and keeps its sampling decision. The other root spans are sampled at
+//!   `sample_ratio`, and the child spans follow their parent.
+//! - Span fields are the span attributes, except for:
+//!   - `otel.name` - the span name (instead of the static name of the span).
+//!   - `otel.kind` - `server`, `client` or `internal` (default).
+//!   - `otel.status_code` - `error` (or `ok`).
+//! - The exporter task posts the spans to `<endpoint>/v1/traces` by batches, every
+//!   `EXPORT_INTERVAL`. When it can't keep up, the spans are dropped (never blocking).
+
+// region:    --- Modules
+
+mod export;
+mod layer;
+
+pub use self::export::OtelExporter;
+pub use self::layer::OtelLayer;
+
+// endregion: --- Modules
+
+pub struct OtelConfig {
+    /// Base url of the collector (e.g., `http://localhost:4318`).
+    pub endpoint: String,
+    /// Between 0 (none) and 1 (all), for the traces without sampled parent.
+    pub sample_ratio: f64,
+}
+
+/// Returns the layer to add to the subscriber, and the exporter to flush at shutdown.
+/// Note: starts the exporter task, so must be called within the runtime.
+pub fn new_layer(config: &OtelConfig) -> (OtelLayer, OtelExporter) {
+    let (exporter, sender) = OtelExporter::start(&config.endpoint);
+    (OtelLayer::new(sender, config.sample_ratio), exporter)
+}
+
+// region:    --- Tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use axum::routing::post;
+    use axum::{Json, Router};
+    use serde_json::Value;
+    use std::time::Duration;
+    use tokio::sync::mpsc;
+    use tracing::info_span;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    const FX_TRACE_ID: &str = "4bf92f3577b34da6a3ce929d0e0e4736";
+    const FX_PARENT_ID: &str = "00f067aa0ba902b7";
+
+    /// Returns the endpoint of the collector stand-in, and the received bodies.
+    async fn start_collector() -> Result<(String, mpsc::UnboundedReceiver<Value>)> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let app = Router::new().route(
+            "/v1/traces",
+            post(move |Json(body): Json<Value>| async move {
+                let _ = tx.send(body);
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let endpoint = format!("http://{}", listener.local_addr()?);
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        Ok((endpoint, rx))
+    }
+
+    fn find_span<'a>(spans: &'a [Value], name: &str) -> &'a Value {
+        spans
+            .iter()
+            .find(|span| span["name"] == name)
+            .unwrap_or_else(|| panic!("no span {name} in {spans:?}"))
+    }
+
+    fn attribute<'a>(span: &'a Value, key: &str) -> Option<&'a Value> {
+        span["attributes"]
+            .as_array()?
+            .iter()
+            .find(|attribute| attribute["key"] == key)
+            .map(|attribute| &attribute["value"])
+    }
+
+    #[tokio::test]
+    async fn test_export_traceparent_ok() -> Result<()> {
+        // -- Setup & Fixtures
+        let (endpoint, mut received) = start_collector().await?;
+        let (layer, exporter) = new_layer(&OtelConfig {
+            endpoint,
+            sample_ratio: 0.,
+        });
+        let subscriber = tracing_subscriber::registry().with(layer);
+        let fx_traceparent = format!("00-{FX_TRACE_ID}-{FX_PARENT_ID}-01");
+
+        // -- Exec
+        tracing::subscriber::with_default(subscriber, || {
+            {
+                let request = info_span!(
+                    "request",
+                    otel.kind = "server",
+                    traceparent = fx_traceparent.as_str(),
+                    user_id = tracing::field::Empty,
+                );
+                request.record("user_id", 1000);
+                let _request = request.enter();
+                let _rpc = info_span!("rpc", otel.name = "list_tasks").entered();
+                let _sql =
+                    info_span!("sql", otel.kind = "client", otel.status_code = "error").entered();
+            }
+
+            // not sampled (ratio 0), not exported, neither its children
+            let _other = info_span!("other_request").entered();
+            let _other_child = info_span!("other_child").entered();
+        });
+        exporter.flush().await;
+        let body = tokio::time::timeout(Duration::from_secs(5), received.recv())
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("collector closed"))?;
+
+        // -- Check
+        let spans = body["resourceSpans"][0]["scopeSpans"][0]["spans"]
+            .as_array()
+            .cloned()
+            .unwrap_or_default();
+        assert_eq!(spans.len(), 3, "{spans:?}");
+        let request = find_span(&spans, "request");
+        let rpc = find_span(&spans, "list_tasks");
+        let sql = find_span(&spans, "sql");
+        for span in [request, rpc, sql] {
+            assert_eq!(span["traceId"], FX_TRACE_ID);
+        }
+        assert_eq!(request["parentSpanId"], FX_PARENT_ID);
+        assert_eq!(rpc["parentSpanId"], request["spanId"]);
+        assert_eq!(sql["parentSpanId"], rpc["spanId"]);
+        assert_eq!(request["kind"], 2);
+        assert_eq!(sql["kind"], 3);
+        assert_eq!(sql["status"]["code"], 2);
+        assert_eq!(
+            attribute(request, "user_id"),
+            Some(&serde_json::json!({"intValue": "1000"}))
+        );
+        assert_eq!(attribute(request, "traceparent"), None);
+
+        Ok(())
+    }
+}
+// endregion: --- Tests
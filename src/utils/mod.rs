@@ -12,6 +12,45 @@ pub fn format_time(time: OffsetDateTime) -> String {
     time.format(&Rfc3339).unwrap() // TODO: need to check if safe
 }
 
+/// Serializes the time in Rfc3339, for `#[serde(serialize_with = "serialize_time")]`.
+pub fn serialize_time<S>(
+    time: &OffsetDateTime,
+    serializer: S,
+) -> core::result::Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    serializer.serialize_str(&format_time(*time))
+}
+
+/// Same as `serialize_time`, for `Option<OffsetDateTime>`.
+pub fn serialize_time_opt<S>(
+    time: &Option<OffsetDateTime>,
+    serializer: S,
+) -> core::result::Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    match time {
+        Some(time) => serialize_time(time, serializer),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// Deserializes an optional Rfc3339 time,
+/// for `#[serde(default, deserialize_with = "deserialize_time_opt")]`.
+pub fn deserialize_time_opt<'de, D>(
+    deserializer: D,
+) -> core::result::Result<Option<OffsetDateTime>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let moment: Option<String> = serde::Deserialize::deserialize(deserializer)?;
+    moment
+        .map(|moment| parse_utc(&moment).map_err(serde::de::Error::custom))
+        .transpose()
+}
+
 pub fn now_utc_plus_sec_str(sec: f64) -> String {
     let new_time = now_utc() + Duration::seconds_f64(sec);
     format_time(new_time)
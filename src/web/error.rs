@@ -20,6 +20,11 @@ pub enum Error {
     LoginFailUserHasNoPwd { user_id: i64 },
     LoginFailPwdNotMatching { user_id: i64 },
 
+    // -- Attachments
+    UploadFail(String),
+    UploadFileMissing,
+    DownloadFail(String),
+
     // -- CtxExtError
     CtxExt(web::mw_auth::CtxExtError),
 
@@ -89,6 +94,9 @@ impl Error {
             | LoginFailUserHasNoPwd { .. }
             | LoginFailPwdNotMatching { .. } => (StatusCode::FORBIDDEN, ClientError::LOGIN_FAIL),
             CtxExt(_) => (StatusCode::FORBIDDEN, ClientError::NO_AUTH),
+            Model(model::Error::CtxNoWorkspace { .. }) => {
+                (StatusCode::FORBIDDEN, ClientError::NO_WORKSPACE)
+            }
 
             // -- Model
             // When matching on a reference, you get a reference to the fields,
@@ -97,6 +105,73 @@ impl Error {
                 StatusCode::NOT_FOUND,
                 ClientError::ENTITY_NOT_FOUND { entity, id: *id },
             ),
+            Model(model::Error::EntityVersionConflict {
+                entity,
+                id,
+                current_version,
+            }) => (
+                StatusCode::CONFLICT,
+                ClientError::ENTITY_VERSION_CONFLICT {
+                    entity,
+                    id: *id,
+                    current_version: *current_version,
+                },
+            ),
+            Model(model::Error::TaskParentCycle { id, parent_id }) => (
+                StatusCode::BAD_REQUEST,
+                ClientError::TASK_PARENT_CYCLE {
+                    id: *id,
+                    parent_id: *parent_id,
+                },
+            ),
+            Model(model::Error::TaskBlockerCycle { id, blocker_id }) => (
+                StatusCode::BAD_REQUEST,
+                ClientError::TASK_BLOCKER_CYCLE {
+                    id: *id,
+                    blocker_id: *blocker_id,
+                },
+            ),
+            Model(model::Error::TaskHasOpenBlockers { id, blocker_ids }) => (
+                StatusCode::CONFLICT,
+                ClientError::TASK_HAS_OPEN_BLOCKERS {
+                    id: *id,
+                    blocker_ids: blocker_ids.clone(),
+                },
+            ),
+            Model(model::Error::AdminRequired { .. }) => {
+                (StatusCode::FORBIDDEN, ClientError::ADMIN_REQUIRED)
+            }
+            Model(model::Error::CommentNotAuthor { id, .. }) => (
+                StatusCode::FORBIDDEN,
+                ClientError::COMMENT_NOT_AUTHOR { id: *id },
+            ),
+            Model(model::Error::AttachmentQuotaExceeded { quota, used }) => (
+                StatusCode::PAYLOAD_TOO_LARGE,
+                ClientError::ATTACHMENT_QUOTA_EXCEEDED {
+                    quota: *quota,
+                    used: *used,
+                },
+            ),
+            Model(model::Error::ListLimitOverMax { max, actual }) => (
+                StatusCode::BAD_REQUEST,
+                ClientError::LIST_LIMIT_OVER_MAX {
+                    max: *max,
+                    actual: *actual,
+                },
+            ),
+            Model(model::Error::BulkCreateOverMax { max, actual }) => (
+                StatusCode::BAD_REQUEST,
+                ClientError::BULK_CREATE_OVER_MAX {
+                    max: *max,
+                    actual: *actual,
+                },
+            ),
+            Model(model::Error::BulkFilterEmpty { .. }) => {
+                (StatusCode::BAD_REQUEST, ClientError::BULK_FILTER_EMPTY)
+            }
+            UploadFail(_) | UploadFileMissing => {
+                (StatusCode::BAD_REQUEST, ClientError::UPLOAD_INVALID)
+            }
             // -- Fallback.
             _ => (
                 StatusCode::INTERNAL_SERVER_ERROR,
@@ -113,7 +188,57 @@ impl Error {
 pub enum ClientError {
     LOGIN_FAIL,
     NO_AUTH,
-    ENTITY_NOT_FOUND { entity: &'static str, id: i64 },
+    /// The user is not a member of any workspace.
+    NO_WORKSPACE,
+    ENTITY_NOT_FOUND {
+        entity: &'static str,
+        id: i64,
+    },
+    ENTITY_VERSION_CONFLICT {
+        entity: &'static str,
+        id: i64,
+        current_version: i64,
+    },
+    /// The parent is the task itself, or one of its subtasks.
+    TASK_PARENT_CYCLE {
+        id: i64,
+        parent_id: i64,
+    },
+    /// The blocker is the task itself, or is (transitively) blocked by it.
+    TASK_BLOCKER_CYCLE {
+        id: i64,
+        blocker_id: i64,
+    },
+    /// The task can't be done before its blockers.
+    TASK_HAS_OPEN_BLOCKERS {
+        id: i64,
+        blocker_ids: Vec<i64>,
+    },
+    /// Only for the admin users.
+    ADMIN_REQUIRED,
+    /// Only the author of a comment can edit it.
+    COMMENT_NOT_AUTHOR {
+        id: i64,
+    },
+    /// The attachments of the user would be over its quota (in bytes).
+    ATTACHMENT_QUOTA_EXCEEDED {
+        quota: i64,
+        used: i64,
+    },
+    /// Not a multipart body with a `file` part.
+    UPLOAD_INVALID,
+    /// The `limit` of the list options is over the max.
+    LIST_LIMIT_OVER_MAX {
+        max: i64,
+        actual: i64,
+    },
+    /// Too many entities in a bulk create.
+    BULK_CREATE_OVER_MAX {
+        max: usize,
+        actual: usize,
+    },
+    /// A bulk update/delete needs a filter (and no empty filter group).
+    BULK_FILTER_EMPTY,
     SERVICE_ERROR,
 }
 // endregion: --- Client Error
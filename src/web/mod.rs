@@ -1,13 +1,35 @@
 mod error;
 pub mod mw_auth;
+pub mod mw_metrics;
+pub mod mw_request_id;
 pub mod mw_res_map;
+pub mod mw_trace;
+pub mod routes_attachment;
+pub mod routes_health;
 pub mod routes_login;
+pub mod routes_metrics;
+pub mod routes_sse;
 pub mod routes_static;
+pub mod routes_ws;
 pub mod rpc;
 
+use axum::body::{Body, HttpBody};
+use axum::extract::ConnectInfo;
+use axum::http::header::USER_AGENT;
+use axum::http::Request;
+use serde_json::{json, Value};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Instant;
 use tower_cookies::{Cookie, Cookies};
+use tracing::error;
 
 use crate::crypt::token::generate_web_token;
+use crate::ctx::{Ctx, CtxOrigin};
+use crate::model::audit_event::{AuditEventBmc, AuditEventForCreate};
+use crate::model::change_event::ChangeEvent;
+use crate::model::ModelManager;
+use crate::web::mw_request_id::RequestId;
 
 pub use self::error::ClientError;
 pub use self::error::{Error, Result};
@@ -15,6 +37,62 @@ pub use self::error::{Error, Result};
 // endregion: --- Modules
 
 pub const AUTH_TOKEN: &str = "auth-token";
+/// Active workspace, set at login.
+pub const WORKSPACE_COOKIE: &str = "workspace-id";
+/// Selects the active workspace of a request, over the cookie.
+pub const WORKSPACE_HEADER: &str = "x-workspace-id";
+
+/// Taken by `mw_ctx_resolve` (when the request gets to it), for the request log line
+/// and the audit events.
+#[derive(Clone)]
+pub struct RequestInfo {
+    pub start: Instant,
+    /// Also on the ctx (see `Ctx::origin`).
+    /// Note: the client ip is the peer address (`None` on the unix socket),
+    ///       the `X-Forwarded-For` header is not trusted.
+    pub origin: Arc<CtxOrigin>,
+    /// `None` when not known upfront (e.g., chunked).
+    pub body_size: Option<u64>,
+}
+
+impl RequestInfo {
+    fn new(req: &Request<Body>) -> Self {
+        let origin = CtxOrigin {
+            request_id: req
+                .extensions()
+                .get::<RequestId>()
+                .map(|request_id| request_id.as_str().to_string()),
+            client_ip: req
+                .extensions()
+                .get::<ConnectInfo<SocketAddr>>()
+                .map(|ConnectInfo(addr)| addr.ip()),
+            user_agent: req
+                .headers()
+                .get(USER_AGENT)
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_string),
+        };
+
+        RequestInfo {
+            start: Instant::now(),
+            origin: Arc::new(origin),
+            body_size: req.body().size_hint().exact(),
+        }
+    }
+
+    /// The root ctx, for the audit events of the requests without user ctx (e.g., login).
+    fn root_ctx(&self) -> Ctx {
+        Ctx::root_ctx().with_origin(self.origin.clone())
+    }
+}
+
+/// Records the audit event with the origin of the ctx.
+/// A failure is logged, but does not fail the request.
+async fn record_audit(ctx: &Ctx, mm: &ModelManager, event_c: AuditEventForCreate) {
+    if let Err(ex) = AuditEventBmc::record(ctx, mm, event_c).await {
+        error!("{:<12} - audit event record fail: {ex}", "AUDIT");
+    }
+}
 
 fn set_token_cookie(cookies: &Cookies, user: &str, salt: &str) -> Result<()> {
     let token = generate_web_token(user, salt)?;
@@ -35,3 +113,35 @@ fn remove_token_cookie(cookies: &Cookies) -> Result<()> {
 
     Ok(())
 }
+
+fn set_workspace_cookie(cookies: &Cookies, workspace_id: i64) {
+    let mut cookie = Cookie::new(WORKSPACE_COOKIE, workspace_id.to_string());
+    cookie.set_http_only(true);
+    cookie.set_path("/");
+    cookies.add(cookie);
+}
+
+fn remove_workspace_cookie(cookies: &Cookies) {
+    let mut cookie = Cookie::from(WORKSPACE_COOKIE);
+    cookie.set_path("/");
+    cookies.remove(cookie);
+}
+
+/// Change event as sent to the clients (ws and sse),
+/// or None if the ctx cannot see the entity.
+async fn change_event_json(ctx: &Ctx, mm: &ModelManager, event: ChangeEvent) -> Option<Value> {
+    let ChangeEvent {
+        entity,
+        op,
+        entity_id,
+        data,
+        ..
+    } = event.for_ctx(ctx, mm).await?;
+
+    Some(json!({
+        "topic": entity,
+        "op": op,
+        "id": entity_id,
+        "data": data,
+    }))
+}
@@ -1,23 +1,24 @@
 use crate::crypt::token::{validate_web_token, Token};
 use crate::ctx::Ctx;
+use crate::model::audit_event::{AuditEventForCreate, AuditKind};
 use crate::model::user::{UserBmc, UserForAuth};
+use crate::model::workspace::WorkspaceBmc;
 use crate::model::ModelManager;
-use crate::web::AUTH_TOKEN;
 use crate::web::{Error, Result};
+use crate::web::{RequestInfo, AUTH_TOKEN, WORKSPACE_COOKIE, WORKSPACE_HEADER};
 use async_trait::async_trait;
 use axum::body::Body;
 use axum::extract::{FromRequestParts, State};
 use axum::http::request::Parts;
-use axum::http::Request;
+use axum::http::{HeaderMap, Request};
 use axum::middleware::Next;
 use axum::response::Response;
 use serde::Serialize;
+use serde_json::json;
 use tower_cookies::{Cookie, Cookies};
-use tracing::debug;
+use tracing::{debug, Span};
 
-use super::set_token_cookie;
-
-#[allow(dead_code)] // For now, until we have the rpc.
+use super::{record_audit, set_token_cookie};
 
 /// Checks that there is no error in the ctx. If there is, returs early.
 /// Under the hood, checks the from_request_parts method
@@ -42,24 +43,52 @@ pub async fn mw_ctx_resolve(
     next: Next,
 ) -> Result<Response> {
     debug!("{:<12} - mw_ctx_resolve", "MIDDLEWARE");
+    let req_info = RequestInfo::new(&req);
 
     // should NOT fail if there is an error. It is the responsibility of the ctx auth
     // or other things downstream, so no ?
-    let ctx_ext_result = _ctx_resolve(mm, &cookies).await;
+    let ctx_ext_result = _ctx_resolve(mm.clone(), &cookies, req.headers())
+        .await
+        .map(|ctx| ctx.with_origin(req_info.origin.clone()));
+    if let Some(event_c) = ctx_ext_result
+        .as_ref()
+        .err()
+        .and_then(CtxExtError::audit_event)
+    {
+        record_audit(&req_info.root_ctx(), &mm, event_c).await;
+    }
 
     // Remove the cookie if something went wrong because we don't want to keep validating
     // a cookie that already failed once
-    if ctx_ext_result.is_err() && !matches!(ctx_ext_result, Err(CtxExtError::TokenNotInCookie)) {
+    // (the token is fine when only the workspace is not)
+    if ctx_ext_result.is_err()
+        && !matches!(
+            ctx_ext_result,
+            Err(CtxExtError::TokenNotInCookie
+                | CtxExtError::WorkspaceIdWrongFormat
+                | CtxExtError::WorkspaceNotMember { .. })
+        )
+    {
         cookies.remove(Cookie::from(AUTH_TOKEN))
     }
 
+    // for the traces (field of the `request` span of `mw_trace`)
+    if let Ok(ctx) = &ctx_ext_result {
+        Span::current().record("user_id", ctx.user_id());
+    }
+
     // Store the ctx_result in the request extension.
     req.extensions_mut().insert(ctx_ext_result);
+    req.extensions_mut().insert(req_info);
 
     Ok(next.run(req).await)
 }
 
-async fn _ctx_resolve(mm: State<ModelManager>, cookies: &Cookies) -> CtxExtResult {
+async fn _ctx_resolve(
+    mm: State<ModelManager>,
+    cookies: &Cookies,
+    headers: &HeaderMap,
+) -> CtxExtResult {
     // -- Get token string
 
     let token = cookies
@@ -81,15 +110,58 @@ async fn _ctx_resolve(mm: State<ModelManager>, cookies: &Cookies) -> CtxExtResul
 
     // -- Validate token
     validate_web_token(&token, &user.token_salt.to_string())
-        .map_err(|_| CtxExtError::FailValidate)?;
+        .map_err(|_| CtxExtError::FailValidate { user_id: user.id })?;
 
     // -- Update token
     set_token_cookie(cookies, &user.username, &user.token_salt.to_string())
         .map_err(|_| CtxExtError::CanNotSetTokenCookie)?;
 
+    // -- Select the workspace
+    // from the header, else the cookie, else the first one of the user
+    let workspace_id = headers
+        .get(WORKSPACE_HEADER)
+        .map(|value| value.to_str().unwrap_or_default().to_string())
+        .or_else(|| cookies.get(WORKSPACE_COOKIE).map(|c| c.value().to_string()))
+        .map(|value| value.parse::<i64>())
+        .transpose()
+        .map_err(|_| CtxExtError::WorkspaceIdWrongFormat)?;
+    let workspace_id = resolve_workspace(&mm, user.id, workspace_id).await?;
+
     // -- Create CtxExtResult, it is independent from the web layer now that the
     // validation is done
-    Ctx::new(user.id).map_err(|ex| CtxExtError::CtxCreateFail(ex.to_string()))
+    Ctx::new(user.id, workspace_id).map_err(|ex| CtxExtError::CtxCreateFail(ex.to_string()))
+}
+
+/// The workspace if the user is a member of it, or by default the first workspace
+/// of the user (None if it has none).
+pub async fn resolve_workspace(
+    mm: &ModelManager,
+    user_id: i64,
+    workspace_id: Option<i64>,
+) -> core::result::Result<Option<i64>, CtxExtError> {
+    let root_ctx = Ctx::root_ctx();
+    let to_ext_error = |ex: crate::model::Error| CtxExtError::ModelAccessError(ex.to_string());
+
+    match workspace_id {
+        Some(workspace_id) => {
+            let is_member = WorkspaceBmc::is_member(&root_ctx, mm, workspace_id, user_id)
+                .await
+                .map_err(to_ext_error)?;
+            if !is_member {
+                return Err(CtxExtError::WorkspaceNotMember {
+                    workspace_id,
+                    user_id,
+                });
+            }
+            Ok(Some(workspace_id))
+        }
+        None => {
+            let workspaces = WorkspaceBmc::list_for_user(&root_ctx, mm, user_id)
+                .await
+                .map_err(to_ext_error)?;
+            Ok(workspaces.first().map(|workspace| workspace.id))
+        }
+    }
 }
 
 // region:    --- Ctx Extractor
@@ -121,9 +193,40 @@ pub enum CtxExtError {
     TokenWrongFormat,
     UserNotFound,             // we don't capture the name
     ModelAccessError(String), // we don't want the full model error over there
-    FailValidate,
+    FailValidate { user_id: i64 },
     CanNotSetTokenCookie,
     CtxNotInRequestExt,
     CtxCreateFail(String),
+    WorkspaceIdWrongFormat,
+    WorkspaceNotMember { workspace_id: i64, user_id: i64 },
+}
+
+impl CtxExtError {
+    /// For the failures to audit: the rejected tokens (not the missing ones),
+    /// and the workspaces the user is not a member of.
+    fn audit_event(&self) -> Option<AuditEventForCreate> {
+        let token_fail = |user_id: Option<i64>, reason: &str| AuditEventForCreate {
+            kind: AuditKind::TokenFail,
+            user_id,
+            detail: json!({ "reason": reason }),
+        };
+
+        let event_c = match self {
+            Self::TokenWrongFormat => token_fail(None, "TokenWrongFormat"),
+            Self::UserNotFound => token_fail(None, "UserNotFound"),
+            Self::FailValidate { user_id } => token_fail(Some(*user_id), "FailValidate"),
+            Self::WorkspaceNotMember {
+                workspace_id,
+                user_id,
+            } => AuditEventForCreate {
+                kind: AuditKind::PermissionDenied,
+                user_id: Some(*user_id),
+                detail: json!({ "action": "select_workspace", "workspace_id": workspace_id }),
+            },
+            _ => return None,
+        };
+
+        Some(event_c)
+    }
 }
 // endregion: --- Ctx Extractor Result/Error
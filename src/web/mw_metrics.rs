@@ -0,0 +1,43 @@
+use crate::metrics;
+use crate::web::rpc::RpcInfo;
+use crate::web::Error;
+use axum::body::Body;
+use axum::extract::MatchedPath;
+use axum::http::Request;
+use axum::middleware::Next;
+use axum::response::Response;
+use std::sync::Arc;
+use std::time::Instant;
+
+/// Records the request metrics, once the response is mapped by `mw_reponse_map`
+/// (so the outermost of the layers).
+pub async fn mw_metrics(req: Request<Body>, next: Next) -> Response {
+    let start = Instant::now();
+    let method = req.method().to_string();
+    // the route, and not the path, so that the ids don't make a label each
+    let route = req
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_else(|| "unmatched".to_string());
+
+    let res = next.run(req).await;
+
+    let duration = start.elapsed();
+    let web_error = res.extensions().get::<Arc<Error>>().map(Arc::as_ref);
+    metrics::record_http(&method, &route, res.status().as_u16(), duration);
+    if let Some(rpc_info) = res.extensions().get::<Arc<RpcInfo>>() {
+        // the unknown methods are any string from the client, one label for all
+        let rpc_method = match web_error {
+            Some(Error::RpcMethodUnknown(_)) => "unknown",
+            _ => rpc_info.method.as_str(),
+        };
+        metrics::record_rpc(rpc_method, web_error.is_none(), duration);
+    }
+    if let Some(web_error) = web_error {
+        let (_, client_error) = web_error.client_status_and_error();
+        metrics::record_client_error(client_error.as_ref());
+    }
+
+    res
+}
@@ -0,0 +1,134 @@
+use axum::body::Body;
+use axum::http::{HeaderValue, Request};
+use axum::middleware::Next;
+use axum::response::Response;
+use std::sync::Arc;
+use tracing::debug;
+use uuid::Uuid;
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+/// Longer ids from the caller are replaced.
+const REQUEST_ID_MAX_LEN: usize = 128;
+
+/// Id of the request, in the request extensions (and on the `Ctx`), for the spans,
+/// the request log line and the error bodies.
+#[derive(Clone, Debug)]
+pub struct RequestId(Arc<str>);
+
+impl RequestId {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+/// Outermost, for all the responses (even the ones without ctx resolve):
+/// accepts the `X-Request-Id` of the caller (e.g., a proxy), or generates one,
+/// and returns it in the `X-Request-Id` header of the response.
+pub async fn mw_request_id(mut req: Request<Body>, next: Next) -> Response {
+    let from_caller = req
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok());
+    let request_id = match from_caller {
+        Some(id) if is_valid(id) => id.to_string(),
+        Some(id) => {
+            debug!(
+                "{:<12} - invalid request id from caller: {id:?}",
+                "MIDDLEWARE"
+            );
+            Uuid::new_v4().to_string()
+        }
+        None => Uuid::new_v4().to_string(),
+    };
+    let header_value = HeaderValue::from_str(&request_id).ok();
+    req.extensions_mut()
+        .insert(RequestId(Arc::from(request_id.as_str())));
+
+    let mut res = next.run(req).await;
+    if let Some(header_value) = header_value {
+        res.headers_mut().insert(REQUEST_ID_HEADER, header_value);
+    }
+
+    res
+}
+
+/// Not empty, up to `REQUEST_ID_MAX_LEN` of `[a-zA-Z0-9._:-]` (e.g., uuids),
+/// so that it is safe in the logs and the headers.
+fn is_valid(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= REQUEST_ID_MAX_LEN
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | ':' | '-'))
+}
+
+// region:    --- Tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use axum::middleware;
+    use axum::routing::get;
+    use axum::{Extension, Router};
+    use tower::ServiceExt;
+
+    async fn echo_request_id(Extension(request_id): Extension<RequestId>) -> String {
+        request_id.as_str().to_string()
+    }
+
+    fn fx_app() -> Router {
+        Router::new()
+            .route("/echo", get(echo_request_id))
+            .layer(middleware::from_fn(mw_request_id))
+    }
+
+    async fn call(header: Option<&str>) -> Result<(String, String)> {
+        let mut req = Request::builder().uri("/echo");
+        if let Some(header) = header {
+            req = req.header(REQUEST_ID_HEADER, header);
+        }
+        let res = fx_app().oneshot(req.body(Body::empty())?).await?;
+        let header = res
+            .headers()
+            .get(REQUEST_ID_HEADER)
+            .map(|v| v.to_str().map(str::to_string))
+            .transpose()?
+            .unwrap_or_default();
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX).await?;
+
+        Ok((header, String::from_utf8(body.to_vec())?))
+    }
+
+    #[tokio::test]
+    async fn test_request_id_from_caller_ok() -> Result<()> {
+        // -- Setup & Fixtures
+        let fx_request_id = "proxy-1234.abc";
+
+        // -- Exec
+        let (header, ext) = call(Some(fx_request_id)).await?;
+
+        // -- Check
+        assert_eq!(header, fx_request_id);
+        assert_eq!(ext, fx_request_id);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_request_id_generated_ok() -> Result<()> {
+        // -- Exec
+        let (header, ext) = call(None).await?;
+        let (header_invalid, _) = call(Some("bad id\twith spaces")).await?;
+
+        // -- Check
+        assert_eq!(header, ext);
+        assert!(Uuid::parse_str(&header).is_ok(), "not a uuid: {header}");
+        assert!(
+            Uuid::parse_str(&header_invalid).is_ok(),
+            "not replaced: {header_invalid}"
+        );
+
+        Ok(())
+    }
+}
+// endregion: --- Tests
@@ -2,23 +2,30 @@ use std::sync::Arc;
 
 use crate::ctx::Ctx;
 use crate::log::log_request;
-use crate::web;
+use crate::web::mw_request_id::RequestId;
 use crate::web::rpc::RpcInfo;
+use crate::web::{self, RequestInfo};
+use axum::body::HttpBody;
 use axum::http::{Method, Uri};
 use axum::response::{IntoResponse, Response};
-use axum::Json;
+use axum::{Extension, Json};
 use serde_json::{json, to_value};
 use tracing::debug;
 use uuid::Uuid;
 
 pub async fn mw_reponse_map(
     ctx: Option<Ctx>,
+    req_info: Option<Extension<RequestInfo>>,
+    request_id: Option<Extension<RequestId>>,
     uri: Uri,
     req_method: Method,
     res: Response,
 ) -> Response {
     debug!("{:<12} - mw_reponse_map", "RES_MAPPER");
-    let uuid = Uuid::new_v4();
+    // set by `mw_request_id` (the outermost), so only missing without it (e.g., tests)
+    let request_id = request_id
+        .map(|Extension(request_id)| request_id.as_str().to_string())
+        .unwrap_or_else(|| Uuid::new_v4().to_string());
 
     // -- extract rpc info
     let rpc_info = res.extensions().get::<Arc<RpcInfo>>().map(Arc::as_ref);
@@ -40,7 +47,7 @@ pub async fn mw_reponse_map(
                 "error": {
                     "message": message, // Variant name
                     "data": {
-                        "req_uuid": uuid.to_string(),
+                        "req_uuid": request_id,
                         "detail": detail
                     },
                 }
@@ -52,21 +59,36 @@ pub async fn mw_reponse_map(
             (*status_code, Json(client_error_body)).into_response()
         });
 
-    // -- Build and log the server log line.
+    // -- Build the final response.
     let client_error = client_status_error.unzip().1;
+    // for the outer middlewares (e.g., metrics), as the error response is a new one
+    let rpc_info = res.extensions().get::<Arc<RpcInfo>>().cloned();
+    let web_error = res.extensions().get::<Arc<web::Error>>().cloned();
+    let mut res = error_response.unwrap_or(res);
+    if let Some(rpc_info) = &rpc_info {
+        res.extensions_mut().insert(rpc_info.clone());
+    }
+    if let Some(web_error) = &web_error {
+        res.extensions_mut().insert(web_error.clone());
+    }
+
+    // -- Log the server log line (with the final status and size).
     // TODO: Need to hander if log_request fail (but should not fail request)
     let _ = log_request(
-        uuid,
+        &request_id,
         req_method,
         uri,
-        rpc_info,
+        req_info.as_ref().map(|Extension(req_info)| req_info),
+        res.status(),
+        res.body().size_hint().exact(),
+        rpc_info.as_deref(),
         ctx,
-        web_error,
+        web_error.as_deref(),
         client_error,
     )
     .await;
 
     debug!("\n"); // FIXME: only used for local dev
 
-    error_response.unwrap_or(res)
+    res
 }
@@ -0,0 +1,51 @@
+use crate::web::mw_request_id::RequestId;
+use axum::body::Body;
+use axum::extract::MatchedPath;
+use axum::http::Request;
+use axum::middleware::Next;
+use axum::response::Response;
+use tracing::{field, info_span, Instrument};
+
+/// W3C trace context header of the caller.
+const TRACEPARENT: &str = "traceparent";
+
+/// Runs the request in the `request` span (see `telemetry`), continuing the trace of the
+/// `traceparent` header if any.
+/// The `request_id` is the one of `mw_request_id`, the inner middlewares record the `user_id`.
+pub async fn mw_trace(req: Request<Body>, next: Next) -> Response {
+    let method = req.method().to_string();
+    let route = req
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_else(|| "unmatched".to_string());
+    let traceparent = req
+        .headers()
+        .get(TRACEPARENT)
+        .and_then(|value| value.to_str().ok());
+    let request_id = req.extensions().get::<RequestId>().cloned();
+
+    let span = info_span!(
+        "request",
+        otel.name = %format!("{method} {route}"),
+        otel.kind = "server",
+        otel.status_code = field::Empty,
+        traceparent,
+        http.request.method = %method,
+        http.route = %route,
+        url.path = %req.uri().path(),
+        http.response.status_code = field::Empty,
+        user_id = field::Empty,
+        request_id = request_id.as_ref().map(RequestId::as_str),
+    );
+
+    let res = next.run(req).instrument(span.clone()).await;
+
+    let status = res.status();
+    span.record("http.response.status_code", status.as_u16());
+    if status.is_server_error() {
+        span.record("otel.status_code", "error");
+    }
+
+    res
+}
@@ -0,0 +1,161 @@
+//! Upload and download of the attachment contents (the other attachment apis are rpc).
+//!
+//! - `POST /api/tasks/:task_id/attachments`, multipart with a `file` part, streamed
+//!   to the blob store (no body size limit but the quota of the user).
+//! - `GET /api/attachments/:id`, with single `Range` requests support.
+
+use crate::ctx::Ctx;
+use crate::model::attachment::{Attachment, AttachmentBmc, AttachmentForCreate};
+use crate::model::ModelManager;
+use crate::web::{Error, Result};
+use axum::body::Body;
+use axum::extract::{DefaultBodyLimit, Multipart, Path, State};
+use axum::http::header::{
+    ACCEPT_RANGES, CONTENT_DISPOSITION, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, ETAG, RANGE,
+};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use futures::TryStreamExt;
+use std::ops::Range;
+use tracing::debug;
+
+const FILE_PART: &str = "file";
+const CONTENT_TYPE_DEFAULT: &str = "application/octet-stream";
+
+pub fn routes(mm: ModelManager) -> Router {
+    Router::new()
+        .route(
+            "/tasks/:task_id/attachments",
+            post(upload_handler).layer(DefaultBodyLimit::disable()),
+        )
+        .route("/attachments/:id", get(download_handler))
+        .with_state(mm)
+}
+
+async fn upload_handler(
+    State(mm): State<ModelManager>,
+    ctx: Ctx,
+    Path(task_id): Path<i64>,
+    mut multipart: Multipart,
+) -> Result<Json<Attachment>> {
+    debug!("{:<12} - upload_handler", "HANDLER");
+
+    while let Some(field) = multipart
+        .next_field()
+        .await
+        .map_err(|ex| Error::UploadFail(ex.body_text()))?
+    {
+        if field.name() != Some(FILE_PART) {
+            continue;
+        }
+
+        let attachment_c = AttachmentForCreate {
+            task_id,
+            name: field.file_name().unwrap_or(FILE_PART).to_string(),
+            content_type: field
+                .content_type()
+                .unwrap_or(CONTENT_TYPE_DEFAULT)
+                .to_string(),
+        };
+        let content = field.map_err(std::io::Error::other);
+        let attachment = AttachmentBmc::create(&ctx, &mm, attachment_c, content).await?;
+
+        return Ok(Json(attachment));
+    }
+
+    Err(Error::UploadFileMissing)
+}
+
+async fn download_handler(
+    State(mm): State<ModelManager>,
+    ctx: Ctx,
+    Path(id): Path<i64>,
+    headers: HeaderMap,
+) -> Result<Response> {
+    debug!("{:<12} - download_handler", "HANDLER");
+
+    let attachment = AttachmentBmc::get(&ctx, &mm, id).await?;
+    let size = attachment.size as u64;
+
+    let range = headers
+        .get(RANGE)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| parse_range(value, size))
+        .transpose();
+    let range = match range {
+        Ok(range) => range.flatten(),
+        Err(RangeNotSatisfiable) => {
+            return Ok((
+                StatusCode::RANGE_NOT_SATISFIABLE,
+                [(CONTENT_RANGE, format!("bytes */{size}"))],
+            )
+                .into_response());
+        }
+    };
+
+    let (_, content) = AttachmentBmc::open(&ctx, &mm, id, range.clone()).await?;
+
+    let builder = Response::builder()
+        .header(CONTENT_TYPE, &attachment.content_type)
+        .header(ACCEPT_RANGES, "bytes")
+        .header(ETAG, format!("\"{}\"", attachment.sha256))
+        .header(
+            CONTENT_DISPOSITION,
+            format!(
+                "attachment; filename=\"{}\"",
+                attachment.name.replace(['"', '\\', '\r', '\n'], "_")
+            ),
+        );
+    let builder = match range {
+        Some(range) => builder
+            .status(StatusCode::PARTIAL_CONTENT)
+            .header(CONTENT_LENGTH, range.end - range.start)
+            .header(
+                CONTENT_RANGE,
+                format!("bytes {}-{}/{size}", range.start, range.end - 1),
+            ),
+        None => builder.status(StatusCode::OK).header(CONTENT_LENGTH, size),
+    };
+
+    let response = builder
+        .body(Body::from_stream(content))
+        .map_err(|ex| Error::DownloadFail(ex.to_string()))?;
+
+    Ok(response)
+}
+
+struct RangeNotSatisfiable;
+
+/// The range of a `Range: bytes=...` header value for a content of `size` bytes.
+/// `None` when the range is to be ignored (invalid, or several ranges), as allowed
+/// by RFC 9110, so that the full content is sent.
+fn parse_range(
+    value: &str,
+    size: u64,
+) -> core::result::Result<Option<Range<u64>>, RangeNotSatisfiable> {
+    let Some((start, end)) = value
+        .strip_prefix("bytes=")
+        .filter(|spec| !spec.contains(','))
+        .and_then(|spec| spec.trim().split_once('-'))
+    else {
+        return Ok(None);
+    };
+
+    let range = match (start.parse::<u64>(), end.parse::<u64>()) {
+        // `bytes=0-99`
+        (Ok(start), Ok(end)) if start <= end => start..size.min(end + 1),
+        // `bytes=100-`
+        (Ok(start), Err(_)) if end.is_empty() => start..size,
+        // `bytes=-100`, the last 100
+        (Err(_), Ok(suffix)) if start.is_empty() => size.saturating_sub(suffix)..size,
+        _ => return Ok(None),
+    };
+
+    if range.start < range.end {
+        Ok(Some(range))
+    } else {
+        Err(RangeNotSatisfiable)
+    }
+}
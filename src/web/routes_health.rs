@@ -0,0 +1,69 @@
+//! Probes of the orchestrator, without auth and without request log lines.
+//!
+//! - `/healthz` - the process is alive.
+//! - `/readyz` - the db is reachable (within `READY_DB_TIMEOUT`) with the schema applied.
+//!   503 otherwise, with the failed checks.
+//! - `/version` - crate version and git hash of the build.
+
+use crate::config;
+use crate::model::ModelManager;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::routing::get;
+use axum::{Json, Router};
+use serde_json::{json, Value};
+use std::time::Duration;
+use tracing::warn;
+
+const READY_DB_TIMEOUT: Duration = Duration::from_secs(2);
+
+pub fn routes(mm: ModelManager) -> Router {
+    Router::new()
+        .route("/healthz", get(healthz_handler))
+        .route("/readyz", get(readyz_handler))
+        .route("/version", get(version_handler))
+        .with_state(mm)
+}
+
+async fn healthz_handler() -> Json<Value> {
+    Json(json!({ "status": "ok" }))
+}
+
+async fn readyz_handler(State(mm): State<ModelManager>) -> (StatusCode, Json<Value>) {
+    // loaded at startup, the app does not start otherwise
+    let _ = config();
+
+    let db_check = match tokio::time::timeout(READY_DB_TIMEOUT, mm.check_db()).await {
+        Ok(Ok(())) => Ok(()),
+        Ok(Err(ex)) => Err(format!("{ex:?}")),
+        Err(_) => Err(format!("timeout after {READY_DB_TIMEOUT:?}")),
+    };
+
+    match db_check {
+        Ok(()) => (
+            StatusCode::OK,
+            Json(json!({
+                "status": "ready",
+                "checks": { "config": "ok", "db": "ok" }
+            })),
+        ),
+        Err(cause) => {
+            warn!("{:<12} - not ready - db: {cause}", "READYZ");
+            (
+                StatusCode::SERVICE_UNAVAILABLE,
+                Json(json!({
+                    "status": "not_ready",
+                    "checks": { "config": "ok", "db": cause }
+                })),
+            )
+        }
+    }
+}
+
+async fn version_handler() -> Json<Value> {
+    Json(json!({
+        "name": env!("CARGO_PKG_NAME"),
+        "version": env!("CARGO_PKG_VERSION"),
+        "git_hash": env!("GIT_HASH"),
+    }))
+}
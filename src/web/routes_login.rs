@@ -1,11 +1,16 @@
 use crate::crypt::{pwd, EncryptContent};
 use crate::ctx::Ctx;
+use crate::metrics;
+use crate::model::audit_event::{AuditEventForCreate, AuditKind};
 use crate::model::user::{UserBmc, UserForLogin};
 use crate::model::ModelManager;
-use crate::web::{self, remove_token_cookie, Error, Result};
+use crate::web::mw_auth::resolve_workspace;
+use crate::web::{
+    self, record_audit, remove_token_cookie, remove_workspace_cookie, Error, RequestInfo, Result,
+};
 use axum::extract::State;
 use axum::routing::post;
-use axum::{Json, Router};
+use axum::{Extension, Json, Router};
 use serde::Deserialize;
 use serde_json::{json, Value};
 use tower_cookies::{Cookie, Cookies};
@@ -20,20 +25,36 @@ pub fn routes(mm: ModelManager) -> Router {
 
 async fn api_login_handler(
     State(mm): State<ModelManager>, // destructuring is optional because State implements Deref
+    req_info: Option<Extension<RequestInfo>>,
     cookies: Cookies,
     Json(payload): Json<LoginPayload>,
 ) -> Result<Json<Value>> {
     debug!("{:<12} - api_login_handler", "HANDLER");
 
+    let res = login(&mm, &cookies, payload).await;
+    metrics::record_login(res.is_ok());
+    let audit_ctx = req_info.map_or_else(Ctx::root_ctx, |Extension(req_info)| req_info.root_ctx());
+    record_audit(&audit_ctx, &mm, login_audit_event(&res)).await;
+
+    res.map(|(_, body)| body)
+}
+
+/// Returns the id of the logged in user, with the body.
+async fn login(
+    mm: &ModelManager,
+    cookies: &Cookies,
+    payload: LoginPayload,
+) -> Result<(i64, Json<Value>)> {
     let LoginPayload {
         username,
         pwd: pwd_clear,
+        workspace_id,
     } = payload;
 
     // we need to use the root_ctx to retrieve the user
     let root_ctx = Ctx::root_ctx();
 
-    let user: UserForLogin = UserBmc::first_by_username(&root_ctx, &mm, &username)
+    let user: UserForLogin = UserBmc::first_by_username(&root_ctx, mm, &username)
         .await?
         // NOTE: never log the username because sometimes users enter their password
         // by mistake
@@ -55,23 +76,59 @@ async fn api_login_handler(
     )
     .map_err(|_| Error::LoginFailPwdNotMatching { user_id })?;
 
+    // -- Select the workspace
+    let workspace_id = resolve_workspace(mm, user_id, workspace_id)
+        .await
+        .map_err(Error::CtxExt)?;
+
     // -- Set the web token
-    web::set_token_cookie(&cookies, &user.username, &user.token_salt.to_string())?;
+    web::set_token_cookie(cookies, &user.username, &user.token_salt.to_string())?;
+    match workspace_id {
+        Some(workspace_id) => web::set_workspace_cookie(cookies, workspace_id),
+        None => remove_workspace_cookie(cookies),
+    }
 
     // Create the success body.
     let body = Json(json!({
         "result": {
-            "success": true
+            "success": true,
+            "workspace_id": workspace_id,
         }
     }));
 
-    Ok(body)
+    Ok((user_id, body))
+}
+
+fn login_audit_event(res: &Result<(i64, Json<Value>)>) -> AuditEventForCreate {
+    let (kind, user_id, detail) = match res {
+        Ok((user_id, _)) => (AuditKind::LoginSuccess, Some(*user_id), json!({})),
+        Err(ex) => {
+            let user_id = match ex {
+                Error::LoginFailUserHasNoPwd { user_id }
+                | Error::LoginFailPwdNotMatching { user_id } => Some(*user_id),
+                _ => None,
+            };
+            (
+                AuditKind::LoginFail,
+                user_id,
+                json!({ "reason": ex.as_ref() }),
+            )
+        }
+    };
+
+    AuditEventForCreate {
+        kind,
+        user_id,
+        detail,
+    }
 }
 
 #[derive(Debug, Deserialize)]
 struct LoginPayload {
     username: String,
     pwd: String,
+    /// Active workspace, by default the first one of the user.
+    workspace_id: Option<i64>,
 }
 
 // we want the log off to be a post request so we put a payload
@@ -81,6 +138,9 @@ struct LogoffPayload {
 }
 
 async fn api_logoff_handler(
+    State(mm): State<ModelManager>,
+    ctx: Option<Ctx>,
+    req_info: Option<Extension<RequestInfo>>,
     cookies: Cookies,
     Json(payload): Json<LogoffPayload>,
 ) -> Result<Json<Value>> {
@@ -89,6 +149,19 @@ async fn api_logoff_handler(
     let should_logoff = payload.logoff;
     if should_logoff {
         remove_token_cookie(&cookies)?;
+        remove_workspace_cookie(&cookies);
+
+        // the user is unknown when the token was not valid anymore
+        let user_id = ctx.as_ref().map(Ctx::user_id);
+        let audit_ctx = ctx.unwrap_or_else(|| {
+            req_info.map_or_else(Ctx::root_ctx, |Extension(req_info)| req_info.root_ctx())
+        });
+        let event_c = AuditEventForCreate {
+            kind: AuditKind::Logoff,
+            user_id,
+            detail: json!({}),
+        };
+        record_audit(&audit_ctx, &mm, event_c).await;
     }
     let body = Json(json!(
         { "result":
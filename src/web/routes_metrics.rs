@@ -0,0 +1,29 @@
+//! Prometheus scrape endpoint, without auth and without request log lines
+//! (restrict it at the network level).
+
+use crate::metrics;
+use crate::model::ModelManager;
+use axum::extract::State;
+use axum::http::header;
+use axum::response::IntoResponse;
+use axum::routing::get;
+use axum::Router;
+use std::time::Duration;
+
+/// Max wait for a db connection, when measuring the pool acquire wait.
+const POOL_ACQUIRE_TIMEOUT: Duration = Duration::from_secs(1);
+
+pub fn routes(mm: ModelManager) -> Router {
+    Router::new()
+        .route("/metrics", get(metrics_handler))
+        .with_state(mm)
+}
+
+async fn metrics_handler(State(mm): State<ModelManager>) -> impl IntoResponse {
+    let pool_stats = mm.pool_stats(POOL_ACQUIRE_TIMEOUT).await;
+
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        metrics::render(Some(&pool_stats)),
+    )
+}
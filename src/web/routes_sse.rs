@@ -0,0 +1,300 @@
+//! Server-Sent Events of the change events, for clients which cannot use the WebSocket.
+//!
+//! - Each `change` event has the change event log id as `id`, so that `EventSource`
+//!   resumes from it with the `Last-Event-ID` header when it reconnects.
+//! - When the log does not go back that far anymore, or when the stream lagged behind
+//!   the changes, a `resync` event tells the client to reload its data.
+//! - The resume is best-effort: the ids are taken in the order of the writes, but committed
+//!   in another order, so an event with a lower id than `Last-Event-ID` committed after
+//!   the disconnection is not replayed.
+//! - Keep-alive comments are sent when there is no event.
+//! - The stream ends on shutdown, the client reconnects to another instance.
+
+use crate::ctx::Ctx;
+use crate::model::change_event::{ChangeEvent, ChangeEventBmc};
+use crate::model::ModelManager;
+use crate::web::{change_event_json, Result};
+use axum::extract::State;
+use axum::http::HeaderMap;
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::routing::get;
+use axum::{Extension, Router};
+use std::collections::HashSet;
+use std::convert::Infallible;
+use std::time::Duration;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_util::sync::CancellationToken;
+use tracing::debug;
+
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(15);
+const REPLAY_PAGE_SIZE: u64 = 500;
+const STREAM_BUFFER: usize = 64;
+
+const LAST_EVENT_ID: &str = "last-event-id";
+
+type EventSender = mpsc::Sender<core::result::Result<Event, Infallible>>;
+
+pub fn routes(mm: ModelManager, shutdown: CancellationToken) -> Router {
+    Router::new()
+        .route("/events", get(sse_handler))
+        .layer(Extension(shutdown))
+        .with_state(mm)
+}
+
+async fn sse_handler(
+    State(mm): State<ModelManager>,
+    Extension(shutdown): Extension<CancellationToken>,
+    ctx: Ctx,
+    headers: HeaderMap,
+) -> Result<Sse<ReceiverStream<core::result::Result<Event, Infallible>>>> {
+    debug!("{:<12} - sse_handler", "HANDLER");
+
+    let last_event_id = headers
+        .get(LAST_EVENT_ID)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<i64>().ok());
+
+    // subscribe before the replay so that no event is missed in between
+    let changes = mm.subscribe_changes().await?;
+
+    let (tx, rx) = mpsc::channel(STREAM_BUFFER);
+    tokio::spawn(async move {
+        tokio::select! {
+            _ = stream_events(ctx, mm, last_event_id, changes, tx) => (),
+            _ = shutdown.cancelled() => (),
+        }
+    });
+
+    Ok(
+        Sse::new(ReceiverStream::new(rx))
+            .keep_alive(KeepAlive::new().interval(KEEP_ALIVE_INTERVAL)),
+    )
+}
+
+/// Sends the missed events (if `last_id`) then the live ones, until the client is gone.
+async fn stream_events(
+    ctx: Ctx,
+    mm: ModelManager,
+    last_id: Option<i64>,
+    mut changes: broadcast::Receiver<ChangeEvent>,
+    tx: EventSender,
+) {
+    // the live events are not ordered by id (the commits are not), so only the ones
+    // already sent by the replay are skipped
+    let mut replayed = HashSet::new();
+    if let Some(after_id) = last_id {
+        let Some(ids) = replay(&ctx, &mm, after_id, &tx).await else {
+            return;
+        };
+        replayed = ids;
+    }
+
+    loop {
+        let event = tokio::select! {
+            event = changes.recv() => event,
+            _ = tx.closed() => break,
+        };
+
+        match event {
+            Ok(event) => {
+                if replayed.remove(&event.id) {
+                    continue;
+                }
+                if !send_change(&ctx, &mm, event, &tx).await {
+                    break;
+                }
+            }
+            // missed events in the channel, which can't be told apart in the log
+            Err(RecvError::Lagged(count)) => {
+                debug!("{:<12} - sse lagged - missed: {count}", "SSE");
+                if !send_resync(&tx).await {
+                    break;
+                }
+            }
+            Err(RecvError::Closed) => break,
+        }
+    }
+
+    debug!("{:<12} - sse closed - user_id: {}", "SSE", ctx.user_id());
+}
+
+/// Sends the events logged after `after_id`.
+/// Returns the ids of the events sent, or None when the client is gone.
+async fn replay(
+    ctx: &Ctx,
+    mm: &ModelManager,
+    mut after_id: i64,
+    tx: &EventSender,
+) -> Option<HashSet<i64>> {
+    let mut replayed = HashSet::new();
+    loop {
+        let events = match ChangeEventBmc::list_after(ctx, mm, after_id, REPLAY_PAGE_SIZE).await {
+            Ok(events) => events,
+            Err(ex) => {
+                // pruned (or failed), the client has to reload
+                debug!("{:<12} - sse replay fail: {ex:?}", "SSE");
+                return send_resync(tx).await.then_some(replayed);
+            }
+        };
+
+        let count = events.len();
+        for event in events {
+            after_id = event.id;
+            replayed.insert(event.id);
+            if !send_change(ctx, mm, event, tx).await {
+                return None;
+            }
+        }
+
+        if (count as u64) < REPLAY_PAGE_SIZE {
+            return Some(replayed);
+        }
+    }
+}
+
+/// Returns false when the client is gone.
+async fn send_change(ctx: &Ctx, mm: &ModelManager, event: ChangeEvent, tx: &EventSender) -> bool {
+    let id = event.id;
+    // not visible to the ctx
+    let Some(data) = change_event_json(ctx, mm, event).await else {
+        return true;
+    };
+
+    let Ok(event) = Event::default()
+        .id(id.to_string())
+        .event("change")
+        .json_data(data)
+    else {
+        return true;
+    };
+
+    tx.send(Ok(event)).await.is_ok()
+}
+
+/// Returns false when the client is gone.
+async fn send_resync(tx: &EventSender) -> bool {
+    let event = Event::default().event("resync").data("{}");
+    tx.send(Ok(event)).await.is_ok()
+}
+
+// region:    --- Tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::_dev_utils;
+    use crate::config;
+    use crate::model::change_event::ChangeOp;
+    use crate::model::task::{Task, TaskBmc};
+    use anyhow::Result;
+    use serde_json::json;
+    use serial_test::serial;
+    use sqlx::{PgPool, Postgres, Transaction};
+
+    /// Logs an update event of the task in a new transaction, notified (and committed)
+    /// with `fx_commit_event`.
+    async fn fx_begin_event(
+        db: &PgPool,
+        task: &Task,
+    ) -> Result<(Transaction<'static, Postgres>, ChangeEvent)> {
+        let mut tx = db.begin().await?;
+        let data = serde_json::to_value(task)?;
+        let id: i64 = sqlx::query_scalar(
+            "INSERT INTO change_event (entity, op, entity_id, workspace_id, data)
+             VALUES ('task', 'update', $1, $2, $3) RETURNING id",
+        )
+        .bind(task.id)
+        .bind(task.workspace_id)
+        .bind(&data)
+        .fetch_one(&mut *tx)
+        .await?;
+
+        let event = ChangeEvent {
+            id,
+            entity: "task".to_string(),
+            op: ChangeOp::Update,
+            entity_id: task.id,
+            workspace_id: Some(task.workspace_id),
+            data,
+        };
+        Ok((tx, event))
+    }
+
+    async fn fx_commit_event(
+        mut tx: Transaction<'static, Postgres>,
+        event: &ChangeEvent,
+    ) -> Result<()> {
+        sqlx::query("SELECT pg_notify('model_change', $1)")
+            .bind(json!([event]).to_string())
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// The `id` field of the sse event (which does not expose its fields).
+    fn event_id(event: &Event) -> Option<i64> {
+        let event = format!("{event:?}");
+        let id = event.split("id: ").nth(1)?;
+        let id: String = id.chars().take_while(char::is_ascii_digit).collect();
+        id.parse().ok()
+    }
+
+    #[serial]
+    #[tokio::test]
+    async fn test_stream_events_commit_out_of_order_ok() -> Result<()> {
+        // -- Setup & Fixtures
+        let mm = _dev_utils::init_test().await;
+        let ctx = _dev_utils::demo_ctx();
+        let fx_task = _dev_utils::seed_tasks(&ctx, &mm, &["test_stream_events_out_of_order"])
+            .await?
+            .remove(0);
+        // (the writers of the fixture events, outside of the model)
+        let db = PgPool::connect(&config().DB_URL).await?;
+        let fx_created_id: i64 = sqlx::query_scalar(
+            "SELECT max(id) FROM change_event WHERE entity = 'task' AND entity_id = $1",
+        )
+        .bind(fx_task.id)
+        .fetch_one(&db)
+        .await?;
+
+        // resumes before the create, so that it is replayed (and skipped live)
+        let changes = mm.subscribe_changes().await?;
+        let (tx, mut rx) = mpsc::channel(STREAM_BUFFER);
+        let stream = tokio::spawn(stream_events(
+            ctx.clone(),
+            mm.clone(),
+            Some(fx_created_id - 1),
+            changes,
+            tx,
+        ));
+
+        // -- Exec
+        // the first id is committed last
+        let (tx_1, event_1) = fx_begin_event(&db, &fx_task).await?;
+        let (tx_2, event_2) = fx_begin_event(&db, &fx_task).await?;
+        fx_commit_event(tx_2, &event_2).await?;
+        fx_commit_event(tx_1, &event_1).await?;
+
+        // -- Check
+        // (until no event for a while, also to catch the ones sent twice)
+        let mut ids = Vec::new();
+        while let Ok(Some(Ok(event))) =
+            tokio::time::timeout(Duration::from_millis(500), rx.recv()).await
+        {
+            ids.extend(event_id(&event));
+        }
+        // (without the events of the tests before, still in the log)
+        let expected = [fx_created_id, event_2.id, event_1.id];
+        ids.retain(|id| expected.contains(id));
+        assert_eq!(ids, expected);
+
+        // -- Clean
+        stream.abort();
+        TaskBmc::delete(&ctx, &mm, fx_task.id).await?;
+
+        Ok(())
+    }
+}
+// endregion: --- Tests
@@ -0,0 +1,141 @@
+//! WebSocket of the change events
+//!
+//! Client messages:
+//! - `{"subscribe": ["task"]}`
+//! - `{"unsubscribe": ["task"]}`
+//!
+//! Server messages:
+//! - `{"topic": "task", "op": "update", "id": 1000, "data": {..task..}}`
+//! - `{"error": {"message": "..", "detail": ..}}`
+//! - `{"resync": {"skipped": 12}}` when the socket lagged behind the changes and some
+//!   events were not sent, the client has to reload its data.
+//!
+//! The socket is closed by the server on shutdown.
+
+use crate::ctx::Ctx;
+use crate::model::change_event::ChangeEvent;
+use crate::model::ModelManager;
+use crate::web::{change_event_json, Result};
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::State;
+use axum::response::Response;
+use axum::routing::get;
+use axum::{Extension, Router};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::HashSet;
+use tokio::sync::broadcast::error::RecvError;
+use tokio_util::sync::CancellationToken;
+use tracing::debug;
+
+/// Entities which changes can be subscribed to.
+const TOPICS: &[&str] = &["task"];
+
+pub fn routes(mm: ModelManager, shutdown: CancellationToken) -> Router {
+    Router::new()
+        .route("/ws", get(ws_handler))
+        .layer(Extension(shutdown))
+        .with_state(mm)
+}
+
+async fn ws_handler(
+    State(mm): State<ModelManager>,
+    Extension(shutdown): Extension<CancellationToken>,
+    ctx: Ctx,
+    ws: WebSocketUpgrade,
+) -> Result<Response> {
+    debug!("{:<12} - ws_handler", "HANDLER");
+
+    // subscribe before the upgrade so that a listener failure is a regular error response
+    let changes = mm.subscribe_changes().await?;
+
+    Ok(ws.on_upgrade(move |socket| handle_socket(socket, ctx, mm, changes, shutdown)))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum WsRequest {
+    Subscribe(Vec<String>),
+    Unsubscribe(Vec<String>),
+}
+
+async fn handle_socket(
+    mut socket: WebSocket,
+    ctx: Ctx,
+    mm: ModelManager,
+    mut changes: tokio::sync::broadcast::Receiver<ChangeEvent>,
+    shutdown: CancellationToken,
+) {
+    let mut topics: HashSet<String> = HashSet::new();
+
+    loop {
+        let out = tokio::select! {
+            msg = socket.recv() => match msg {
+                Some(Ok(Message::Text(text))) => handle_request(&text, &mut topics),
+                // ping/pong are answered by axum
+                Some(Ok(Message::Binary(_) | Message::Ping(_) | Message::Pong(_))) => None,
+                // closed by the client
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+            },
+            event = changes.recv() => match event {
+                Ok(event) if topics.contains(&event.entity) => {
+                    change_event_json(&ctx, &mm, event).await
+                }
+                Ok(_) => None,
+                Err(RecvError::Lagged(skipped)) => {
+                    debug!("{:<12} - ws lagged, skipped {skipped} events", "WS");
+                    (!topics.is_empty()).then(|| json!({ "resync": { "skipped": skipped } }))
+                }
+                Err(RecvError::Closed) => break,
+            },
+            _ = shutdown.cancelled() => {
+                let _ = socket.send(Message::Close(None)).await;
+                break;
+            }
+        };
+
+        if let Some(out) = out {
+            if socket.send(Message::Text(out.to_string())).await.is_err() {
+                break;
+            }
+        }
+    }
+
+    debug!("{:<12} - ws closed - user_id: {}", "WS", ctx.user_id());
+}
+
+/// Updates the subscribed topics. Returns the error message to send back, if any.
+fn handle_request(text: &str, topics: &mut HashSet<String>) -> Option<Value> {
+    let Ok(request) = serde_json::from_str::<WsRequest>(text) else {
+        return Some(ws_error("WS_INVALID_REQUEST", Value::Null));
+    };
+
+    match request {
+        WsRequest::Subscribe(names) => {
+            let unknown: Vec<&String> = names
+                .iter()
+                .filter(|n| !TOPICS.contains(&n.as_str()))
+                .collect();
+            if !unknown.is_empty() {
+                return Some(ws_error("WS_UNKNOWN_TOPICS", json!(unknown)));
+            }
+            topics.extend(names);
+        }
+        WsRequest::Unsubscribe(names) => {
+            for name in names {
+                topics.remove(&name);
+            }
+        }
+    }
+
+    None
+}
+
+fn ws_error(message: &str, detail: Value) -> Value {
+    json!({
+        "error": {
+            "message": message,
+            "detail": detail,
+        }
+    })
+}
@@ -0,0 +1,39 @@
+use crate::ctx::Ctx;
+use crate::model::attachment::{Attachment, AttachmentBmc, AttachmentFilter, AttachmentUsage};
+use crate::model::ModelManager;
+use crate::web::{
+    rpc::params::{ParamsIded, ParamsList},
+    Result,
+};
+
+// Notes: The upload and download are not rpc, see `routes_attachment`.
+
+pub async fn list_attachments(
+    ctx: Ctx,
+    mm: ModelManager,
+    params: ParamsList<AttachmentFilter>,
+) -> Result<Vec<Attachment>> {
+    let attachments = AttachmentBmc::list(&ctx, &mm, params.filters, params.list_options).await?;
+
+    Ok(attachments)
+}
+
+pub async fn delete_attachment(
+    ctx: Ctx,
+    mm: ModelManager,
+    params: ParamsIded,
+) -> Result<Attachment> {
+    let ParamsIded { id } = params;
+
+    let attachment = AttachmentBmc::get(&ctx, &mm, id).await?;
+    AttachmentBmc::delete(&ctx, &mm, id).await?;
+
+    Ok(attachment)
+}
+
+/// Size of the attachments of the user, and its quota.
+pub async fn get_attachment_usage(ctx: Ctx, mm: ModelManager) -> Result<AttachmentUsage> {
+    let usage = AttachmentBmc::usage(&ctx, &mm).await?;
+
+    Ok(usage)
+}
@@ -0,0 +1,16 @@
+use crate::ctx::Ctx;
+use crate::model::audit_event::{AuditEvent, AuditEventBmc, AuditEventFilter};
+use crate::model::ModelManager;
+use crate::web::{rpc::params::ParamsList, Result};
+
+/// Admins only. Paginated with the `list_options`, e.g., with the
+/// `{"kind": "login_fail", "ctime": {"$gte": "2024-03-01T00:00:00Z"}}` filter.
+pub async fn list_audit_events(
+    ctx: Ctx,
+    mm: ModelManager,
+    params: ParamsList<AuditEventFilter>,
+) -> Result<Vec<AuditEvent>> {
+    let events = AuditEventBmc::list(&ctx, &mm, params.filters, params.list_options).await?;
+
+    Ok(events)
+}
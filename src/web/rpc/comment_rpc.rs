@@ -0,0 +1,64 @@
+use crate::ctx::Ctx;
+use crate::model::comment::{
+    Comment, CommentBmc, CommentFilter, CommentForCreate, CommentForUpdate,
+};
+use crate::model::ModelManager;
+use crate::web::{
+    rpc::params::{ParamsForCreate, ParamsIded, ParamsList},
+    Result,
+};
+use serde::Deserialize;
+
+/// Params of `edit_comment` (comments have no version).
+#[derive(Deserialize)]
+pub struct ParamsEditComment {
+    pub id: i64,
+    pub data: CommentForUpdate,
+}
+
+/// Authored by the user of the ctx.
+pub async fn add_comment(
+    ctx: Ctx,
+    mm: ModelManager,
+    params: ParamsForCreate<CommentForCreate>,
+) -> Result<Comment> {
+    let ParamsForCreate { data } = params;
+
+    let comment = CommentBmc::create(&ctx, &mm, data).await?;
+
+    Ok(comment)
+}
+
+/// Paginated with the `list_options` (`limit`, `offset`), e.g., with the
+/// `{"task_id": 1000}` filter.
+pub async fn list_comments(
+    ctx: Ctx,
+    mm: ModelManager,
+    params: ParamsList<CommentFilter>,
+) -> Result<Vec<Comment>> {
+    let comments = CommentBmc::list(&ctx, &mm, params.filters, params.list_options).await?;
+
+    Ok(comments)
+}
+
+/// Only for the author of the comment.
+pub async fn edit_comment(
+    ctx: Ctx,
+    mm: ModelManager,
+    params: ParamsEditComment,
+) -> Result<Comment> {
+    let ParamsEditComment { id, data } = params;
+
+    let comment = CommentBmc::update(&ctx, &mm, id, data).await?;
+
+    Ok(comment)
+}
+
+pub async fn delete_comment(ctx: Ctx, mm: ModelManager, params: ParamsIded) -> Result<Comment> {
+    let ParamsIded { id } = params;
+
+    let comment = CommentBmc::get(&ctx, &mm, id).await?;
+    CommentBmc::delete(&ctx, &mm, id).await?;
+
+    Ok(comment)
+}
@@ -0,0 +1,75 @@
+use crate::ctx::Ctx;
+use crate::model::label::{Label, LabelBmc, LabelFilter, LabelForCreate};
+use crate::model::task::{Task, TaskBmc};
+use crate::model::ModelManager;
+use crate::web::{
+    rpc::params::{ParamsForCreate, ParamsIded, ParamsList},
+    Result,
+};
+use serde::Deserialize;
+
+/// Params of the apis that attach/detach a label to/from a task
+#[derive(Deserialize)]
+pub struct ParamsTaskLabel {
+    pub task_id: i64,
+    pub label_id: i64,
+}
+
+pub async fn create_label(
+    ctx: Ctx,
+    mm: ModelManager,
+    params: ParamsForCreate<LabelForCreate>,
+) -> Result<Label> {
+    let ParamsForCreate { data } = params;
+
+    let label = LabelBmc::create(&ctx, &mm, data).await?;
+
+    Ok(label)
+}
+
+pub async fn list_labels(
+    ctx: Ctx,
+    mm: ModelManager,
+    params: ParamsList<LabelFilter>,
+) -> Result<Vec<Label>> {
+    let labels = LabelBmc::list(&ctx, &mm, params.filters, params.list_options).await?;
+
+    Ok(labels)
+}
+
+pub async fn delete_label(ctx: Ctx, mm: ModelManager, params: ParamsIded) -> Result<Label> {
+    let ParamsIded { id } = params;
+
+    let label = LabelBmc::get(&ctx, &mm, id).await?;
+    LabelBmc::delete(&ctx, &mm, id).await?;
+
+    Ok(label)
+}
+
+/// Returns the task, with its labels.
+pub async fn attach_task_label(
+    ctx: Ctx,
+    mm: ModelManager,
+    params: ParamsTaskLabel,
+) -> Result<Task> {
+    let ParamsTaskLabel { task_id, label_id } = params;
+
+    LabelBmc::attach(&ctx, &mm, task_id, label_id).await?;
+    let task = TaskBmc::get(&ctx, &mm, task_id).await?;
+
+    Ok(task)
+}
+
+/// Returns the task, with its labels.
+pub async fn detach_task_label(
+    ctx: Ctx,
+    mm: ModelManager,
+    params: ParamsTaskLabel,
+) -> Result<Task> {
+    let ParamsTaskLabel { task_id, label_id } = params;
+
+    LabelBmc::detach(&ctx, &mm, task_id, label_id).await?;
+    let task = TaskBmc::get(&ctx, &mm, task_id).await?;
+
+    Ok(task)
+}
@@ -1,11 +1,27 @@
+mod attachment_rpc;
+mod audit_rpc;
+mod comment_rpc;
+mod label_rpc;
 mod params;
 mod task_rpc;
+mod workspace_rpc;
 
 use std::sync::Arc;
 
 use crate::ctx::Ctx;
 use crate::model::ModelManager;
-use crate::web::rpc::task_rpc::{create_task, delete_task, list_tasks, update_task};
+use crate::web::rpc::attachment_rpc::{delete_attachment, get_attachment_usage, list_attachments};
+use crate::web::rpc::audit_rpc::list_audit_events;
+use crate::web::rpc::comment_rpc::{add_comment, delete_comment, edit_comment, list_comments};
+use crate::web::rpc::label_rpc::{
+    attach_task_label, create_label, delete_label, detach_task_label, list_labels,
+};
+use crate::web::rpc::task_rpc::{
+    create_task, create_tasks, delete_task, delete_tasks, get_task_graph, get_task_history,
+    link_task_blocker, list_tasks, search_tasks, set_task_parent, unlink_task_blocker, update_task,
+    update_tasks, upsert_task,
+};
+use crate::web::rpc::workspace_rpc::list_workspaces;
 use crate::web::{Error, Result};
 use axum::extract::State;
 use axum::response::{IntoResponse, Response};
@@ -13,7 +29,7 @@ use axum::routing::post;
 use axum::{Json, Router};
 use serde::Deserialize;
 use serde_json::{from_value, json, to_value, Value};
-use tracing::debug;
+use tracing::{debug, field, info_span, Instrument};
 
 /// JSON RPC Request body
 #[derive(Deserialize)]
@@ -39,7 +55,23 @@ async fn rpc_handler(
         method: rpc_req.method.clone(),
     };
 
-    let mut res = _rpc_handler(ctx, mm, rpc_req).await.into_response();
+    let span = info_span!(
+        "rpc",
+        otel.name = %rpc_info.method,
+        otel.status_code = field::Empty,
+        rpc.system = "jsonrpc",
+        rpc.method = %rpc_info.method,
+        user_id = ctx.user_id(),
+        request_id = ctx.request_id(),
+    );
+    let res = _rpc_handler(ctx, mm, rpc_req)
+        .instrument(span.clone())
+        .await;
+    if res.is_err() {
+        span.record("otel.status_code", "error");
+    }
+
+    let mut res = res.into_response();
     res.extensions_mut().insert(Arc::new(rpc_info));
     res
 }
@@ -102,8 +134,42 @@ async fn _rpc_handler(ctx: Ctx, mm: ModelManager, rpc_req: RpcRequest) -> Result
         // }
         "create_task" => exec_rpc_fn!(create_task, ctx, mm, rpc_params),
         "list_tasks" => exec_rpc_fn!(list_tasks, ctx, mm, rpc_params),
+        "search_tasks" => exec_rpc_fn!(search_tasks, ctx, mm, rpc_params),
         "update_task" => exec_rpc_fn!(update_task, ctx, mm, rpc_params),
+        "upsert_task" => exec_rpc_fn!(upsert_task, ctx, mm, rpc_params),
         "delete_task" => exec_rpc_fn!(delete_task, ctx, mm, rpc_params),
+        "get_task_history" => exec_rpc_fn!(get_task_history, ctx, mm, rpc_params),
+        "set_task_parent" => exec_rpc_fn!(set_task_parent, ctx, mm, rpc_params),
+        "link_task_blocker" => exec_rpc_fn!(link_task_blocker, ctx, mm, rpc_params),
+        "unlink_task_blocker" => exec_rpc_fn!(unlink_task_blocker, ctx, mm, rpc_params),
+        "get_task_graph" => exec_rpc_fn!(get_task_graph, ctx, mm, rpc_params),
+        "attach_task_label" => exec_rpc_fn!(attach_task_label, ctx, mm, rpc_params),
+        "detach_task_label" => exec_rpc_fn!(detach_task_label, ctx, mm, rpc_params),
+        "create_tasks" => exec_rpc_fn!(create_tasks, ctx, mm, rpc_params),
+        "update_tasks" => exec_rpc_fn!(update_tasks, ctx, mm, rpc_params),
+        "delete_tasks" => exec_rpc_fn!(delete_tasks, ctx, mm, rpc_params),
+
+        // -- Label RPC methods.
+        "create_label" => exec_rpc_fn!(create_label, ctx, mm, rpc_params),
+        "list_labels" => exec_rpc_fn!(list_labels, ctx, mm, rpc_params),
+        "delete_label" => exec_rpc_fn!(delete_label, ctx, mm, rpc_params),
+
+        // -- Comment RPC methods.
+        "add_comment" => exec_rpc_fn!(add_comment, ctx, mm, rpc_params),
+        "list_comments" => exec_rpc_fn!(list_comments, ctx, mm, rpc_params),
+        "edit_comment" => exec_rpc_fn!(edit_comment, ctx, mm, rpc_params),
+        "delete_comment" => exec_rpc_fn!(delete_comment, ctx, mm, rpc_params),
+
+        // -- Attachment RPC methods.
+        "list_attachments" => exec_rpc_fn!(list_attachments, ctx, mm, rpc_params),
+        "delete_attachment" => exec_rpc_fn!(delete_attachment, ctx, mm, rpc_params),
+        "get_attachment_usage" => exec_rpc_fn!(get_attachment_usage, ctx, mm),
+
+        // -- Workspace RPC methods.
+        "list_workspaces" => exec_rpc_fn!(list_workspaces, ctx, mm),
+
+        // -- Audit RPC methods (admins only).
+        "list_audit_events" => exec_rpc_fn!(list_audit_events, ctx, mm, rpc_params),
 
         // -- Fallback as Err.
         _ => return Err(Error::RpcMethodUnknown(rpc_method)),
@@ -9,18 +9,62 @@ pub struct ParamsForCreate<D> {
 }
 
 /// All apis that want to update something
+/// `version`, when given, is the version the client expects the entity to be at.
 #[derive(Deserialize)]
 pub struct ParamsForUpdate<D> {
     pub id: i64,
+    pub version: Option<i64>,
     pub data: D,
 }
 
+/// All apis that create several entities at once
+#[derive(Deserialize)]
+pub struct ParamsForCreateMany<D> {
+    pub data: Vec<D>,
+}
+
+/// All apis that update every entity matching the filters
+#[serde_as]
+#[derive(Deserialize)]
+pub struct ParamsForUpdateMany<F, D>
+where
+    F: DeserializeOwned,
+{
+    #[serde_as(deserialize_as = "OneOrMany<_>")]
+    pub filters: Vec<F>,
+    pub data: D,
+}
+
+/// All apis that delete every entity matching the filters
+#[serde_as]
+#[derive(Deserialize)]
+pub struct ParamsForDeleteMany<F>
+where
+    F: DeserializeOwned,
+{
+    #[serde_as(deserialize_as = "OneOrMany<_>")]
+    pub filters: Vec<F>,
+}
+
 /// All apis that only need the id
 #[derive(Deserialize)]
 pub struct ParamsIded {
     pub id: i64,
 }
 
+/// All apis that full text search entities, see `ParamsList` for the rest
+#[serde_as]
+#[derive(Deserialize)]
+pub struct ParamsSearch<F>
+where
+    F: DeserializeOwned,
+{
+    pub query: String,
+    #[serde_as(deserialize_as = "Option<OneOrMany<_>>")]
+    pub filters: Option<Vec<F>>,
+    pub list_options: Option<ListOptions>,
+}
+
 #[serde_as]
 #[derive(Deserialize)]
 pub struct ParamsList<F>
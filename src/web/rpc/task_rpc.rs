@@ -1,10 +1,19 @@
 use crate::ctx::Ctx;
-use crate::model::task::{Task, TaskBmc, TaskFilter, TaskForCreate, TaskForUpdate};
+use crate::model::task::{
+    Task, TaskBmc, TaskFilter, TaskForCreate, TaskForUpdate, TaskForUpsert, TaskGraph,
+    TaskSearchHit,
+};
+use crate::model::task_dependency::TaskDependencyBmc;
+use crate::model::task_history::{TaskHistory, TaskHistoryBmc};
 use crate::model::ModelManager;
 use crate::web::{
-    rpc::params::{ParamsForCreate, ParamsForUpdate, ParamsIded, ParamsList},
+    rpc::params::{
+        ParamsForCreate, ParamsForCreateMany, ParamsForDeleteMany, ParamsForUpdate,
+        ParamsForUpdateMany, ParamsIded, ParamsList, ParamsSearch,
+    },
     Result,
 };
+use serde::{Deserialize, Serialize};
 
 // Notes: Here we consume the ctx and the model manager because we don't need them
 // afterwards.
@@ -16,8 +25,7 @@ pub async fn create_task(
 ) -> Result<Task> {
     let ParamsForCreate { data } = params;
 
-    let id = TaskBmc::create(&ctx, &mm, data).await?;
-    let task = TaskBmc::get(&ctx, &mm, id).await?;
+    let task = TaskBmc::create_returning(&ctx, &mm, data).await?;
 
     Ok(task)
 }
@@ -32,20 +40,58 @@ pub async fn list_tasks(
     Ok(tasks)
 }
 
+/// Full text search, most relevant first, see `TaskBmc::search` for the query syntax.
+pub async fn search_tasks(
+    ctx: Ctx,
+    mm: ModelManager,
+    params: ParamsSearch<TaskFilter>,
+) -> Result<Vec<TaskSearchHit>> {
+    let ParamsSearch {
+        query,
+        filters,
+        list_options,
+    } = params;
+
+    let hits = TaskBmc::search(&ctx, &mm, &query, filters, list_options).await?;
+
+    Ok(hits)
+}
+
 pub async fn update_task(
     ctx: Ctx,
     mm: ModelManager,
     params: ParamsForUpdate<TaskForUpdate>,
 ) -> Result<Task> {
-    let ParamsForUpdate { id, data } = params;
+    let ParamsForUpdate { id, version, data } = params;
 
-    TaskBmc::update(&ctx, &mm, id, data).await?;
-
-    let task = TaskBmc::get(&ctx, &mm, id).await?;
+    let task = TaskBmc::update_returning(&ctx, &mm, id, version, data).await?;
 
     Ok(task)
 }
 
+#[derive(Serialize)]
+pub struct TaskUpserted {
+    /// `false` when an existing task with the same `external_id` was updated.
+    pub inserted: bool,
+    pub data: Task,
+}
+
+/// Creates the task, or updates the one with the same `external_id`.
+pub async fn upsert_task(
+    ctx: Ctx,
+    mm: ModelManager,
+    params: ParamsForCreate<TaskForUpsert>,
+) -> Result<TaskUpserted> {
+    let ParamsForCreate { data } = params;
+
+    let (task, inserted) = TaskBmc::upsert(&ctx, &mm, data).await?;
+
+    Ok(TaskUpserted {
+        inserted,
+        data: task,
+    })
+}
+
 pub async fn delete_task(ctx: Ctx, mm: ModelManager, params: ParamsIded) -> Result<Task> {
     let ParamsIded { id } = params;
 
@@ -54,3 +100,130 @@ pub async fn delete_task(ctx: Ctx, mm: ModelManager, params: ParamsIded) -> Resu
 
     Ok(task)
 }
+
+// -- Bulk
+// Each of them runs in a single transaction: either all the tasks are changed, or none.
+
+pub async fn create_tasks(
+    ctx: Ctx,
+    mm: ModelManager,
+    params: ParamsForCreateMany<TaskForCreate>,
+) -> Result<Vec<Task>> {
+    let ParamsForCreateMany { data } = params;
+
+    let tasks = TaskBmc::create_many_returning(&ctx, &mm, data).await?;
+
+    Ok(tasks)
+}
+
+pub async fn update_tasks(
+    ctx: Ctx,
+    mm: ModelManager,
+    params: ParamsForUpdateMany<TaskFilter, TaskForUpdate>,
+) -> Result<Vec<Task>> {
+    let ParamsForUpdateMany { filters, data } = params;
+
+    let tasks = TaskBmc::update_many_returning(&ctx, &mm, filters, data).await?;
+
+    Ok(tasks)
+}
+
+#[derive(Serialize)]
+pub struct TasksDeleted {
+    pub count: usize,
+    pub ids: Vec<i64>,
+}
+
+pub async fn delete_tasks(
+    ctx: Ctx,
+    mm: ModelManager,
+    params: ParamsForDeleteMany<TaskFilter>,
+) -> Result<TasksDeleted> {
+    let ParamsForDeleteMany { filters } = params;
+
+    let ids = TaskBmc::delete_many(&ctx, &mm, filters).await?;
+
+    Ok(TasksDeleted {
+        count: ids.len(),
+        ids,
+    })
+}
+
+// -- Graph
+
+#[derive(Deserialize)]
+pub struct ParamsSetParent {
+    pub id: i64,
+    /// `null` to move the task to the top level
+    pub parent_id: Option<i64>,
+}
+
+#[derive(Deserialize)]
+pub struct ParamsTaskBlocker {
+    pub task_id: i64,
+    pub blocker_id: i64,
+}
+
+pub async fn set_task_parent(ctx: Ctx, mm: ModelManager, params: ParamsSetParent) -> Result<Task> {
+    let ParamsSetParent { id, parent_id } = params;
+
+    let task = TaskBmc::set_parent(&ctx, &mm, id, parent_id).await?;
+
+    Ok(task)
+}
+
+/// Returns the task with its (transitive) blockers.
+pub async fn link_task_blocker(
+    ctx: Ctx,
+    mm: ModelManager,
+    params: ParamsTaskBlocker,
+) -> Result<TaskGraph> {
+    let ParamsTaskBlocker {
+        task_id,
+        blocker_id,
+    } = params;
+
+    TaskDependencyBmc::link(&ctx, &mm, task_id, blocker_id).await?;
+    let graph = TaskBmc::get_graph(&ctx, &mm, task_id).await?;
+
+    Ok(graph)
+}
+
+/// Returns the task with its (transitive) blockers.
+pub async fn unlink_task_blocker(
+    ctx: Ctx,
+    mm: ModelManager,
+    params: ParamsTaskBlocker,
+) -> Result<TaskGraph> {
+    let ParamsTaskBlocker {
+        task_id,
+        blocker_id,
+    } = params;
+
+    TaskDependencyBmc::unlink(&ctx, &mm, task_id, blocker_id).await?;
+    let graph = TaskBmc::get_graph(&ctx, &mm, task_id).await?;
+
+    Ok(graph)
+}
+
+/// The task with all its subtasks and all its blockers.
+pub async fn get_task_graph(ctx: Ctx, mm: ModelManager, params: ParamsIded) -> Result<TaskGraph> {
+    let ParamsIded { id } = params;
+
+    let graph = TaskBmc::get_graph(&ctx, &mm, id).await?;
+
+    Ok(graph)
+}
+
+/// Revisions of the task, oldest first. Still available once the task is deleted.
+pub async fn get_task_history(
+    ctx: Ctx,
+    mm: ModelManager,
+    params: ParamsIded,
+) -> Result<Vec<TaskHistory>> {
+    let ParamsIded { id } = params;
+
+    let revisions = TaskHistoryBmc::list_for(&ctx, &mm, "task", id).await?;
+
+    Ok(revisions)
+}
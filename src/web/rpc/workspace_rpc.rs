@@ -0,0 +1,13 @@
+use crate::ctx::Ctx;
+use crate::model::workspace::{Workspace, WorkspaceBmc};
+use crate::model::ModelManager;
+use crate::web::Result;
+
+// Notes: The active workspace is selected at login, or with the `X-Workspace-Id` header.
+
+/// Workspaces the user is a member of.
+pub async fn list_workspaces(ctx: Ctx, mm: ModelManager) -> Result<Vec<Workspace>> {
+    let workspaces = WorkspaceBmc::list_for_user(&ctx, &mm, ctx.user_id()).await?;
+
+    Ok(workspaces)
+}